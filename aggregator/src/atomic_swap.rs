@@ -0,0 +1,549 @@
+//! A built-in plugin matching the pooled otxs which swap assets with each
+//! other, e.g. "give 100 UDT, want 90 CKB" with "give 100 CKB, want 100 UDT".
+//!
+//! The otxs are indexed by the assets they give away. On every interval the
+//! matcher looks for pairs, or rings up to `max_ring_size`, in which every
+//! asset asked for is given away by another otx, merges them and sends the
+//! transaction. The udts given away must be asked for exactly, since nobody
+//! would claim the rest. The capacity left over pays the fee, up to `max_fee`,
+//! and the rest goes to the change output of `change_lock`.
+//!
+//! An otx with a divisible intent may give away only part of the order, the
+//! residual otx of the rest is kept in the pool for its owner to sign.
+
+use crate::fee::{resolve_input_capacity, FeeEngine};
+use crate::merge_txs;
+use crate::partial_fill::residual_of;
+
+use otx_format::jsonrpc_types::tx_view::{otx_to_tx_view, tx_view_to_otx};
+use otx_format::jsonrpc_types::OpenTransaction;
+use otx_plugin_protocol::framing::Framing;
+use otx_plugin_protocol::{EventType, Permission, PluginInfo, PROTOCOL_VERSION};
+use otx_pool::blocking::run_blocking;
+use otx_pool::plugin::{Plugin, PluginContext};
+use otx_pool::pool::{validator::AssetBalance, Id, OtxEntry};
+use utils::const_definition::CKB_URI;
+
+use async_trait::async_trait;
+use ckb_jsonrpc_types::{self as json_types, OutPoint, Script};
+use ckb_types::{
+    core::{Capacity, TransactionView},
+    packed::{self, CellOutput, Transaction},
+    prelude::*,
+    H256,
+};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+pub const DEFAULT_MAX_RING_SIZE: usize = 3;
+/// In shannons, the capacity a match must leave over for the fee.
+pub const DEFAULT_MIN_FEE: u64 = 10_000;
+/// In shannons, the most capacity a match may leave to the miner.
+pub const DEFAULT_MAX_FEE: u64 = 1_000_000;
+
+/// `None` is the capacity, and a type script is a udt.
+type Asset = Option<Script>;
+
+#[derive(Clone, Debug)]
+pub struct AtomicSwapConfig {
+    pub ckb_uri: String,
+    /// The most otxs merged into one transaction, 2 matches pairs only.
+    pub max_ring_size: usize,
+    pub min_fee: u64,
+    pub max_fee: u64,
+    /// The lock of the change output taking the capacity left over above the
+    /// fee. Without it, only the matches leaving at most `max_fee` settle.
+    pub change_lock: Option<Script>,
+}
+
+impl Default for AtomicSwapConfig {
+    fn default() -> Self {
+        AtomicSwapConfig {
+            ckb_uri: CKB_URI.to_string(),
+            max_ring_size: DEFAULT_MAX_RING_SIZE,
+            min_fee: DEFAULT_MIN_FEE,
+            max_fee: DEFAULT_MAX_FEE,
+            change_lock: None,
+        }
+    }
+}
+
+impl AtomicSwapConfig {
+    fn fee_limits(&self) -> FeeLimits {
+        let min_change = self.change_lock.as_ref().map(|change_lock| {
+            CellOutput::new_builder()
+                .lock(packed::Script::from(change_lock.clone()))
+                .build()
+                .occupied_capacity(Capacity::zero())
+                .map(|capacity| capacity.as_u64())
+                .unwrap_or(u64::MAX)
+        });
+        FeeLimits {
+            min_fee: self.min_fee,
+            max_fee: self.max_fee,
+            min_change,
+        }
+    }
+}
+
+pub struct AtomicSwapMatcher {
+    config: AtomicSwapConfig,
+    fee_engine: FeeEngine,
+    book: Mutex<SwapBook>,
+}
+
+impl AtomicSwapMatcher {
+    pub fn new(config: AtomicSwapConfig) -> Self {
+        AtomicSwapMatcher {
+            config,
+            fee_engine: FeeEngine::default(),
+            book: Mutex::default(),
+        }
+    }
+
+    /// Merge the matched otxs, send the transaction, and discard the otxs from
+    /// the pool.
+    fn settle(&self, context: &PluginContext, ids: &[Id]) -> Result<H256, String> {
+        let otxs: Vec<OpenTransaction> = {
+            let book = self.book.lock().expect("acquire lock");
+            ids.iter()
+                .map(|id| {
+                    book.offers
+                        .get(id)
+                        .map(|offer| offer.otx.clone())
+                        .ok_or_else(|| format!("otx {:#x} is gone", id))
+                })
+                .collect::<Result<_, _>>()?
+        };
+        let txes = otxs
+            .into_iter()
+            .map(|otx| {
+                otx_to_tx_view(otx)
+                    .map(|tx_view| Transaction::from(tx_view.inner).into_view())
+                    .map_err(|err| err.to_string())
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let config = self.config.clone();
+        let fee_engine = self.fee_engine;
+        let tx = run_blocking(move || {
+            let tx = merge_txs(txes, &config.ckb_uri).map_err(|err| err.to_string())?;
+            pay_fee(&fee_engine, &config, tx)
+        })??;
+        let otx = tx_view_to_otx(json_types::TransactionView::from(tx.clone()))
+            .map_err(|err| err.to_string())?;
+        let tx_hash = context.send_ckb_tx(otx)?;
+
+        // the residuals of the partially filled otxs are left for their owners
+        // to sign, before the otxs are discarded
+        for (id, residual) in self.residuals(ids, &tx) {
+            if let Err(err) = context.pool.add_residual_template(&id, residual) {
+                log::warn!("keep residual of otx {:#x} failed: {}", id, err);
+            }
+        }
+        let mut book = self.book.lock().expect("acquire lock");
+        ids.iter().for_each(|id| book.remove(id));
+        drop(book);
+        for id in ids {
+            if let Err(err) = context.discard_otx(id.clone()) {
+                log::warn!("discard matched otx {:#x} failed: {}", id, err);
+            }
+        }
+        Ok(tx_hash)
+    }
+
+    /// Build the residual otxs of the matched otxs which are filled partially
+    /// by the settlement.
+    fn residuals(&self, ids: &[Id], settlement: &TransactionView) -> Vec<(Id, OpenTransaction)> {
+        let book = self.book.lock().expect("acquire lock");
+        let mut residuals = vec![];
+        for id in ids {
+            let offer = match book.offers.get(id) {
+                Some(offer) => offer,
+                None => continue,
+            };
+            let (owner_lock, intent) = match (&offer.owner_lock, offer.otx.get_intent()) {
+                (Some(owner_lock), Ok(Some(intent))) if intent.allow_partial_fill => {
+                    (owner_lock, intent)
+                }
+                _ => continue,
+            };
+            let offered = intent.offered_asset.type_script().cloned();
+            let given = offer.balance.get(&offered).copied().unwrap_or_default();
+            let owner_lock = packed::Script::from(owner_lock.clone());
+            match residual_of(
+                id,
+                &offer.otx,
+                u128::try_from(given).unwrap_or_default(),
+                &owner_lock,
+                settlement,
+            ) {
+                Ok(Some(residual)) => residuals.push((id.clone(), residual)),
+                Ok(None) => {}
+                Err(err) => log::warn!("build residual of otx {:#x} failed: {}", id, err),
+            }
+        }
+        residuals
+    }
+}
+
+#[async_trait]
+impl Plugin for AtomicSwapMatcher {
+    fn info(&self) -> PluginInfo {
+        PluginInfo {
+            name: String::from("atomic swap matcher"),
+            description: String::from("Matches the otxs swapping assets with each other"),
+            version: String::from("0.1.0"),
+            protocol_version: PROTOCOL_VERSION,
+            subscriptions: vec![
+                EventType::NewOtx,
+                EventType::DeleteOtx,
+                EventType::NewInterval,
+            ],
+            permissions: vec![Permission::SendCkbTx, Permission::DiscardOtx],
+            framing: Framing::default(),
+        }
+    }
+
+    async fn on_new_otx(&self, context: &PluginContext, id: Id, _otx: OpenTransaction) {
+        // the pool keeps the balance resolved from the input cells
+        match context.pool.get_otx_entry(&id) {
+            Some(entry) => self.book.lock().expect("acquire lock").insert(id, entry),
+            None => log::debug!("otx {:#x} is gone before being indexed", id),
+        }
+    }
+
+    async fn on_delete_otx(&self, _context: &PluginContext, id: Id) {
+        self.book.lock().expect("acquire lock").remove(&id);
+    }
+
+    async fn on_interval(&self, context: &PluginContext) {
+        let matches = self
+            .book
+            .lock()
+            .expect("acquire lock")
+            .find_matches(self.config.max_ring_size, &self.config.fee_limits());
+        for ids in matches {
+            match self.settle(context, &ids) {
+                Ok(tx_hash) => log::info!("swapped {} otxs in tx {:#x}", ids.len(), tx_hash),
+                Err(err) => log::warn!("settle swap of {:?} failed: {}", ids, err),
+            }
+        }
+    }
+}
+
+struct Offer {
+    otx: OpenTransaction,
+    inputs: Vec<OutPoint>,
+    owner_lock: Option<Script>,
+    balance: AssetBalance,
+    inserted_at: u64,
+}
+
+impl From<OtxEntry> for Offer {
+    fn from(entry: OtxEntry) -> Self {
+        Offer {
+            otx: entry.otx,
+            inputs: entry.inputs,
+            owner_lock: entry.input_locks.first().cloned(),
+            balance: entry.balance,
+            inserted_at: entry.inserted_at,
+        }
+    }
+}
+
+#[derive(Default)]
+struct SwapBook {
+    offers: HashMap<Id, Offer>,
+    // asset -> ids of the otxs giving it away
+    givers: HashMap<Asset, HashSet<Id>>,
+}
+
+impl SwapBook {
+    fn insert(&mut self, id: Id, offer: impl Into<Offer>) {
+        let offer = offer.into();
+        for (asset, amount) in &offer.balance {
+            if *amount > 0 {
+                self.givers
+                    .entry(asset.clone())
+                    .or_default()
+                    .insert(id.clone());
+            }
+        }
+        self.offers.insert(id, offer);
+    }
+
+    fn remove(&mut self, id: &Id) {
+        if let Some(offer) = self.offers.remove(id) {
+            for asset in offer.balance.keys() {
+                if let Some(ids) = self.givers.get_mut(asset) {
+                    ids.remove(id);
+                    if ids.is_empty() {
+                        self.givers.remove(asset);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the disjoint groups of otxs which settle each other, the older
+    /// otxs are matched first.
+    fn find_matches(&self, max_ring_size: usize, fee_limits: &FeeLimits) -> Vec<Vec<Id>> {
+        let mut matched = HashSet::new();
+        let mut matches = vec![];
+        for id in self.sorted(self.offers.keys()) {
+            if matched.contains(&id) {
+                continue;
+            }
+            let mut ring = vec![id];
+            if self.extend_ring(&mut ring, &matched, max_ring_size, fee_limits) {
+                matched.extend(ring.iter().cloned());
+                matches.push(ring);
+            }
+        }
+        matches
+    }
+
+    /// Depth-first search for the otxs giving away what the ring still asks for.
+    fn extend_ring(
+        &self,
+        ring: &mut Vec<Id>,
+        matched: &HashSet<Id>,
+        max_ring_size: usize,
+        fee_limits: &FeeLimits,
+    ) -> bool {
+        let balance = self.ring_balance(ring);
+        if ring.len() > 1 && fee_limits.is_settled(&balance) {
+            return true;
+        }
+        if ring.len() >= max_ring_size {
+            return false;
+        }
+
+        let spent: HashSet<&OutPoint> = ring
+            .iter()
+            .flat_map(|id| self.offers[id].inputs.iter())
+            .collect();
+        let candidates = balance
+            .iter()
+            .filter(|(_, amount)| **amount < 0)
+            .filter_map(|(asset, _)| self.givers.get(asset))
+            .flatten()
+            .filter(|id| !ring.contains(*id) && !matched.contains(*id))
+            .filter(|id| {
+                // the otxs spending the same cell can not be merged
+                !self.offers[*id]
+                    .inputs
+                    .iter()
+                    .any(|input| spent.contains(input))
+            });
+        for candidate in self.sorted(candidates) {
+            ring.push(candidate);
+            if self.extend_ring(ring, matched, max_ring_size, fee_limits) {
+                return true;
+            }
+            ring.pop();
+        }
+        false
+    }
+
+    fn ring_balance(&self, ring: &[Id]) -> AssetBalance {
+        let mut balance = AssetBalance::new();
+        for (asset, amount) in ring.iter().flat_map(|id| self.offers[id].balance.iter()) {
+            let total = balance.entry(asset.clone()).or_default();
+            *total = total.saturating_add(*amount);
+        }
+        balance
+    }
+
+    fn sorted<'a>(&self, ids: impl Iterator<Item = &'a Id>) -> Vec<Id> {
+        let mut ids: Vec<&Id> = ids.collect::<HashSet<_>>().into_iter().collect();
+        ids.sort_by_key(|id| (self.offers[*id].inserted_at, (*id).clone()));
+        ids.into_iter().cloned().collect()
+    }
+}
+
+/// What a match may leave over, in shannons.
+#[derive(Clone, Copy, Debug)]
+struct FeeLimits {
+    min_fee: u64,
+    max_fee: u64,
+    // the capacity of the change output, `None` without the change lock
+    min_change: Option<u64>,
+}
+
+impl FeeLimits {
+    /// Every udt given away is asked for exactly, and the capacity left over
+    /// is a fee in range, or enough to pay `min_fee` and a change output.
+    fn is_settled(&self, balance: &AssetBalance) -> bool {
+        let udts_settled = balance
+            .iter()
+            .all(|(asset, amount)| asset.is_none() || *amount == 0);
+        let surplus = balance.get(&None).copied().unwrap_or_default();
+        let fee_in_range = (self.min_fee as i128..=self.max_fee as i128).contains(&surplus);
+        let with_change = self
+            .min_change
+            .map(|min_change| surplus >= self.min_fee as i128 + min_change as i128)
+            .unwrap_or(false);
+        udts_settled && (fee_in_range || with_change)
+    }
+}
+
+/// Send the capacity left over above the fee to the change lock, and check
+/// the miner gets no more than `max_fee`.
+fn pay_fee(
+    fee_engine: &FeeEngine,
+    config: &AtomicSwapConfig,
+    tx: TransactionView,
+) -> Result<TransactionView, String> {
+    let input_capacity =
+        resolve_input_capacity(&config.ckb_uri, &tx).map_err(|err| err.to_string())?;
+    let tx = match &config.change_lock {
+        Some(change_lock) => fee_engine
+            .add_change_output(
+                tx,
+                input_capacity,
+                0,
+                packed::Script::from(change_lock.clone()),
+            )
+            .map_err(|err| err.to_string())?,
+        None => tx,
+    };
+    let output_capacity = tx
+        .outputs_capacity()
+        .map_err(|err| err.to_string())?
+        .as_u64();
+    let fee = input_capacity
+        .checked_sub(output_capacity)
+        .ok_or_else(|| String::from("outputs capacity exceeds inputs capacity"))?;
+    let min_fee = fee_engine.calculate_fee(fee_engine.estimate_tx_size(&tx, 0));
+    if fee < min_fee {
+        return Err(format!("fee {} is less than {}", fee, min_fee));
+    }
+    if fee > config.max_fee.max(min_fee) {
+        return Err(format!("match leaves {} shannons to the miner", fee));
+    }
+    Ok(tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ckb_jsonrpc_types::{JsonBytes, ScriptHashType};
+
+    const CKB: u64 = 100_000_000;
+
+    fn udt(args: u8) -> Asset {
+        Some(Script {
+            code_hash: H256::default(),
+            hash_type: ScriptHashType::Type,
+            args: JsonBytes::from_vec(vec![args]),
+        })
+    }
+
+    fn offer(index: u32, balance: Vec<(Asset, i128)>) -> (Id, Offer) {
+        let mut id = [0u8; 32];
+        id[..4].copy_from_slice(&index.to_le_bytes());
+        let offer = Offer {
+            otx: OpenTransaction::default(),
+            inputs: vec![OutPoint {
+                tx_hash: H256::default(),
+                index: index.into(),
+            }],
+            owner_lock: None,
+            balance: balance.into_iter().collect(),
+            inserted_at: index as u64,
+        };
+        (H256::from(id), offer)
+    }
+
+    fn limits() -> FeeLimits {
+        AtomicSwapConfig::default().fee_limits()
+    }
+
+    #[test]
+    fn test_match_pair() {
+        let mut book = SwapBook::default();
+        let fee = DEFAULT_MIN_FEE as i128;
+        let (a, offer_a) = offer(1, vec![(udt(1), 100), (None, -90 * CKB as i128)]);
+        let (b, offer_b) = offer(2, vec![(udt(1), -100), (None, 90 * CKB as i128 + fee)]);
+        // asks for a udt nobody gives
+        let (c, offer_c) = offer(3, vec![(udt(2), -1), (None, 10 * CKB as i128)]);
+        book.insert(a.clone(), offer_a);
+        book.insert(b.clone(), offer_b);
+        book.insert(c, offer_c);
+
+        assert_eq!(book.find_matches(2, &limits()), vec![vec![a, b]]);
+    }
+
+    #[test]
+    fn test_match_ring() {
+        let mut book = SwapBook::default();
+        let fee = DEFAULT_MIN_FEE as i128;
+        let (a, offer_a) = offer(1, vec![(udt(1), 10), (udt(2), -10), (None, fee)]);
+        let (b, offer_b) = offer(2, vec![(udt(2), 10), (udt(3), -10)]);
+        let (c, offer_c) = offer(3, vec![(udt(3), 10), (udt(1), -10)]);
+        book.insert(a.clone(), offer_a);
+        book.insert(b.clone(), offer_b);
+        book.insert(c.clone(), offer_c);
+
+        assert!(book.find_matches(2, &limits()).is_empty());
+        assert_eq!(book.find_matches(3, &limits()), vec![vec![a, b, c]]);
+    }
+
+    #[test]
+    fn test_no_match_without_fee() {
+        let mut book = SwapBook::default();
+        let (a, offer_a) = offer(1, vec![(udt(1), 100), (None, -90 * CKB as i128)]);
+        let (b, offer_b) = offer(2, vec![(udt(1), -100), (None, 90 * CKB as i128)]);
+        book.insert(a.clone(), offer_a);
+        book.insert(b.clone(), offer_b);
+
+        assert!(book.find_matches(2, &limits()).is_empty());
+        let free = FeeLimits {
+            min_fee: 0,
+            ..limits()
+        };
+        assert_eq!(book.find_matches(2, &free), vec![vec![a.clone(), b]]);
+        book.remove(&a);
+        assert!(book.find_matches(2, &free).is_empty());
+    }
+
+    #[test]
+    fn test_no_match_with_surplus() {
+        let fee = DEFAULT_MIN_FEE as i128;
+        // the udt given away is more than asked for
+        let mut book = SwapBook::default();
+        let (a, offer_a) = offer(1, vec![(udt(1), 101), (None, -90 * CKB as i128)]);
+        let (b, offer_b) = offer(2, vec![(udt(1), -100), (None, 90 * CKB as i128 + fee)]);
+        book.insert(a, offer_a);
+        book.insert(b, offer_b);
+        assert!(book.find_matches(2, &limits()).is_empty());
+
+        // 1 CKB left over is too much for the fee, and too little for a change cell
+        let mut book = SwapBook::default();
+        let (a, offer_a) = offer(1, vec![(udt(1), 100), (None, -90 * CKB as i128)]);
+        let (b, offer_b) = offer(2, vec![(udt(1), -100), (None, 91 * CKB as i128)]);
+        book.insert(a.clone(), offer_a);
+        book.insert(b.clone(), offer_b);
+        assert!(book.find_matches(2, &limits()).is_empty());
+        let config = AtomicSwapConfig {
+            change_lock: Some(Script {
+                code_hash: H256::default(),
+                hash_type: ScriptHashType::Type,
+                args: JsonBytes::from_vec(vec![0; 20]),
+            }),
+            ..Default::default()
+        };
+        assert!(book.find_matches(2, &config.fee_limits()).is_empty());
+
+        // the change cell takes the rest
+        let (c, offer_c) = offer(3, vec![(udt(1), -100), (None, 190 * CKB as i128)]);
+        book.remove(&b);
+        book.insert(c.clone(), offer_c);
+        assert!(book.find_matches(2, &limits()).is_empty());
+        assert_eq!(book.find_matches(2, &config.fee_limits()), vec![vec![a, c]]);
+    }
+}
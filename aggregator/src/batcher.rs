@@ -0,0 +1,735 @@
+//! A built-in plugin aggregating the pooled otxs into ckb transactions.
+//!
+//! The batcher keeps the otxs locked by the omnilock in opentx mode. On every
+//! interval it selects the oldest otxs which fit into one transaction, merges
+//! them, adds the input and output of the aggregator, pays the fee, signs and
+//! sends the transaction. The otxs are discarded from the pool once it is
+//! committed, and queued again if the node drops it.
+//!
+//! A batch must not ask for more of any asset than it gives away. The capacity
+//! left over pays the fee, the change goes to the aggregator. The udt left over
+//! goes to the aggregator if its cell holds the same udt, a batch leaving over
+//! any other udt is refused.
+//!
+//! Every batch spends the cell of the aggregator created by the previous one,
+//! so the next batch waits until the previous transaction is committed.
+
+use crate::fee::{FeeConfig, FeeEngine};
+use crate::{merge_txs, AddInputArgs, AddOutputArgs, Committer, OtxBuilder, OtxService, Signer};
+
+use otx_format::jsonrpc_types::tx_view::otx_to_tx_view;
+use otx_format::jsonrpc_types::OpenTransaction;
+use otx_plugin_protocol::framing::Framing;
+use otx_plugin_protocol::{EventType, Permission, PluginInfo, PROTOCOL_VERSION};
+use otx_pool::blocking::run_blocking;
+use otx_pool::plugin::{Plugin, PluginContext};
+use otx_pool::pool::{unix_timestamp, validator::AssetBalance, Id, OtxEntry};
+use utils::const_definition::{CKB_URI, OMNI_OPENTX_TX_HASH, OMNI_OPENTX_TX_IDX};
+use utils::lock::omni::{build_cell_dep, TxInfo};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use ckb_jsonrpc_types::{self as json_types, OutPoint, Script, Status};
+use ckb_sdk::{rpc::CkbRpcClient, unlock::OmniLockConfig, HumanCapacity};
+use ckb_types::{packed::Transaction, prelude::*, H160, H256};
+use serde::{Deserialize, Serialize};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 10;
+/// The default `max_tx_verify_cycles` of the ckb node.
+pub const DEFAULT_MAX_TX_CYCLES: u64 = 70_000_000;
+/// The default `max_tx_size` of the ckb tx pool.
+pub const DEFAULT_MAX_TX_BYTES: usize = 512_000;
+/// A rough cost of verifying one input locked by the omnilock.
+pub const DEFAULT_CYCLES_PER_INPUT: u64 = 5_000_000;
+/// The bytes kept for the cell deps, input, outputs and witness added by the
+/// aggregator.
+pub const RESERVED_TX_BYTES: usize = 2048;
+
+#[derive(Clone, Debug)]
+pub struct BatcherConfig {
+    pub ckb_uri: String,
+    pub min_batch_size: usize,
+    pub max_batch_size: usize,
+    pub max_tx_bytes: usize,
+    pub max_cycles: u64,
+    /// Used to estimate the cycles of a batch, the scripts are not run
+    /// before the transaction is sent.
+    pub cycles_per_input: u64,
+    pub fee: FeeConfig,
+}
+
+impl Default for BatcherConfig {
+    fn default() -> Self {
+        BatcherConfig {
+            ckb_uri: CKB_URI.to_string(),
+            min_batch_size: 1,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            max_tx_bytes: DEFAULT_MAX_TX_BYTES,
+            max_cycles: DEFAULT_MAX_TX_CYCLES,
+            cycles_per_input: DEFAULT_CYCLES_PER_INPUT,
+            fee: FeeConfig::default(),
+        }
+    }
+}
+
+/// Counters of the committed batches. The latency of an otx is the time from
+/// its insertion into the pool to the commit of its batch.
+#[derive(Debug, Default)]
+pub struct BatcherMetrics {
+    batches_committed: AtomicU64,
+    batches_failed: AtomicU64,
+    otxs_committed: AtomicU64,
+    last_batch_size: AtomicU64,
+    max_batch_size: AtomicU64,
+    total_latency_secs: AtomicU64,
+    max_latency_secs: AtomicU64,
+    last_build_millis: AtomicU64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BatcherMetricsSnapshot {
+    pub batches_committed: u64,
+    pub batches_failed: u64,
+    pub otxs_committed: u64,
+    pub last_batch_size: u64,
+    pub max_batch_size: u64,
+    pub average_latency_secs: u64,
+    pub max_latency_secs: u64,
+    /// The time spent merging, signing and sending the last batch.
+    pub last_build_millis: u64,
+}
+
+impl BatcherMetrics {
+    fn record_batch(&self, size: usize, latencies: &[u64], build_millis: u64) {
+        let size = size as u64;
+        self.batches_committed.fetch_add(1, Ordering::Relaxed);
+        self.otxs_committed.fetch_add(size, Ordering::Relaxed);
+        self.last_batch_size.store(size, Ordering::Relaxed);
+        self.max_batch_size.fetch_max(size, Ordering::Relaxed);
+        self.total_latency_secs
+            .fetch_add(latencies.iter().sum(), Ordering::Relaxed);
+        if let Some(max) = latencies.iter().max() {
+            self.max_latency_secs.fetch_max(*max, Ordering::Relaxed);
+        }
+        self.last_build_millis
+            .store(build_millis, Ordering::Relaxed);
+    }
+
+    fn record_failure(&self) {
+        self.batches_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> BatcherMetricsSnapshot {
+        let otxs_committed = self.otxs_committed.load(Ordering::Relaxed);
+        let total_latency_secs = self.total_latency_secs.load(Ordering::Relaxed);
+        BatcherMetricsSnapshot {
+            batches_committed: self.batches_committed.load(Ordering::Relaxed),
+            batches_failed: self.batches_failed.load(Ordering::Relaxed),
+            otxs_committed,
+            last_batch_size: self.last_batch_size.load(Ordering::Relaxed),
+            max_batch_size: self.max_batch_size.load(Ordering::Relaxed),
+            average_latency_secs: total_latency_secs
+                .checked_div(otxs_committed)
+                .unwrap_or_default(),
+            max_latency_secs: self.max_latency_secs.load(Ordering::Relaxed),
+            last_build_millis: self.last_build_millis.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A sent batch waiting to be committed. Its otxs stay in the pool until then.
+struct PendingBatch {
+    tx_hash: H256,
+    // the cell of the aggregator created by the batch
+    next_cell: OutPoint,
+    entries: Vec<(Id, OtxEntry)>,
+    build_millis: u64,
+}
+
+pub struct Batcher {
+    config: BatcherConfig,
+    service: Arc<OtxService>,
+    // the cell of the aggregator, spent and recreated by every batch
+    own_cell: Mutex<OutPoint>,
+    // the udt type of the cell of the aggregator, resolved on start
+    own_udt: Mutex<Option<Script>>,
+    // the sent batch not committed yet
+    pending: Mutex<Option<PendingBatch>>,
+    // the type hash of the omnilock in opentx mode, resolved on start
+    omnilock_type_hash: Mutex<Option<H256>>,
+    queue: Mutex<HashMap<Id, OtxEntry>>,
+    metrics: BatcherMetrics,
+}
+
+impl Batcher {
+    /// The aggregator signs with `signer`, and `own_cell` is a live cell of
+    /// its secp256k1 address.
+    pub fn new(config: BatcherConfig, signer: Signer, own_cell: OutPoint) -> Self {
+        let service = OtxService {
+            signer,
+            builder: OtxBuilder::new(vec![]),
+            committer: Committer::new(&config.ckb_uri),
+            fee_engine: FeeEngine::new(config.fee),
+        };
+        Batcher {
+            config,
+            service: Arc::new(service),
+            own_cell: Mutex::new(own_cell),
+            own_udt: Mutex::default(),
+            pending: Mutex::default(),
+            omnilock_type_hash: Mutex::default(),
+            queue: Mutex::default(),
+            metrics: BatcherMetrics::default(),
+        }
+    }
+
+    pub fn metrics(&self) -> BatcherMetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    fn is_compatible(&self, entry: &OtxEntry) -> bool {
+        let omnilock_type_hash = self.omnilock_type_hash.lock().expect("acquire lock");
+        match omnilock_type_hash.as_ref() {
+            Some(type_hash) => {
+                !entry.input_locks.is_empty()
+                    && entry.input_locks.iter().all(|lock| {
+                        &lock.code_hash == type_hash
+                            && lock.hash_type == json_types::ScriptHashType::Type
+                    })
+            }
+            None => false,
+        }
+    }
+
+    /// Merge the batch, add the cell of the aggregator, pay the fee, sign and
+    /// send the transaction. Returns the hash of the transaction and the cell
+    /// of the aggregator it creates.
+    fn commit_batch(
+        &self,
+        otxs: Vec<OpenTransaction>,
+        balance: AssetBalance,
+    ) -> Result<(H256, OutPoint)> {
+        let txes = otxs
+            .into_iter()
+            .map(|otx| {
+                otx_to_tx_view(otx)
+                    .map(|tx_view| Transaction::from(tx_view.inner).into_view())
+                    .map_err(|err| anyhow!(err.to_string()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let omnilock_config = omnilock_config(&txes[0], &self.config.ckb_uri)?;
+        let own_cell = self.own_cell.lock().expect("acquire lock").clone();
+        let service = self.service.clone();
+        let ckb_uri = self.config.ckb_uri.clone();
+
+        let (tx_hash, own_index) = run_blocking(move || -> Result<(H256, u32)> {
+            let merged = merge_txs(txes, &ckb_uri)?;
+            let own_index = merged.outputs().len() as u32;
+            let tx_info = TxInfo {
+                tx: json_types::TransactionView::from(merged),
+                omnilock_config,
+            };
+
+            let (capacity, udt) = resolve_own_cell(&ckb_uri, &own_cell)?;
+            let own_udt = udt.as_ref().map(|(type_script, _)| type_script);
+            if let Some(type_script) = unclaimed_udt(&balance, own_udt) {
+                return Err(anyhow!(
+                    "the batch leaves over udt {} not claimed by the aggregator",
+                    type_script.code_hash
+                ));
+            }
+            let udt_amount = match udt {
+                Some((type_script, amount)) => {
+                    let claimed = balance.get(&Some(type_script)).copied().unwrap_or_default();
+                    let udt_amount = amount
+                        .checked_add(claimed.max(0) as u128)
+                        .ok_or_else(|| anyhow!("udt amount of the aggregator overflow"))?;
+                    Some(udt_amount)
+                }
+                None => None,
+            };
+            let input = AddInputArgs {
+                tx_hash: own_cell.tx_hash,
+                index: own_cell.index.value() as usize,
+            };
+            let output = AddOutputArgs {
+                capacity: HumanCapacity(capacity),
+                udt_amount,
+            };
+            let tx_info = service.add_input_and_output(tx_info, input, output)?;
+            let tx_info = service.collect_fee(tx_info, 1)?;
+            let tx = service.signer.sign_tx(tx_info)?;
+            let tx_hash = service.committer.send_tx(tx)?;
+            Ok((tx_hash, own_index))
+        })
+        .map_err(|err| anyhow!(err))??;
+
+        let next_cell = OutPoint {
+            tx_hash: tx_hash.clone(),
+            index: own_index.into(),
+        };
+        Ok((tx_hash, next_cell))
+    }
+
+    /// Whether the previous batch is still waiting to be committed. Once it is
+    /// committed its otxs are discarded from the pool and its cell of the
+    /// aggregator is spent by the next batch. Once it is dropped by the node
+    /// its otxs are queued again and the current cell is still live.
+    fn is_pending(&self, context: &PluginContext) -> bool {
+        let tx_hash = match self.pending.lock().expect("acquire lock").as_ref() {
+            Some(pending) => pending.tx_hash.clone(),
+            None => return false,
+        };
+        let ckb_uri = self.config.ckb_uri.clone();
+        let hash = tx_hash.clone();
+        let status = run_blocking(move || {
+            CkbRpcClient::new(&ckb_uri)
+                .get_transaction(hash)
+                .map(|tx| tx.map(|tx| tx.tx_status.status))
+                .map_err(|err| err.to_string())
+        })
+        .and_then(|status| status);
+        let status = match status {
+            Ok(status) => status,
+            Err(err) => {
+                log::warn!("get the status of batch tx {:#x} failed: {}", tx_hash, err);
+                return true;
+            }
+        };
+        if let Some(Status::Pending) | Some(Status::Proposed) = status {
+            return true;
+        }
+
+        let pending = match self.pending.lock().expect("acquire lock").take() {
+            Some(pending) => pending,
+            None => return false,
+        };
+        if let Some(Status::Committed) = status {
+            *self.own_cell.lock().expect("acquire lock") = pending.next_cell;
+            let now = unix_timestamp();
+            let latencies: Vec<u64> = pending
+                .entries
+                .iter()
+                .map(|(_, entry)| now.saturating_sub(entry.inserted_at))
+                .collect();
+            self.metrics
+                .record_batch(pending.entries.len(), &latencies, pending.build_millis);
+            log::info!(
+                "committed {} otxs in tx {:#x}, metrics: {:?}",
+                pending.entries.len(),
+                tx_hash,
+                self.metrics.snapshot()
+            );
+            for (id, _) in pending.entries {
+                if let Err(err) = context.discard_otx(id.clone()) {
+                    log::warn!("discard committed otx {:#x} failed: {}", id, err);
+                }
+            }
+        } else {
+            self.metrics.record_failure();
+            log::warn!(
+                "batch tx {:#x} is dropped by the node, queue its {} otxs again",
+                tx_hash,
+                pending.entries.len()
+            );
+            let mut queue = self.queue.lock().expect("acquire lock");
+            for (id, entry) in pending.entries {
+                // the otxs deleted from the pool meanwhile are gone
+                if context.pool.get_otx_entry(&id).is_some() {
+                    queue.insert(id, entry);
+                }
+            }
+        }
+        false
+    }
+}
+
+#[async_trait]
+impl Plugin for Batcher {
+    fn info(&self) -> PluginInfo {
+        PluginInfo {
+            name: String::from("batcher"),
+            description: String::from("Aggregates the pooled otxs into ckb transactions"),
+            version: String::from("0.1.0"),
+            protocol_version: PROTOCOL_VERSION,
+            subscriptions: vec![
+                EventType::OtxPoolStart,
+                EventType::NewOtx,
+                EventType::DeleteOtx,
+                EventType::NewInterval,
+            ],
+            permissions: vec![Permission::DiscardOtx],
+            framing: Framing::default(),
+        }
+    }
+
+    /// The metrics snapshot in json.
+    fn status(&self) -> String {
+        serde_json::to_string(&self.metrics()).unwrap_or_default()
+    }
+
+    async fn on_start(&self, _context: &PluginContext) {
+        let ckb_uri = self.config.ckb_uri.clone();
+        let own_cell = self.own_cell.lock().expect("acquire lock").clone();
+        let own_udt = run_blocking(move || resolve_own_cell(&ckb_uri, &own_cell))
+            .map_err(|err| anyhow!(err))
+            .and_then(|own_cell| own_cell);
+        match own_udt {
+            Ok((_, udt)) => {
+                *self.own_udt.lock().expect("acquire lock") =
+                    udt.map(|(type_script, _)| type_script)
+            }
+            Err(err) => log::error!("resolve the cell of the aggregator failed: {}", err),
+        }
+
+        let ckb_uri = self.config.ckb_uri.clone();
+        let type_hash = run_blocking(move || {
+            let mut ckb_client = CkbRpcClient::new(&ckb_uri);
+            build_cell_dep(&mut ckb_client, &OMNI_OPENTX_TX_HASH, OMNI_OPENTX_TX_IDX)
+                .map(|cell| cell.type_hash)
+        })
+        .map_err(|err| anyhow!(err))
+        .and_then(|type_hash| type_hash);
+        match type_hash {
+            Ok(type_hash) => {
+                *self.omnilock_type_hash.lock().expect("acquire lock") = Some(type_hash)
+            }
+            Err(err) => log::error!("resolve the omnilock failed: {}", err),
+        }
+    }
+
+    async fn on_new_otx(&self, context: &PluginContext, id: Id, _otx: OpenTransaction) {
+        match context.pool.get_otx_entry(&id) {
+            Some(entry) if self.is_compatible(&entry) => {
+                self.queue.lock().expect("acquire lock").insert(id, entry);
+            }
+            Some(_) => log::debug!("otx {:#x} is not locked by the opentx omnilock", id),
+            None => log::debug!("otx {:#x} is gone before being queued", id),
+        }
+    }
+
+    async fn on_delete_otx(&self, _context: &PluginContext, id: Id) {
+        self.queue.lock().expect("acquire lock").remove(&id);
+    }
+
+    async fn on_interval(&self, context: &PluginContext) {
+        if self.is_pending(context) {
+            log::debug!("the previous batch is not committed yet");
+            return;
+        }
+        let (entries, otxs, balance) = {
+            let queue = self.queue.lock().expect("acquire lock");
+            let own_udt = self.own_udt.lock().expect("acquire lock").clone();
+            let ids = select_batch(
+                &queue,
+                &self.config,
+                &self.service.fee_engine,
+                own_udt.as_ref(),
+            );
+            if ids.is_empty() {
+                return;
+            }
+            let entries: Vec<(Id, OtxEntry)> = ids
+                .into_iter()
+                .map(|id| {
+                    let entry = queue[&id].clone();
+                    (id, entry)
+                })
+                .collect();
+            let otxs: Vec<OpenTransaction> =
+                entries.iter().map(|(_, entry)| entry.otx.clone()).collect();
+            let balance =
+                batch_balance(&entries.iter().map(|(_, entry)| entry).collect::<Vec<_>>());
+            (entries, otxs, balance)
+        };
+
+        let started_at = Instant::now();
+        match self.commit_batch(otxs, balance) {
+            Ok((tx_hash, next_cell)) => {
+                let build_millis = started_at.elapsed().as_millis() as u64;
+                log::info!("sent {} otxs in tx {:#x}", entries.len(), tx_hash);
+                let mut queue = self.queue.lock().expect("acquire lock");
+                for (id, _) in &entries {
+                    queue.remove(id);
+                }
+                drop(queue);
+                *self.pending.lock().expect("acquire lock") = Some(PendingBatch {
+                    tx_hash,
+                    next_cell,
+                    entries,
+                    build_millis,
+                });
+            }
+            Err(err) => {
+                self.metrics.record_failure();
+                log::warn!("commit batch of {} otxs failed: {}", entries.len(), err);
+            }
+        }
+    }
+}
+
+/// Select the oldest otxs which fit into one transaction and settle each
+/// other, leaving over no udt but `own_udt`. Returns an empty batch if none is
+/// ready.
+fn select_batch(
+    queue: &HashMap<Id, OtxEntry>,
+    config: &BatcherConfig,
+    fee_engine: &FeeEngine,
+    own_udt: Option<&Script>,
+) -> Vec<Id> {
+    let mut candidates: Vec<(&Id, &OtxEntry)> = queue.iter().collect();
+    candidates.sort_by(|(id_a, a), (id_b, b)| {
+        a.inserted_at
+            .cmp(&b.inserted_at)
+            .then_with(|| id_a.cmp(id_b))
+    });
+
+    let max_bytes = config.max_tx_bytes.saturating_sub(RESERVED_TX_BYTES);
+    // the input of the aggregator is verified too
+    let max_cycles = config.max_cycles.saturating_sub(config.cycles_per_input);
+    let mut batch: Vec<(&Id, &OtxEntry)> = vec![];
+    let mut spent: HashSet<&OutPoint> = HashSet::new();
+    let (mut bytes, mut cycles) = (0usize, 0u64);
+    for (id, entry) in candidates {
+        if batch.len() >= config.max_batch_size {
+            break;
+        }
+        let entry_cycles = entry.inputs.len() as u64 * config.cycles_per_input;
+        if bytes + entry.size > max_bytes || cycles + entry_cycles > max_cycles {
+            continue;
+        }
+        // the otxs spending the same cell can not be merged
+        if entry.inputs.iter().any(|input| spent.contains(input)) {
+            continue;
+        }
+        spent.extend(entry.inputs.iter());
+        bytes += entry.size;
+        cycles += entry_cycles;
+        batch.push((id, entry));
+    }
+
+    // drop the newest otxs asking for what the batch lacks
+    loop {
+        let balance = batch_balance(&batch.iter().map(|(_, entry)| *entry).collect::<Vec<_>>());
+        let lacking: Vec<&Option<Script>> = balance
+            .iter()
+            .filter(|(_, amount)| **amount < 0)
+            .map(|(asset, _)| asset)
+            .collect();
+        if lacking.is_empty() {
+            // then the newest otxs giving away a udt no one claims
+            if let Some(type_script) = unclaimed_udt(&balance, own_udt) {
+                let asset = Some(type_script.clone());
+                let position = batch.iter().rposition(|(_, entry)| {
+                    entry.balance.get(&asset).copied().unwrap_or_default() > 0
+                });
+                match position {
+                    Some(position) => {
+                        let (_, entry) = batch.remove(position);
+                        bytes -= entry.size;
+                        continue;
+                    }
+                    None => return vec![],
+                }
+            }
+            let fee = fee_engine.calculate_fee(bytes + RESERVED_TX_BYTES);
+            let open_capacity = balance.get(&None).copied().unwrap_or_default();
+            if batch.len() < config.min_batch_size || open_capacity < fee as i128 {
+                return vec![];
+            }
+            return batch.into_iter().map(|(id, _)| id.clone()).collect();
+        }
+        let position = batch.iter().rposition(|(_, entry)| {
+            lacking
+                .iter()
+                .any(|asset| entry.balance.get(*asset).copied().unwrap_or_default() < 0)
+        });
+        match position {
+            Some(position) => {
+                let (_, entry) = batch.remove(position);
+                bytes -= entry.size;
+            }
+            None => return vec![],
+        }
+    }
+}
+
+/// A udt the batch leaves over which the cell of the aggregator does not hold.
+fn unclaimed_udt<'a>(balance: &'a AssetBalance, own_udt: Option<&Script>) -> Option<&'a Script> {
+    balance
+        .iter()
+        .filter(|(_, amount)| **amount > 0)
+        .filter_map(|(asset, _)| asset.as_ref())
+        .find(|type_script| Some(*type_script) != own_udt)
+}
+
+fn batch_balance(entries: &[&OtxEntry]) -> AssetBalance {
+    let mut balance = AssetBalance::new();
+    for entry in entries {
+        for (asset, amount) in &entry.balance {
+            *balance.entry(asset.clone()).or_default() += amount;
+        }
+    }
+    balance
+}
+
+/// The omnilock config of the first input of the first otx. `sign_tx` checks
+/// with it that the otxs are signed already.
+fn omnilock_config(tx: &ckb_types::core::TransactionView, ckb_uri: &str) -> Result<OmniLockConfig> {
+    let input = tx
+        .inputs()
+        .get(0)
+        .ok_or_else(|| anyhow!("otx without inputs"))?;
+    let out_point: OutPoint = input.previous_output().into();
+    let ckb_uri = ckb_uri.to_owned();
+    let cell = run_blocking(move || CkbRpcClient::new(&ckb_uri).get_live_cell(out_point, false))
+        .map_err(|err| anyhow!(err))??
+        .cell
+        .ok_or_else(|| anyhow!("the input of the otx is not live"))?;
+    let args = cell.output.lock.args.as_bytes();
+    if args.len() < 21 {
+        return Err(anyhow!("invalid omnilock args"));
+    }
+    let auth = H160::from_slice(&args[1..21]).map_err(|err| anyhow!(err.to_string()))?;
+    let mut config = match args[0] {
+        0 => OmniLockConfig::new_pubkey_hash(auth),
+        1 => OmniLockConfig::new_ethereum(auth),
+        flag => return Err(anyhow!("unsupported omnilock identity {}", flag)),
+    };
+    config.set_opentx_mode();
+    Ok(config)
+}
+
+/// The capacity of the cell, and its udt type script and amount if any.
+fn resolve_own_cell(ckb_uri: &str, out_point: &OutPoint) -> Result<(u64, Option<(Script, u128)>)> {
+    let cell = CkbRpcClient::new(ckb_uri)
+        .get_live_cell(out_point.clone(), true)?
+        .cell
+        .ok_or_else(|| anyhow!("the cell of the aggregator is not live"))?;
+    let capacity = cell.output.capacity.value();
+    let udt = match cell.output.type_ {
+        Some(type_script) => {
+            let data = cell.data.map(|data| data.content).unwrap_or_default();
+            let data = data.as_bytes();
+            if data.len() < 16 {
+                return Err(anyhow!("the cell of the aggregator holds no udt"));
+            }
+            let mut amount = [0u8; 16];
+            amount.copy_from_slice(&data[..16]);
+            Some((type_script, u128::from_le_bytes(amount)))
+        }
+        None => None,
+    };
+    Ok((capacity, udt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use otx_format::types::packed;
+
+    fn entry(inserted_at: u64, inputs: &[u32], balance: &[(Option<Script>, i128)]) -> OtxEntry {
+        OtxEntry {
+            otx: packed::OpenTransaction::default().into(),
+            inputs: inputs
+                .iter()
+                .map(|index| OutPoint {
+                    tx_hash: H256::default(),
+                    index: (*index).into(),
+                })
+                .collect(),
+            fee: 0,
+            expire_at: 0,
+            expire_block_number: None,
+            inserted_at,
+            size: 1000,
+            submitter: None,
+            input_locks: vec![],
+            lock_hashes: HashSet::new(),
+            type_hashes: HashSet::new(),
+            balance: balance.iter().cloned().collect(),
+            parent_id: None,
+        }
+    }
+
+    fn id(n: u8) -> Id {
+        let mut bytes = [0u8; 32];
+        bytes[31] = n;
+        H256(bytes)
+    }
+
+    fn udt() -> Option<Script> {
+        Some(Script::default())
+    }
+
+    #[test]
+    fn test_select_batch() {
+        let config = BatcherConfig {
+            max_batch_size: 3,
+            ..Default::default()
+        };
+        let fee_engine = FeeEngine::default();
+        let mut queue = HashMap::new();
+        queue.insert(id(1), entry(1, &[0], &[(None, 50_0000_0000), (udt(), -51)]));
+        queue.insert(id(2), entry(2, &[1], &[(udt(), 51)]));
+        // spends the same cell as the first
+        queue.insert(id(3), entry(3, &[0], &[(None, 1_0000_0000)]));
+        queue.insert(id(4), entry(4, &[2], &[(None, 1_0000_0000)]));
+        queue.insert(id(5), entry(5, &[3], &[(None, 1_0000_0000)]));
+
+        let batch = select_batch(&queue, &config, &fee_engine, None);
+        assert_eq!(batch, vec![id(1), id(2), id(4)]);
+    }
+
+    #[test]
+    fn test_select_batch_drops_unsettled() {
+        let config = BatcherConfig::default();
+        let fee_engine = FeeEngine::default();
+        let mut queue = HashMap::new();
+        queue.insert(id(1), entry(1, &[0], &[(None, 1_0000_0000)]));
+        queue.insert(id(2), entry(2, &[1], &[(None, 1_0000), (udt(), -10)]));
+
+        let batch = select_batch(&queue, &config, &fee_engine, None);
+        assert_eq!(batch, vec![id(1)]);
+
+        // no one gives away the udt asked for
+        queue.remove(&id(1));
+        assert!(select_batch(&queue, &config, &fee_engine, None).is_empty());
+    }
+
+    #[test]
+    fn test_select_batch_drops_unclaimed_udt() {
+        let config = BatcherConfig::default();
+        let fee_engine = FeeEngine::default();
+        let mut queue = HashMap::new();
+        queue.insert(id(1), entry(1, &[0], &[(None, 1_0000_0000)]));
+        queue.insert(id(2), entry(2, &[1], &[(None, 1_0000), (udt(), 10)]));
+
+        // the cell of the aggregator holds no udt
+        let batch = select_batch(&queue, &config, &fee_engine, None);
+        assert_eq!(batch, vec![id(1)]);
+
+        let own_udt = udt();
+        let batch = select_batch(&queue, &config, &fee_engine, own_udt.as_ref());
+        assert_eq!(batch, vec![id(1), id(2)]);
+    }
+
+    #[test]
+    fn test_metrics() {
+        let metrics = BatcherMetrics::default();
+        metrics.record_batch(2, &[3, 5], 10);
+        metrics.record_failure();
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.batches_committed, 1);
+        assert_eq!(snapshot.batches_failed, 1);
+        assert_eq!(snapshot.otxs_committed, 2);
+        assert_eq!(snapshot.average_latency_secs, 4);
+        assert_eq!(snapshot.max_latency_secs, 5);
+    }
+}
@@ -0,0 +1,183 @@
+//! The fee of the aggregated transaction.
+//!
+//! The participants leave some capacity open in their otxs. The aggregator
+//! pays the fee out of it, or out of its own input cell, and sends the surplus
+//! back to itself in a change output.
+
+use anyhow::{anyhow, Result};
+use ckb_sdk::rpc::CkbRpcClient;
+use ckb_types::{
+    core::{Capacity, TransactionView},
+    packed::{CellOutput, Script},
+    prelude::*,
+};
+
+/// The minimal fee rate accepted by the ckb node, in shannons/KB.
+pub const DEFAULT_FEE_RATE: u64 = 1000;
+
+/// A `WitnessArgs` with a secp256k1 signature in the lock, as serialized in
+/// the witness vector.
+pub const SIGNATURE_WITNESS_SIZE: usize = 85 + 4 + 4;
+
+#[derive(Clone, Copy, Debug)]
+pub struct FeeConfig {
+    /// shannons/KB
+    pub fee_rate: u64,
+}
+
+impl Default for FeeConfig {
+    fn default() -> Self {
+        FeeConfig {
+            fee_rate: DEFAULT_FEE_RATE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FeeEngine {
+    config: FeeConfig,
+}
+
+impl FeeEngine {
+    pub fn new(config: FeeConfig) -> Self {
+        FeeEngine { config }
+    }
+
+    pub fn fee_rate(&self) -> u64 {
+        self.config.fee_rate
+    }
+
+    /// The fee of a transaction of `size` bytes, rounded up.
+    pub fn calculate_fee(&self, size: usize) -> u64 {
+        let fee = size as u128 * self.config.fee_rate as u128;
+        (fee / 1000 + u128::from(fee % 1000 != 0)) as u64
+    }
+
+    /// The size of the transaction in a block, counting `unsigned_witnesses`
+    /// witnesses to be signed after the fee is paid.
+    pub fn estimate_tx_size(&self, tx: &TransactionView, unsigned_witnesses: usize) -> usize {
+        tx.data().serialized_size_in_block() + unsigned_witnesses * SIGNATURE_WITNESS_SIZE
+    }
+
+    /// Pay the fee out of the open capacity, `input_capacity` minus the
+    /// capacity of the outputs, and add a change output to `change_lock` for
+    /// the surplus. The surplus too small for a cell is left to the miner.
+    pub fn add_change_output(
+        &self,
+        tx: TransactionView,
+        input_capacity: u64,
+        unsigned_witnesses: usize,
+        change_lock: Script,
+    ) -> Result<TransactionView> {
+        let output_capacity = tx
+            .outputs_capacity()
+            .map_err(|err| anyhow!(err.to_string()))?
+            .as_u64();
+        let open_capacity = input_capacity.checked_sub(output_capacity).ok_or_else(|| {
+            anyhow!(
+                "outputs capacity {} exceeds inputs capacity {}",
+                output_capacity,
+                input_capacity
+            )
+        })?;
+
+        let change_output = CellOutput::new_builder().lock(change_lock).build();
+        let change_occupied = change_output
+            .occupied_capacity(Capacity::zero())
+            .map_err(|err| anyhow!(err.to_string()))?
+            .as_u64();
+        let with_change = tx
+            .as_advanced_builder()
+            .output(change_output.clone())
+            .output_data(Default::default())
+            .build();
+        let fee = self.calculate_fee(self.estimate_tx_size(&with_change, unsigned_witnesses));
+        if let Some(change) = open_capacity
+            .checked_sub(fee)
+            .filter(|change| *change >= change_occupied)
+        {
+            let change_output = change_output.as_builder().capacity(change.pack()).build();
+            return Ok(tx
+                .as_advanced_builder()
+                .output(change_output)
+                .output_data(Default::default())
+                .build());
+        }
+
+        let fee = self.calculate_fee(self.estimate_tx_size(&tx, unsigned_witnesses));
+        if open_capacity < fee {
+            return Err(anyhow!(
+                "open capacity {} is less than the fee {}",
+                open_capacity,
+                fee
+            ));
+        }
+        Ok(tx)
+    }
+}
+
+/// The capacity of the live cells spent by the transaction.
+pub fn resolve_input_capacity(ckb_uri: &str, tx: &TransactionView) -> Result<u64> {
+    let mut ckb_client = CkbRpcClient::new(ckb_uri);
+    let mut capacity = 0u64;
+    for input in tx.inputs().into_iter() {
+        let cell = ckb_client
+            .get_live_cell(input.previous_output().into(), false)?
+            .cell
+            .ok_or_else(|| anyhow!("input {} is not live", input.previous_output()))?;
+        capacity = capacity
+            .checked_add(cell.output.capacity.value())
+            .ok_or_else(|| anyhow!("inputs capacity overflow"))?;
+    }
+    Ok(capacity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ckb_types::core::TransactionBuilder;
+
+    fn tx_paying(capacity: u64) -> TransactionView {
+        TransactionBuilder::default()
+            .output(CellOutput::new_builder().capacity(capacity.pack()).build())
+            .output_data(Default::default())
+            .build()
+    }
+
+    #[test]
+    fn test_calculate_fee() {
+        let engine = FeeEngine::default();
+        assert_eq!(engine.calculate_fee(1000), 1000);
+        assert_eq!(engine.calculate_fee(1001), 1001);
+
+        let engine = FeeEngine::new(FeeConfig { fee_rate: 1500 });
+        assert_eq!(engine.calculate_fee(1), 2);
+    }
+
+    #[test]
+    fn test_add_change_output() {
+        let engine = FeeEngine::default();
+        let tx = tx_paying(100_0000_0000);
+
+        let with_change = engine
+            .add_change_output(tx.clone(), 200_0000_0000, 1, Script::default())
+            .unwrap();
+        assert_eq!(with_change.outputs().len(), 2);
+        let fee = 200_0000_0000 - with_change.outputs_capacity().unwrap().as_u64();
+        assert_eq!(
+            fee,
+            engine.calculate_fee(engine.estimate_tx_size(&with_change, 1))
+        );
+
+        // too little left for a change cell
+        let without_change = engine
+            .add_change_output(tx.clone(), 100_0001_0000, 1, Script::default())
+            .unwrap();
+        assert_eq!(without_change.outputs().len(), 1);
+
+        assert!(engine
+            .add_change_output(tx, 100_0000_0001, 1, Script::default())
+            .is_err());
+    }
+}
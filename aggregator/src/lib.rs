@@ -1,16 +1,24 @@
+pub mod atomic_swap;
+pub mod batcher;
+pub mod fee;
+pub mod partial_fill;
+
+use fee::{resolve_input_capacity, FeeEngine};
+
 use utils::build_tx::{add_input, add_output, sighash_sign};
 use utils::const_definition::{CKB_URI, OMNI_OPENTX_TX_HASH, OMNI_OPENTX_TX_IDX};
 use utils::lock::omni::{build_cell_dep, TxInfo};
-use utils::lock::secp::generate_rand_secp_address_pk_pair;
+use utils::lock::secp::{generate_rand_secp_address_pk_pair, secp_address_from_pk};
 
 use anyhow::{anyhow, Result};
 use ckb_jsonrpc_types as json_types;
 use ckb_sdk::{
-    rpc::CkbRpcClient, traits::DefaultTransactionDependencyProvider,
+    constants::SIGHASH_TYPE_HASH, rpc::CkbRpcClient, traits::DefaultTransactionDependencyProvider,
     unlock::opentx::assembler::assemble_new_tx, unlock::OmniUnlockMode, Address, HumanCapacity,
 };
 use ckb_types::{
-    packed::{Transaction, WitnessArgs},
+    core::TransactionView,
+    packed::{Script, Transaction, WitnessArgs},
     prelude::*,
     H256,
 };
@@ -19,6 +27,7 @@ pub struct OtxService {
     pub signer: Signer,
     pub builder: OtxBuilder,
     pub committer: Committer,
+    pub fee_engine: FeeEngine,
 }
 
 impl OtxService {
@@ -30,6 +39,7 @@ impl OtxService {
             signer,
             builder,
             committer,
+            fee_engine: FeeEngine::default(),
         }
     }
 
@@ -47,6 +57,27 @@ impl OtxService {
             output.udt_amount,
         )
     }
+
+    /// Pay the fee out of the capacity left open by the participants and the
+    /// inputs of the aggregator, and send the surplus to the aggregator.
+    ///
+    /// The `aggregator_inputs` cells added by the aggregator are signed after
+    /// the fee is paid, so their signatures are counted in the size.
+    pub fn collect_fee(&self, tx_info: TxInfo, aggregator_inputs: usize) -> Result<TxInfo> {
+        let tx = Transaction::from(tx_info.tx.inner).into_view();
+        let input_capacity = resolve_input_capacity(&self.committer.ckb_uri, &tx)?;
+        let change_lock = Script::from(self.signer.get_secp_address().payload());
+        let tx = self.fee_engine.add_change_output(
+            tx,
+            input_capacity,
+            aggregator_inputs,
+            change_lock,
+        )?;
+        Ok(TxInfo {
+            tx: json_types::TransactionView::from(tx),
+            omnilock_config: tx_info.omnilock_config,
+        })
+    }
 }
 
 pub struct Committer {
@@ -79,21 +110,42 @@ impl Signer {
         Signer { pk, secp_address }
     }
 
+    /// The signer of an existing secp256k1 account.
+    pub fn new(pk: H256) -> Result<Self> {
+        let secp_address = secp_address_from_pk(&pk)?;
+        Ok(Signer { pk, secp_address })
+    }
+
     pub fn get_secp_address(&self) -> &Address {
         &self.secp_address
     }
 
+    /// Sign the inputs of the aggregator. Fails if they are left unsigned, or
+    /// if the otxs merged in the transaction are not signed by their owners.
     pub fn sign_tx(&self, tx_info: TxInfo) -> Result<json_types::TransactionView> {
         let tx = Transaction::from(tx_info.tx.inner).into_view();
-        let (tx, _) = sighash_sign(&[self.pk.clone()], tx)?;
-        let witness_args =
-            WitnessArgs::from_slice(tx.witnesses().get(0).unwrap().raw_data().as_ref())?;
-        let lock_field = witness_args.lock().to_opt().unwrap().raw_data();
-        if lock_field != tx_info.omnilock_config.zero_lock(OmniUnlockMode::Normal)? {
-            println!("> transaction ready to send!");
-        } else {
-            println!("failed to sign tx");
+        let (tx, still_locked_groups) = sighash_sign(&[self.pk.clone()], tx)?;
+        let sighash_code_hash = SIGHASH_TYPE_HASH.pack();
+        if still_locked_groups
+            .iter()
+            .any(|group| group.script.code_hash().as_slice() == sighash_code_hash.as_slice())
+        {
+            return Err(anyhow!("failed to sign the inputs of the aggregator"));
+        }
+        let witness = tx
+            .witnesses()
+            .get(0)
+            .ok_or_else(|| anyhow!("transaction without witnesses"))?;
+        let witness_args = WitnessArgs::from_slice(witness.raw_data().as_ref())?;
+        let lock_field = witness_args
+            .lock()
+            .to_opt()
+            .ok_or_else(|| anyhow!("the first witness has no lock"))?
+            .raw_data();
+        if lock_field == tx_info.omnilock_config.zero_lock(OmniUnlockMode::Normal)? {
+            return Err(anyhow!("the first otx is not signed"));
         }
+        log::debug!("transaction {:#x} is signed", tx.hash());
         Ok(json_types::TransactionView::from(tx))
     }
 }
@@ -117,10 +169,7 @@ impl OtxBuilder {
             omnilock_config = Some(tx_info.omnilock_config.clone());
         }
         if !txes.is_empty() {
-            let mut ckb_client = CkbRpcClient::new(CKB_URI);
-            let cell = build_cell_dep(&mut ckb_client, &OMNI_OPENTX_TX_HASH, OMNI_OPENTX_TX_IDX)?;
-            let tx_dep_provider = DefaultTransactionDependencyProvider::new(CKB_URI, 10);
-            let tx = assemble_new_tx(txes, &tx_dep_provider, cell.type_hash.pack())?;
+            let tx = merge_txs(txes, CKB_URI)?;
             let tx_info = TxInfo {
                 tx: json_types::TransactionView::from(tx),
                 omnilock_config: omnilock_config.unwrap(),
@@ -131,6 +180,15 @@ impl OtxBuilder {
     }
 }
 
+/// Assemble the otxs locked by the omnilock in opentx mode into one transaction.
+pub fn merge_txs(txes: Vec<TransactionView>, ckb_uri: &str) -> Result<TransactionView> {
+    let mut ckb_client = CkbRpcClient::new(ckb_uri);
+    let cell = build_cell_dep(&mut ckb_client, &OMNI_OPENTX_TX_HASH, OMNI_OPENTX_TX_IDX)?;
+    let tx_dep_provider = DefaultTransactionDependencyProvider::new(ckb_uri, 10);
+    let tx = assemble_new_tx(txes, &tx_dep_provider, cell.type_hash.pack())?;
+    Ok(tx)
+}
+
 pub struct AddInputArgs {
     /// omnilock script deploy transaction hash
     pub tx_hash: H256,
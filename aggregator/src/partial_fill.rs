@@ -0,0 +1,268 @@
+//! The residual otx of a partially filled order.
+//!
+//! When the settlement fills only part of a divisible order, the unfilled
+//! offered asset goes back to the owner in a change cell of the settlement.
+//! The residual otx spends that cell and asks for the rest of the requested
+//! asset, carrying the residual intent and a link to the parent otx.
+//!
+//! Only the owner can sign the residual otx, so the settler leaves it unsigned
+//! as a residual template in the pool, see `OtxPool::add_residual_template`.
+//! The owner fetches the template, signs it and submits it as a new otx.
+
+use otx_format::jsonrpc_types::tx_view::{otx_to_tx_view, tx_view_to_otx};
+use otx_format::jsonrpc_types::{IntentAsset, OpenTransaction, PartialFill};
+use otx_pool::pool::Id;
+
+use ckb_jsonrpc_types as json_types;
+use ckb_types::{
+    core::{Capacity, TransactionBuilder, TransactionView},
+    packed::{self, CellInput, CellOutput, OutPoint},
+    prelude::*,
+};
+
+/// The change cell of the settlement holding the unfilled offered asset.
+#[derive(Clone, Debug)]
+pub struct ResidualCell {
+    pub out_point: OutPoint,
+    pub output: CellOutput,
+}
+
+/// Build the residual otx of the parent after `fill`. Returns `None` if the
+/// order is filled up.
+///
+/// The witness is left empty for the signature of the owner.
+pub fn build_residual_otx(
+    parent_id: &Id,
+    parent: &OpenTransaction,
+    fill: &PartialFill,
+    residual_cell: ResidualCell,
+) -> Result<Option<OpenTransaction>, String> {
+    let intent = match &fill.residual {
+        Some(intent) => intent,
+        None => return Ok(None),
+    };
+    let parent_tx = otx_to_tx_view(parent.clone()).map_err(|err| err.to_string())?;
+    let parent_tx = packed::Transaction::from(parent_tx.inner).into_view();
+
+    let mut capacity: u64 = residual_cell.output.capacity().unpack();
+    if intent.offered_asset == IntentAsset::Ckb {
+        let offered = u64::try_from(intent.offered_amount).map_err(|err| err.to_string())?;
+        capacity = capacity
+            .checked_sub(offered)
+            .ok_or_else(|| String::from("residual cell holds less than the offer"))?;
+    }
+    let output = CellOutput::new_builder().lock(residual_cell.output.lock());
+    let (output, data) = match &intent.requested_asset {
+        IntentAsset::Ckb => {
+            let requested =
+                u64::try_from(intent.requested_amount).map_err(|err| err.to_string())?;
+            let capacity = capacity
+                .checked_add(requested)
+                .ok_or_else(|| String::from("requested capacity overflow"))?;
+            (output.capacity(capacity.pack()).build(), Vec::new())
+        }
+        IntentAsset::Udt(type_script) => {
+            let type_script = packed::Script::from(type_script.clone());
+            let output = output
+                .capacity(capacity.pack())
+                .type_(Some(type_script).pack())
+                .build();
+            (output, intent.requested_amount.to_le_bytes().to_vec())
+        }
+    };
+    let occupied = Capacity::bytes(data.len())
+        .and_then(|data_capacity| output.occupied_capacity(data_capacity))
+        .map_err(|err| err.to_string())?;
+    let capacity: u64 = output.capacity().unpack();
+    if occupied.as_u64() > capacity {
+        return Err(format!(
+            "residual output needs {} shannons but has {}",
+            occupied.as_u64(),
+            capacity
+        ));
+    }
+
+    let tx = TransactionBuilder::default()
+        .cell_deps(parent_tx.cell_deps())
+        .input(CellInput::new(residual_cell.out_point, 0))
+        .witness(packed::Bytes::default())
+        .output(output)
+        .output_data(data.pack())
+        .build();
+    let mut otx =
+        tx_view_to_otx(json_types::TransactionView::from(tx)).map_err(|err| err.to_string())?;
+    otx.set_intent(intent);
+    otx.set_parent_id(parent_id);
+    Ok(Some(otx))
+}
+
+/// Build the residual otx of the parent settled in `settlement`, which gives
+/// away `given` of the offered asset. The residual cell is the change output
+/// of the parent to `owner_lock` holding the offered asset. Returns `None` if
+/// the parent declares no divisible intent or is filled up.
+pub fn residual_of(
+    parent_id: &Id,
+    parent: &OpenTransaction,
+    given: u128,
+    owner_lock: &packed::Script,
+    settlement: &TransactionView,
+) -> Result<Option<OpenTransaction>, String> {
+    let intent = match parent.get_intent().map_err(|err| err.to_string())? {
+        Some(intent) if intent.allow_partial_fill && given < intent.offered_amount => intent,
+        _ => return Ok(None),
+    };
+    let fill = intent.fill(given).map_err(|err| err.to_string())?;
+
+    // the change output of the parent, found again in the settlement
+    let parent_tx = otx_to_tx_view(parent.clone()).map_err(|err| err.to_string())?;
+    let parent_tx = packed::Transaction::from(parent_tx.inner).into_view();
+    let offered_type = intent
+        .offered_asset
+        .type_script()
+        .map(|script| packed::Script::from(script.clone()))
+        .pack();
+    let change = parent_tx
+        .outputs_with_data_iter()
+        .find(|(output, _)| {
+            output.lock().as_slice() == owner_lock.as_slice()
+                && output.type_().as_slice() == offered_type.as_slice()
+        })
+        .ok_or_else(|| String::from("no change output holding the offered asset"))?;
+    let index = settlement
+        .outputs_with_data_iter()
+        .position(|(output, data)| output.as_slice() == change.0.as_slice() && data == change.1)
+        .ok_or_else(|| String::from("change output is not in the settlement"))?;
+    let residual_cell = ResidualCell {
+        out_point: OutPoint::new(settlement.hash(), index as u32),
+        output: change.0,
+    };
+    build_residual_otx(parent_id, parent, &fill, residual_cell)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use otx_format::jsonrpc_types::OtxIntent;
+
+    use ckb_types::{h256, H256};
+
+    fn udt_script() -> json_types::Script {
+        packed::Script::new_builder()
+            .code_hash(h256!("0x1234").pack())
+            .build()
+            .into()
+    }
+
+    #[test]
+    fn test_residual_otx() {
+        let parent = tx_view_to_otx(json_types::TransactionView::from(
+            TransactionBuilder::default().build(),
+        ))
+        .unwrap();
+        let parent_id = parent.get_otx_hash();
+        let intent = OtxIntent::new(
+            IntentAsset::Udt(udt_script()),
+            100,
+            IntentAsset::Ckb,
+            1_000_000_000,
+        )
+        .with_partial_fill(true);
+        let fill = intent.fill(40).unwrap();
+        let residual_cell = ResidualCell {
+            out_point: OutPoint::new(H256::default().pack(), 1),
+            output: CellOutput::new_builder()
+                .capacity(14_200_000_000u64.pack())
+                .build(),
+        };
+
+        let otx = build_residual_otx(&parent_id, &parent, &fill, residual_cell)
+            .unwrap()
+            .unwrap();
+        assert_eq!(otx.get_parent_id().unwrap(), Some(parent_id.clone()));
+        assert_eq!(otx.get_intent().unwrap(), fill.residual.clone());
+        let tx = otx_to_tx_view(otx).unwrap();
+        assert_eq!(tx.inner.inputs.len(), 1);
+        assert_eq!(
+            tx.inner.outputs[0].capacity.value(),
+            14_200_000_000 + 600_000_000
+        );
+
+        let fill = intent.fill(100).unwrap();
+        let residual_cell = ResidualCell {
+            out_point: OutPoint::default(),
+            output: CellOutput::default(),
+        };
+        assert!(
+            build_residual_otx(&parent_id, &parent, &fill, residual_cell)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_residual_of_settlement() {
+        let owner_lock = packed::Script::new_builder()
+            .args(vec![1u8; 20].pack())
+            .build();
+        let udt_type = packed::Script::from(udt_script());
+        let receive = CellOutput::new_builder()
+            .capacity(16_100_000_000u64.pack())
+            .lock(owner_lock.clone())
+            .build();
+        let change = CellOutput::new_builder()
+            .capacity(14_200_000_000u64.pack())
+            .lock(owner_lock.clone())
+            .type_(Some(udt_type).pack())
+            .build();
+        let mut parent = tx_view_to_otx(json_types::TransactionView::from(
+            TransactionBuilder::default()
+                .input(CellInput::new(OutPoint::default(), 0))
+                .witness(packed::Bytes::default())
+                .output(receive.clone())
+                .output_data(packed::Bytes::default())
+                .output(change.clone())
+                .output_data(60u128.to_le_bytes().to_vec().pack())
+                .build(),
+        ))
+        .unwrap();
+        let intent = OtxIntent::new(
+            IntentAsset::Udt(udt_script()),
+            100,
+            IntentAsset::Ckb,
+            1_000_000_000,
+        )
+        .with_partial_fill(true);
+        parent.set_intent(&intent);
+        let parent_id = parent.get_otx_hash();
+
+        // the outputs of another otx come first in the settlement
+        let settlement = TransactionBuilder::default()
+            .output(CellOutput::default())
+            .output_data(packed::Bytes::default())
+            .output(receive)
+            .output_data(packed::Bytes::default())
+            .output(change)
+            .output_data(60u128.to_le_bytes().to_vec().pack())
+            .build();
+        let otx = residual_of(&parent_id, &parent, 40, &owner_lock, &settlement)
+            .unwrap()
+            .unwrap();
+        assert_eq!(otx.get_parent_id().unwrap(), Some(parent_id.clone()));
+        let tx = otx_to_tx_view(otx).unwrap();
+        let input = &tx.inner.inputs[0].previous_output;
+        let settlement_hash: H256 = settlement.hash().unpack();
+        assert_eq!(input.tx_hash, settlement_hash);
+        assert_eq!(input.index.value(), 2);
+        // left for the signature of the owner
+        assert_eq!(tx.inner.witnesses.len(), 1);
+        assert!(tx.inner.witnesses[0].is_empty());
+
+        // filled up
+        assert!(
+            residual_of(&parent_id, &parent, 100, &owner_lock, &settlement)
+                .unwrap()
+                .is_none()
+        );
+    }
+}
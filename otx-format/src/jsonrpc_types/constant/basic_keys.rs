@@ -1,5 +1,7 @@
 /// Meta Map Keys
 pub const OTX_META_VERSION: u32 = 0x01;
+pub const OTX_META_EXPIRE_TIMESTAMP: u32 = 0x13;
+pub const OTX_META_EXPIRE_BLOCK_NUMBER: u32 = 0x14;
 
 /// Cell Dep Keys
 pub const OTX_CELL_DEP_OUTPOINT_TX_HASH: u32 = 0x02;
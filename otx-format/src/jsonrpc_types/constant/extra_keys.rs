@@ -1,2 +1,14 @@
 /// Meta Map Keys
 pub const OTX_VERSIONING_META_OPEN_TX_VERSION: u32 = 0x10000;
+
+/// Intent Meta Keys
+pub const OTX_INTENT_OFFERED_ASSET: u32 = 0x10001;
+pub const OTX_INTENT_OFFERED_AMOUNT: u32 = 0x10002;
+pub const OTX_INTENT_REQUESTED_ASSET: u32 = 0x10003;
+pub const OTX_INTENT_REQUESTED_AMOUNT: u32 = 0x10004;
+pub const OTX_INTENT_MIN_RECEIVE: u32 = 0x10005;
+pub const OTX_INTENT_FEE_BUDGET: u32 = 0x10006;
+pub const OTX_INTENT_ALLOW_PARTIAL_FILL: u32 = 0x10007;
+
+/// Partial Fill Meta Keys
+pub const OTX_META_PARENT_ID: u32 = 0x10008;
@@ -0,0 +1,455 @@
+use super::constant::basic_keys::OTX_META_EXPIRE_TIMESTAMP;
+use super::constant::extra_keys::{
+    OTX_INTENT_ALLOW_PARTIAL_FILL, OTX_INTENT_FEE_BUDGET, OTX_INTENT_MIN_RECEIVE,
+    OTX_INTENT_OFFERED_AMOUNT, OTX_INTENT_OFFERED_ASSET, OTX_INTENT_REQUESTED_AMOUNT,
+    OTX_INTENT_REQUESTED_ASSET, OTX_META_PARENT_ID,
+};
+use super::opentx::to_kv_map;
+use super::{OpenTransaction, OtxKeyPair};
+use crate::error::OtxFormatError;
+use crate::types::OtxHash;
+
+use ckb_jsonrpc_types::{JsonBytes, Script};
+use ckb_types::{packed, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+
+const INTENT_KEYS: &[u32] = &[
+    OTX_INTENT_OFFERED_ASSET,
+    OTX_INTENT_OFFERED_AMOUNT,
+    OTX_INTENT_REQUESTED_ASSET,
+    OTX_INTENT_REQUESTED_AMOUNT,
+    OTX_INTENT_MIN_RECEIVE,
+    OTX_INTENT_FEE_BUDGET,
+    OTX_INTENT_ALLOW_PARTIAL_FILL,
+];
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum IntentAsset {
+    /// The capacity, in shannons.
+    Ckb,
+    /// The udt with the type script, the amount is in its smallest unit.
+    Udt(Script),
+}
+
+impl IntentAsset {
+    pub fn type_script(&self) -> Option<&Script> {
+        match self {
+            IntentAsset::Ckb => None,
+            IntentAsset::Udt(script) => Some(script),
+        }
+    }
+
+    // empty for ckb, or the molecule encoded type script
+    fn to_bytes(&self) -> JsonBytes {
+        match self {
+            IntentAsset::Ckb => JsonBytes::default(),
+            IntentAsset::Udt(script) => {
+                JsonBytes::from_bytes(packed::Script::from(script.clone()).as_bytes())
+            }
+        }
+    }
+
+    fn from_bytes(bytes: &JsonBytes) -> Result<Self, OtxFormatError> {
+        if bytes.as_bytes().is_empty() {
+            return Ok(IntentAsset::Ckb);
+        }
+        let script = packed::Script::from_slice(bytes.as_bytes())
+            .map_err(|e| OtxFormatError::OtxMapParseFailed(e.to_string()))?;
+        Ok(IntentAsset::Udt(script.into()))
+    }
+}
+
+/// What the otx is for: give `offered_amount` of `offered_asset`, and want
+/// `requested_amount` of `requested_asset`. It is kept in the meta map, so
+/// that a matcher does not have to work it out from the inputs and outputs.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OtxIntent {
+    pub offered_asset: IntentAsset,
+    pub offered_amount: u128,
+    pub requested_asset: IntentAsset,
+    pub requested_amount: u128,
+    /// The least of the requested asset accepted, `requested_amount` if `None`.
+    pub min_receive: Option<u128>,
+    /// The most capacity in shannons paid for the fee.
+    pub fee_budget: Option<u64>,
+    /// Kept as `OTX_META_EXPIRE_TIMESTAMP`, which the pool already honors.
+    pub expire_timestamp: Option<u64>,
+    pub allow_partial_fill: bool,
+}
+
+impl OtxIntent {
+    pub fn new(
+        offered_asset: IntentAsset,
+        offered_amount: u128,
+        requested_asset: IntentAsset,
+        requested_amount: u128,
+    ) -> Self {
+        OtxIntent {
+            offered_asset,
+            offered_amount,
+            requested_asset,
+            requested_amount,
+            min_receive: None,
+            fee_budget: None,
+            expire_timestamp: None,
+            allow_partial_fill: false,
+        }
+    }
+
+    pub fn with_min_receive(mut self, amount: u128) -> Self {
+        self.min_receive = Some(amount);
+        self
+    }
+
+    pub fn with_fee_budget(mut self, shannons: u64) -> Self {
+        self.fee_budget = Some(shannons);
+        self
+    }
+
+    pub fn with_expire_timestamp(mut self, timestamp: u64) -> Self {
+        self.expire_timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn with_partial_fill(mut self, allow: bool) -> Self {
+        self.allow_partial_fill = allow;
+        self
+    }
+
+    /// The least of the requested asset accepted.
+    pub fn get_min_receive(&self) -> u128 {
+        self.min_receive.unwrap_or(self.requested_amount)
+    }
+
+    /// Split the order at `filled_amount` of the offered asset. The amounts
+    /// of the fill are rounded up, so the rest never asks for more than the
+    /// order as a whole.
+    pub fn fill(&self, filled_amount: u128) -> Result<PartialFill, OtxFormatError> {
+        if filled_amount == 0 || filled_amount > self.offered_amount {
+            return Err(OtxFormatError::InvalidPartialFill(format!(
+                "fill {} of offered {}",
+                filled_amount, self.offered_amount
+            )));
+        }
+        if filled_amount < self.offered_amount && !self.allow_partial_fill {
+            return Err(OtxFormatError::InvalidPartialFill(
+                "the order is not divisible".to_string(),
+            ));
+        }
+        let pro_rata = |amount: u128| mul_div_ceil(amount, filled_amount, self.offered_amount);
+        let received_amount = pro_rata(self.requested_amount)?;
+        let residual = if filled_amount == self.offered_amount {
+            None
+        } else {
+            let min_receive = match self.min_receive {
+                Some(min_receive) => Some(min_receive - pro_rata(min_receive)?),
+                None => None,
+            };
+            let fee_budget = match self.fee_budget {
+                Some(fee_budget) => {
+                    let filled_fee = pro_rata(fee_budget as u128)? as u64;
+                    Some(fee_budget - filled_fee)
+                }
+                None => None,
+            };
+            Some(OtxIntent {
+                offered_amount: self.offered_amount - filled_amount,
+                requested_amount: self.requested_amount - received_amount,
+                min_receive,
+                fee_budget,
+                ..self.clone()
+            })
+        };
+        Ok(PartialFill {
+            filled_amount,
+            received_amount,
+            residual,
+        })
+    }
+
+    fn to_key_pairs(&self) -> Vec<OtxKeyPair> {
+        let mut pairs = vec![
+            key_pair(OTX_INTENT_OFFERED_ASSET, self.offered_asset.to_bytes()),
+            key_pair(OTX_INTENT_OFFERED_AMOUNT, pack_u128(self.offered_amount)),
+            key_pair(OTX_INTENT_REQUESTED_ASSET, self.requested_asset.to_bytes()),
+            key_pair(
+                OTX_INTENT_REQUESTED_AMOUNT,
+                pack_u128(self.requested_amount),
+            ),
+        ];
+        if let Some(min_receive) = self.min_receive {
+            pairs.push(key_pair(OTX_INTENT_MIN_RECEIVE, pack_u128(min_receive)));
+        }
+        if let Some(fee_budget) = self.fee_budget {
+            let fee_budget = JsonBytes::from_bytes(fee_budget.pack().as_bytes());
+            pairs.push(key_pair(OTX_INTENT_FEE_BUDGET, fee_budget));
+        }
+        if self.allow_partial_fill {
+            let allow = JsonBytes::from_bytes(packed::Byte::new(1).as_bytes());
+            pairs.push(key_pair(OTX_INTENT_ALLOW_PARTIAL_FILL, allow));
+        }
+        pairs
+    }
+}
+
+/// One fill of a divisible order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartialFill {
+    /// Of the offered asset, given away in this fill.
+    pub filled_amount: u128,
+    /// Of the requested asset, what the owner receives in this fill.
+    pub received_amount: u128,
+    /// The intent of the rest of the order, `None` if the order is filled up.
+    pub residual: Option<OtxIntent>,
+}
+
+impl OpenTransaction {
+    /// Link a residual otx to the otx it is the rest of.
+    pub fn set_parent_id(&mut self, parent_id: &OtxHash) {
+        let mut meta: Vec<OtxKeyPair> = self
+            .meta
+            .clone()
+            .into_iter()
+            .filter(|pair| pair.key_type() != OTX_META_PARENT_ID)
+            .collect();
+        meta.push(key_pair(
+            OTX_META_PARENT_ID,
+            JsonBytes::from_bytes(parent_id.pack().as_bytes()),
+        ));
+        self.meta = meta.into();
+    }
+
+    /// Returns `None` if the otx is not the residual of another one.
+    pub fn get_parent_id(&self) -> Result<Option<OtxHash>, OtxFormatError> {
+        let kv_map = to_kv_map(&self.meta)?;
+        match kv_map.get(&OTX_META_PARENT_ID) {
+            Some((_, value)) => {
+                let parent_id = packed::Byte32::from_slice(value.as_bytes())
+                    .map_err(|e| OtxFormatError::OtxMapParseFailed(e.to_string()))?;
+                Ok(Some(parent_id.unpack()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Replace the intent in the meta map. The expire timestamp is part of the
+    /// intent, it is removed if the intent has none.
+    pub fn set_intent(&mut self, intent: &OtxIntent) {
+        let mut meta: Vec<OtxKeyPair> = self
+            .meta
+            .clone()
+            .into_iter()
+            .filter(|pair| {
+                !INTENT_KEYS.contains(&pair.key_type())
+                    && pair.key_type() != OTX_META_EXPIRE_TIMESTAMP
+            })
+            .collect();
+        meta.extend(intent.to_key_pairs());
+        self.meta = meta.into();
+        if let Some(timestamp) = intent.expire_timestamp {
+            self.set_expire_timestamp(timestamp);
+        }
+    }
+
+    /// Returns `None` if the otx declares no intent.
+    pub fn get_intent(&self) -> Result<Option<OtxIntent>, OtxFormatError> {
+        let kv_map = to_kv_map(&self.meta)?;
+        if !INTENT_KEYS.iter().any(|key| kv_map.contains_key(key)) {
+            return Ok(None);
+        }
+
+        let min_receive = match kv_map.get(&OTX_INTENT_MIN_RECEIVE) {
+            Some((_, value)) => Some(unpack_u128(value)?),
+            None => None,
+        };
+        let fee_budget = match kv_map.get(&OTX_INTENT_FEE_BUDGET) {
+            Some((_, value)) => Some(
+                packed::Uint64::from_slice(value.as_bytes())
+                    .map_err(|e| OtxFormatError::OtxMapParseFailed(e.to_string()))?
+                    .unpack(),
+            ),
+            None => None,
+        };
+        let allow_partial_fill = match kv_map.get(&OTX_INTENT_ALLOW_PARTIAL_FILL) {
+            Some((_, value)) => {
+                let allow: u8 = packed::Byte::from_slice(value.as_bytes())
+                    .map_err(|e| OtxFormatError::OtxMapParseFailed(e.to_string()))?
+                    .into();
+                match allow {
+                    0 => false,
+                    1 => true,
+                    _ => {
+                        return Err(OtxFormatError::OtxMapParseFailed(
+                            "allow partial fill".to_string(),
+                        ))
+                    }
+                }
+            }
+            None => false,
+        };
+
+        Ok(Some(OtxIntent {
+            offered_asset: IntentAsset::from_bytes(get_value(&kv_map, OTX_INTENT_OFFERED_ASSET)?)?,
+            offered_amount: unpack_u128(get_value(&kv_map, OTX_INTENT_OFFERED_AMOUNT)?)?,
+            requested_asset: IntentAsset::from_bytes(get_value(
+                &kv_map,
+                OTX_INTENT_REQUESTED_ASSET,
+            )?)?,
+            requested_amount: unpack_u128(get_value(&kv_map, OTX_INTENT_REQUESTED_AMOUNT)?)?,
+            min_receive,
+            fee_budget,
+            expire_timestamp: self.get_expire_timestamp()?,
+            allow_partial_fill,
+        }))
+    }
+}
+
+// rounds up, fails if the product overflows
+fn mul_div_ceil(amount: u128, numerator: u128, denominator: u128) -> Result<u128, OtxFormatError> {
+    let product = amount
+        .checked_mul(numerator)
+        .ok_or_else(|| OtxFormatError::InvalidPartialFill("amount overflow".to_string()))?;
+    Ok(product / denominator + u128::from(product % denominator != 0))
+}
+
+fn key_pair(key_type: u32, value_data: JsonBytes) -> OtxKeyPair {
+    OtxKeyPair::new(key_type.into(), None, value_data)
+}
+
+fn get_value(
+    kv_map: &HashMap<u32, (Option<JsonBytes>, JsonBytes)>,
+    key_type: u32,
+) -> Result<&JsonBytes, OtxFormatError> {
+    kv_map
+        .get(&key_type)
+        .map(|(_, value)| value)
+        .ok_or_else(|| OtxFormatError::OtxMapParseMissingField(key_type.to_string()))
+}
+
+fn pack_u128(amount: u128) -> JsonBytes {
+    JsonBytes::from_bytes(amount.pack().as_bytes())
+}
+
+fn unpack_u128(value: &JsonBytes) -> Result<u128, OtxFormatError> {
+    Ok(packed::Uint128::from_slice(value.as_bytes())
+        .map_err(|e| OtxFormatError::OtxMapParseFailed(e.to_string()))?
+        .unpack())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::types::packed::OpenTransaction as PackedOpenTransaction;
+
+    use ckb_jsonrpc_types::ScriptHashType;
+    use ckb_types::H256;
+
+    fn udt() -> IntentAsset {
+        IntentAsset::Udt(Script {
+            code_hash: H256::default(),
+            hash_type: ScriptHashType::Type,
+            args: JsonBytes::from_vec(vec![1; 32]),
+        })
+    }
+
+    #[test]
+    fn test_intent_round_trip() {
+        let intent = OtxIntent::new(udt(), 100, IntentAsset::Ckb, 9_000_000_000)
+            .with_min_receive(8_900_000_000)
+            .with_fee_budget(100_000)
+            .with_expire_timestamp(1_700_000_000)
+            .with_partial_fill(true);
+        let mut otx = OpenTransaction::default();
+        otx.set_intent(&intent);
+        assert_eq!(otx.get_intent().unwrap(), Some(intent.clone()));
+        assert_eq!(otx.get_expire_timestamp().unwrap(), Some(1_700_000_000));
+
+        // survives the molecule encoding
+        let otx: OpenTransaction = PackedOpenTransaction::from(otx).into();
+        assert_eq!(otx.get_intent().unwrap(), Some(intent));
+    }
+
+    #[test]
+    fn test_set_intent_replaces() {
+        let mut otx = OpenTransaction::default();
+        assert_eq!(otx.get_intent().unwrap(), None);
+
+        otx.set_intent(&OtxIntent::new(udt(), 100, IntentAsset::Ckb, 90).with_partial_fill(true));
+        let intent = OtxIntent::new(IntentAsset::Ckb, 90, udt(), 100);
+        otx.set_intent(&intent);
+        assert_eq!(otx.get_intent().unwrap(), Some(intent));
+    }
+
+    #[test]
+    fn test_set_intent_removes_expire_timestamp() {
+        let mut otx = OpenTransaction::default();
+        otx.set_intent(
+            &OtxIntent::new(udt(), 100, IntentAsset::Ckb, 90).with_expire_timestamp(1_700_000_000),
+        );
+        assert_eq!(otx.get_expire_timestamp().unwrap(), Some(1_700_000_000));
+
+        let intent = OtxIntent::new(udt(), 100, IntentAsset::Ckb, 90);
+        otx.set_intent(&intent);
+        assert_eq!(otx.get_expire_timestamp().unwrap(), None);
+        assert_eq!(otx.get_intent().unwrap(), Some(intent));
+    }
+
+    #[test]
+    fn test_partial_fill() {
+        let intent = OtxIntent::new(udt(), 1000, IntentAsset::Ckb, 900)
+            .with_min_receive(800)
+            .with_partial_fill(true);
+
+        let fill = intent.fill(250).unwrap();
+        assert_eq!(fill.filled_amount, 250);
+        assert_eq!(fill.received_amount, 225);
+        let residual = fill.residual.unwrap();
+        assert_eq!(residual.offered_amount, 750);
+        assert_eq!(residual.requested_amount, 675);
+        assert_eq!(residual.min_receive, Some(600));
+
+        // rounds up in favor of the owner
+        let fill = OtxIntent::new(udt(), 3, IntentAsset::Ckb, 10)
+            .with_partial_fill(true)
+            .fill(1)
+            .unwrap();
+        assert_eq!(fill.received_amount, 4);
+        assert_eq!(fill.residual.unwrap().requested_amount, 6);
+
+        assert_eq!(intent.fill(1000).unwrap().residual, None);
+        assert!(intent.fill(1001).is_err());
+        assert!(intent.with_partial_fill(false).fill(250).is_err());
+    }
+
+    #[test]
+    fn test_parent_id_round_trip() {
+        let mut otx = OpenTransaction::default();
+        assert_eq!(otx.get_parent_id().unwrap(), None);
+        let parent_id = OtxHash::from([1u8; 32]);
+        otx.set_parent_id(&parent_id);
+        otx.set_parent_id(&parent_id);
+        assert_eq!(otx.get_parent_id().unwrap(), Some(parent_id));
+    }
+
+    #[test]
+    fn test_intent_missing_field() {
+        let mut otx = OpenTransaction::default();
+        otx.set_intent(&OtxIntent::new(udt(), 100, IntentAsset::Ckb, 90));
+        let meta: Vec<OtxKeyPair> = otx
+            .meta
+            .clone()
+            .into_iter()
+            .filter(|pair| pair.key_type() != OTX_INTENT_REQUESTED_AMOUNT)
+            .collect();
+        otx.meta = meta.into();
+        assert_eq!(
+            otx.get_intent(),
+            Err(OtxFormatError::OtxMapParseMissingField(
+                OTX_INTENT_REQUESTED_AMOUNT.to_string()
+            ))
+        );
+    }
+}
@@ -1,5 +1,7 @@
 pub mod constant;
+mod intent;
 mod opentx;
 pub mod tx_view;
 
+pub use intent::*;
 pub use opentx::*;
@@ -1,13 +1,15 @@
 use super::constant::basic_keys::{
     OTX_CELL_DEP_OUTPOINT_INDEX, OTX_CELL_DEP_OUTPOINT_TX_HASH, OTX_CELL_DEP_TYPE,
     OTX_HEADER_DEP_HASH, OTX_INPUT_OUTPOINT_INDEX, OTX_INPUT_OUTPOINT_TX_HASH, OTX_INPUT_SINCE,
-    OTX_OUTPUT_CAPACITY, OTX_OUTPUT_DATA, OTX_OUTPUT_LOCK_ARGS, OTX_OUTPUT_LOCK_CODE_HASH,
-    OTX_OUTPUT_LOCK_HASH_TYPE, OTX_OUTPUT_TYPE_ARGS, OTX_OUTPUT_TYPE_CODE_HASH,
-    OTX_OUTPUT_TYPE_HASH_TYPE, OTX_WITNESS_RAW,
+    OTX_META_EXPIRE_BLOCK_NUMBER, OTX_META_EXPIRE_TIMESTAMP, OTX_OUTPUT_CAPACITY, OTX_OUTPUT_DATA,
+    OTX_OUTPUT_LOCK_ARGS, OTX_OUTPUT_LOCK_CODE_HASH, OTX_OUTPUT_LOCK_HASH_TYPE,
+    OTX_OUTPUT_TYPE_ARGS, OTX_OUTPUT_TYPE_CODE_HASH, OTX_OUTPUT_TYPE_HASH_TYPE, OTX_WITNESS_RAW,
 };
 use crate::error::OtxFormatError;
 use crate::types::packed::{self, OpenTransactionBuilder, OtxMapBuilder, OtxMapVecBuilder};
+use crate::types::OtxHash;
 
+use ckb_hash::blake2b_256;
 use ckb_jsonrpc_types::{CellDep, CellInput, CellOutput, DepType, JsonBytes, Script, Uint32};
 use ckb_types::bytes::Bytes;
 use ckb_types::core::{self, ScriptHashType};
@@ -37,6 +39,18 @@ impl OtxKeyPair {
             value_data,
         }
     }
+
+    pub fn key_type(&self) -> u32 {
+        self.key_type.value()
+    }
+
+    pub fn key_data(&self) -> Option<&JsonBytes> {
+        self.key_data.as_ref()
+    }
+
+    pub fn value_data(&self) -> &JsonBytes {
+        &self.value_data
+    }
 }
 
 impl From<OtxKeyPair> for packed::OtxKeyPair {
@@ -70,6 +84,24 @@ impl IntoIterator for OtxMapVec {
     }
 }
 
+impl OtxMapVec {
+    pub fn iter(&self) -> Iter<OtxMap> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn normalize(&self) -> OtxMapVec {
+        OtxMapVec(self.0.iter().map(OtxMap::normalize).collect())
+    }
+}
+
 impl From<Vec<OtxMap>> for OtxMapVec {
     fn from(vec: Vec<OtxMap>) -> Self {
         OtxMapVec(vec)
@@ -117,6 +149,69 @@ impl OpenTransaction {
             outputs,
         }
     }
+
+    /// Returns a copy with the key pairs of every map in canonical order.
+    /// The order of maps within a map vector is meaningful and is kept.
+    pub fn normalize(&self) -> OpenTransaction {
+        OpenTransaction {
+            meta: self.meta.normalize(),
+            cell_deps: self.cell_deps.normalize(),
+            header_deps: self.header_deps.normalize(),
+            inputs: self.inputs.normalize(),
+            witnesses: self.witnesses.normalize(),
+            outputs: self.outputs.normalize(),
+        }
+    }
+
+    /// The unix timestamp in seconds after which the otx should be dropped.
+    pub fn get_expire_timestamp(&self) -> Result<Option<u64>, OtxFormatError> {
+        self.get_meta_u64(OTX_META_EXPIRE_TIMESTAMP)
+    }
+
+    pub fn set_expire_timestamp(&mut self, timestamp: u64) {
+        self.set_meta_u64(OTX_META_EXPIRE_TIMESTAMP, timestamp)
+    }
+
+    /// The block number from which on the otx should be dropped.
+    pub fn get_expire_block_number(&self) -> Result<Option<u64>, OtxFormatError> {
+        self.get_meta_u64(OTX_META_EXPIRE_BLOCK_NUMBER)
+    }
+
+    pub fn set_expire_block_number(&mut self, block_number: u64) {
+        self.set_meta_u64(OTX_META_EXPIRE_BLOCK_NUMBER, block_number)
+    }
+
+    fn get_meta_u64(&self, key_type: u32) -> Result<Option<u64>, OtxFormatError> {
+        let pair = match self
+            .meta
+            .iter()
+            .find(|pair| pair.key_type.value() == key_type)
+        {
+            Some(pair) => pair,
+            None => return Ok(None),
+        };
+        let value: u64 = ckb_types::packed::Uint64::from_slice(pair.value_data.as_bytes())
+            .map_err(|e| OtxFormatError::OtxMapParseFailed(e.to_string()))?
+            .unpack();
+        Ok(Some(value))
+    }
+
+    fn set_meta_u64(&mut self, key_type: u32, value: u64) {
+        let pair = OtxKeyPair::new(
+            key_type.into(),
+            None,
+            JsonBytes::from_bytes(value.pack().as_bytes()),
+        );
+        self.meta.0.retain(|pair| pair.key_type.value() != key_type);
+        self.meta.0.push(pair);
+    }
+
+    /// Calculate the blake2b-256 hash over the normalized molecule encoding,
+    /// so that the same logical otx always gets the same hash.
+    pub fn get_otx_hash(&self) -> OtxHash {
+        let otx: packed::OpenTransaction = self.normalize().into();
+        blake2b_256(otx.as_slice()).into()
+    }
 }
 
 impl From<OpenTransaction> for packed::OpenTransaction {
@@ -157,17 +252,39 @@ impl IntoIterator for OtxMap {
 }
 
 impl OtxMap {
-    fn iter(&self) -> Iter<OtxKeyPair> {
+    pub fn iter(&self) -> Iter<OtxKeyPair> {
         self.0.iter()
     }
 
-    fn get(&self, index: usize) -> Option<&OtxKeyPair> {
+    pub fn get(&self, index: usize) -> Option<&OtxKeyPair> {
         self.0.get(index)
     }
 
-    fn len(&self) -> usize {
+    pub fn len(&self) -> usize {
         self.0.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn normalize(&self) -> OtxMap {
+        let mut pairs = self.0.clone();
+        pairs.sort_by(|a, b| {
+            let key_a = (
+                a.key_type.value(),
+                a.key_data.as_ref().map(|data| data.as_bytes()),
+                a.value_data.as_bytes(),
+            );
+            let key_b = (
+                b.key_type.value(),
+                b.key_data.as_ref().map(|data| data.as_bytes()),
+                b.value_data.as_bytes(),
+            );
+            key_a.cmp(&key_b)
+        });
+        OtxMap(pairs)
+    }
 }
 
 impl From<Vec<OtxKeyPair>> for OtxMap {
@@ -546,7 +663,7 @@ fn has_unique_elements(iter: &OtxMap) -> bool {
     iter.iter().all(|pair| uniq.insert(pair.key_type))
 }
 
-fn to_kv_map(
+pub(crate) fn to_kv_map(
     iter: &OtxMap,
 ) -> Result<HashMap<u32, (Option<JsonBytes>, JsonBytes)>, OtxFormatError> {
     let mut map = HashMap::new();
@@ -1,5 +1,10 @@
 pub use crate::generated::packed;
 
+use ckb_types::H256;
+
+/// The blake2b-256 hash of a normalized open transaction.
+pub type OtxHash = H256;
+
 #[cfg(test)]
 mod test {
     use crate::types::packed::OpenTransaction;
@@ -24,4 +29,30 @@ mod test {
 
         assert_eq!(opentx.as_bytes(), opentx_rebuild.as_bytes());
     }
+
+    #[test]
+    fn test_otx_hash_ignores_keypair_order() {
+        use crate::jsonrpc_types::{OpenTransaction as JsonOpenTransaction, OtxKeyPair, OtxMap};
+
+        let key_pair = |key_type: u32, value: &[u8]| {
+            OtxKeyPair::new(key_type.into(), None, JsonBytes::from_vec(value.to_vec()))
+        };
+        let input: OtxMap = vec![key_pair(0x06, &[1; 32]), key_pair(0x07, &[0; 4])].into();
+        let input_reordered: OtxMap =
+            vec![key_pair(0x07, &[0; 4]), key_pair(0x06, &[1; 32])].into();
+        let otx = |input: OtxMap| {
+            JsonOpenTransaction::new(
+                Default::default(),
+                Default::default(),
+                Default::default(),
+                vec![input].into(),
+                Default::default(),
+                Default::default(),
+            )
+        };
+
+        let hash = otx(input.clone()).get_otx_hash();
+        assert_eq!(hash, otx(input_reordered).get_otx_hash());
+        assert_ne!(hash, JsonOpenTransaction::default().get_otx_hash());
+    }
 }
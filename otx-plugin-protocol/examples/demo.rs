@@ -1,43 +1,32 @@
 /// NOTE: this example is for plugin integration tests
-use otx_plugin_protocol::{MessageFromHost, MessageFromPlugin, PluginInfo};
+use otx_plugin_protocol::framing::Framing;
+use otx_plugin_protocol::runner::{init_logger, Host, PluginHandler, PluginRunner};
+use otx_plugin_protocol::{EventType, Id, Permission, PluginInfo, PROTOCOL_VERSION};
 
-use std::io::{self, Write};
+struct Demo;
 
-fn main() {
-    loop {
-        let mut line = String::new();
-        match io::stdin().read_line(&mut line) {
-            Ok(0) => {
-                break;
-            }
-            Ok(_) => {
-                let (id, msg): (u64, MessageFromHost) = serde_json::from_str(&line).unwrap();
-                if let Some(msg) = handle(msg) {
-                    let response_string =
-                        format!("{}\n", serde_json::to_string(&(id, msg)).unwrap());
-                    io::stdout().write_all(response_string.as_bytes()).unwrap();
-                    io::stdout().flush().unwrap();
-                }
-            }
-            Err(_err) => {}
+impl PluginHandler for Demo {
+    fn info(&self) -> PluginInfo {
+        PluginInfo {
+            name: String::from("plugin demo"),
+            description: String::from("It's a plugin demo"),
+            version: 0.to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            subscriptions: vec![EventType::NewInterval],
+            permissions: vec![Permission::DiscardOtx],
+            framing: Framing::default(),
         }
     }
-}
 
-fn handle(msg: MessageFromHost) -> Option<MessageFromPlugin> {
-    match msg {
-        MessageFromHost::GetPluginInfo => {
-            let info = PluginInfo {
-                name: String::from("plugin demo"),
-                description: String::from("It's a plugin demo"),
-                version: 0.to_string(),
-            };
-            Some(MessageFromPlugin::PluginInfo(info))
-        }
-        MessageFromHost::NewInterval => {
-            log::info!("New interval");
-            Some(MessageFromPlugin::DiscardOtx(0))
+    fn on_interval(&mut self, host: &Host) {
+        log::info!("New interval");
+        if let Err(err) = host.discard_otx(Id::default()) {
+            log::info!("discard otx: {}", err);
         }
-        _ => None,
     }
 }
+
+fn main() {
+    init_logger(None);
+    PluginRunner::new(Demo).run();
+}
@@ -0,0 +1,289 @@
+//! The wire formats of the stdio protocol.
+//!
+//! Every plugin process starts with json lines. The host sends `GetPluginInfo`
+//! first and waits for the answer, if the `PluginInfo` asks for
+//! `Framing::LengthPrefixed`, both sides switch to it right after the answer.
+//!
+//! A length-prefixed frame is a `u32` little-endian length followed by the
+//! payload: the `u64` little-endian message id, a tag byte, and the body. The
+//! messages carrying an otx are encoded as the molecule
+//! `packed::OpenTransaction` bytes, the others as json.
+
+use crate::{Id, MessageFromHost, MessageFromPlugin};
+
+use otx_format::{jsonrpc_types::OpenTransaction, types::packed};
+
+use ckb_types::{prelude::*, H256};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use std::io::{self, BufRead, Write};
+
+/// The largest frame accepted, a frame beyond it is treated as corrupted.
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+const TAG_JSON: u8 = 0;
+const TAG_NEW_OTX: u8 = 1;
+const TAG_MODIFY_OTX: u8 = 2;
+const TAG_SEND_CKB_TX: u8 = 3;
+const TAG_PROPOSED_TX: u8 = 4;
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Framing {
+    #[default]
+    JsonLines,
+    LengthPrefixed,
+}
+
+impl Framing {
+    pub fn write_message<W: Write, M: Message>(
+        &self,
+        writer: &mut W,
+        id: u64,
+        msg: &M,
+    ) -> io::Result<()> {
+        match self {
+            Framing::JsonLines => {
+                let mut line = serde_json::to_vec(&(id, msg)).map_err(invalid_data)?;
+                line.push(b'\n');
+                writer.write_all(&line)?;
+            }
+            Framing::LengthPrefixed => {
+                let mut payload = id.to_le_bytes().to_vec();
+                msg.encode(&mut payload)?;
+                if payload.len() > MAX_FRAME_SIZE {
+                    return Err(invalid_data(format!(
+                        "frame of {} bytes exceeds the limit",
+                        payload.len()
+                    )));
+                }
+                writer.write_all(&(payload.len() as u32).to_le_bytes())?;
+                writer.write_all(&payload)?;
+            }
+        }
+        writer.flush()
+    }
+
+    /// Returns `None` at EOF. A message which can not be decoded is an
+    /// `InvalidData` error, the reader may go on with the next message.
+    pub fn read_message<R: BufRead, M: Message>(
+        &self,
+        reader: &mut R,
+    ) -> io::Result<Option<(u64, M)>> {
+        match self {
+            Framing::JsonLines => {
+                let mut line = String::new();
+                if reader.read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+                serde_json::from_str(&line)
+                    .map(Some)
+                    .map_err(|err| invalid_data(format!("{}, message: {}", err, line.trim())))
+            }
+            Framing::LengthPrefixed => {
+                let mut len = [0u8; 4];
+                match reader.read_exact(&mut len) {
+                    Ok(()) => {}
+                    Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                    Err(err) => return Err(err),
+                }
+                let len = u32::from_le_bytes(len) as usize;
+                if len > MAX_FRAME_SIZE || len < 9 {
+                    // the stream can not be resynchronized
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("invalid frame length {}", len),
+                    ));
+                }
+                let mut payload = vec![0u8; len];
+                reader.read_exact(&mut payload)?;
+                let mut id = [0u8; 8];
+                id.copy_from_slice(&payload[..8]);
+                let msg = M::decode(&payload[8..])?;
+                Ok(Some((u64::from_le_bytes(id), msg)))
+            }
+        }
+    }
+}
+
+/// The binary encoding of a message, used by `Framing::LengthPrefixed`.
+pub trait Message: Serialize + DeserializeOwned {
+    fn encode(&self, buf: &mut Vec<u8>) -> io::Result<()>;
+
+    fn decode(bytes: &[u8]) -> io::Result<Self>;
+}
+
+impl Message for MessageFromHost {
+    fn encode(&self, buf: &mut Vec<u8>) -> io::Result<()> {
+        match self {
+            MessageFromHost::NewOtx((id, otx)) => {
+                buf.push(TAG_NEW_OTX);
+                encode_id(buf, id);
+                encode_otx(buf, otx);
+                Ok(())
+            }
+            msg => encode_json(buf, msg),
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> io::Result<Self> {
+        let (tag, body) = split_tag(bytes)?;
+        match tag {
+            TAG_JSON => decode_json(body),
+            TAG_NEW_OTX => {
+                let (id, otx) = decode_id(body)?;
+                Ok(MessageFromHost::NewOtx((id, decode_otx(otx)?)))
+            }
+            tag => Err(invalid_data(format!("unknown message tag {}", tag))),
+        }
+    }
+}
+
+impl Message for MessageFromPlugin {
+    fn encode(&self, buf: &mut Vec<u8>) -> io::Result<()> {
+        match self {
+            MessageFromPlugin::NewOtx(otx) => {
+                buf.push(TAG_NEW_OTX);
+                encode_otx(buf, otx);
+            }
+            MessageFromPlugin::ModifyOtx((id, otx)) => {
+                buf.push(TAG_MODIFY_OTX);
+                encode_id(buf, id);
+                encode_otx(buf, otx);
+            }
+            MessageFromPlugin::SendCkbTx(otx) => {
+                buf.push(TAG_SEND_CKB_TX);
+                encode_otx(buf, otx);
+            }
+            MessageFromPlugin::ProposedTx(Some(otx)) => {
+                buf.push(TAG_PROPOSED_TX);
+                encode_otx(buf, otx);
+            }
+            msg => return encode_json(buf, msg),
+        }
+        Ok(())
+    }
+
+    fn decode(bytes: &[u8]) -> io::Result<Self> {
+        let (tag, body) = split_tag(bytes)?;
+        match tag {
+            TAG_JSON => decode_json(body),
+            TAG_NEW_OTX => Ok(MessageFromPlugin::NewOtx(decode_otx(body)?)),
+            TAG_MODIFY_OTX => {
+                let (id, otx) = decode_id(body)?;
+                Ok(MessageFromPlugin::ModifyOtx((id, decode_otx(otx)?)))
+            }
+            TAG_SEND_CKB_TX => Ok(MessageFromPlugin::SendCkbTx(decode_otx(body)?)),
+            TAG_PROPOSED_TX => Ok(MessageFromPlugin::ProposedTx(Some(decode_otx(body)?))),
+            tag => Err(invalid_data(format!("unknown message tag {}", tag))),
+        }
+    }
+}
+
+fn split_tag(bytes: &[u8]) -> io::Result<(u8, &[u8])> {
+    bytes
+        .split_first()
+        .map(|(tag, body)| (*tag, body))
+        .ok_or_else(|| invalid_data("empty message"))
+}
+
+fn encode_json<M: Serialize>(buf: &mut Vec<u8>, msg: &M) -> io::Result<()> {
+    buf.push(TAG_JSON);
+    serde_json::to_writer(buf, msg).map_err(invalid_data)
+}
+
+fn decode_json<M: DeserializeOwned>(body: &[u8]) -> io::Result<M> {
+    serde_json::from_slice(body).map_err(invalid_data)
+}
+
+fn encode_id(buf: &mut Vec<u8>, id: &Id) {
+    buf.extend_from_slice(id.as_bytes());
+}
+
+fn decode_id(body: &[u8]) -> io::Result<(Id, &[u8])> {
+    if body.len() < 32 {
+        return Err(invalid_data("truncated otx id"));
+    }
+    let (id, rest) = body.split_at(32);
+    let id = H256::from_slice(id).map_err(|err| invalid_data(err.to_string()))?;
+    Ok((id, rest))
+}
+
+fn encode_otx(buf: &mut Vec<u8>, otx: &OpenTransaction) {
+    let otx: packed::OpenTransaction = otx.clone().into();
+    buf.extend_from_slice(otx.as_slice());
+}
+
+fn decode_otx(body: &[u8]) -> io::Result<OpenTransaction> {
+    packed::OpenTransaction::from_slice(body)
+        .map(Into::into)
+        .map_err(|err| invalid_data(err.to_string()))
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    fn round_trip<M: Message>(framing: Framing, id: u64, msg: &M) -> (u64, M) {
+        let mut buf = vec![];
+        framing.write_message(&mut buf, id, msg).unwrap();
+        let mut reader = Cursor::new(buf);
+        let decoded = framing.read_message(&mut reader).unwrap().unwrap();
+        assert!(framing.read_message::<_, M>(&mut reader).unwrap().is_none());
+        decoded
+    }
+
+    #[test]
+    fn test_length_prefixed_otx_payload() {
+        let otx: OpenTransaction = packed::OpenTransaction::default().into();
+        let otx_id = otx.get_otx_hash();
+
+        let (id, msg) = round_trip(
+            Framing::LengthPrefixed,
+            3,
+            &MessageFromHost::NewOtx((otx_id.clone(), otx.clone())),
+        );
+        assert_eq!(id, 3);
+        match msg {
+            MessageFromHost::NewOtx((id, decoded)) => {
+                assert_eq!(id, otx_id);
+                assert_eq!(decoded, otx);
+            }
+            msg => panic!("unexpected message {:?}", msg),
+        }
+
+        let (_, msg) = round_trip(
+            Framing::LengthPrefixed,
+            4,
+            &MessageFromPlugin::ModifyOtx((otx_id.clone(), otx.clone())),
+        );
+        match msg {
+            MessageFromPlugin::ModifyOtx((id, decoded)) => {
+                assert_eq!(id, otx_id);
+                assert_eq!(decoded, otx);
+            }
+            msg => panic!("unexpected message {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn test_json_payload() {
+        for framing in [Framing::JsonLines, Framing::LengthPrefixed] {
+            let (id, msg) = round_trip(framing, 0, &MessageFromHost::DeleteOtx(H256::default()));
+            assert_eq!(id, 0);
+            assert!(matches!(msg, MessageFromHost::DeleteOtx(id) if id == H256::default()));
+        }
+    }
+
+    #[test]
+    fn test_invalid_frame_length() {
+        let mut reader = Cursor::new(u32::MAX.to_le_bytes().to_vec());
+        let result = Framing::LengthPrefixed.read_message::<_, MessageFromHost>(&mut reader);
+        assert!(result.is_err());
+    }
+}
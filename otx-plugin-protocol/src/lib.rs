@@ -1,8 +1,21 @@
-use otx_format::jsonrpc_types::OpenTransaction;
+pub mod framing;
+pub mod request;
+pub mod runner;
 
+use framing::Framing;
+
+use otx_format::{jsonrpc_types::OpenTransaction, types::OtxHash};
+
+use ckb_types::H256;
 use serde_derive::{Deserialize, Serialize};
 
-pub type Id = u64;
+pub type Id = OtxHash;
+
+/// The revision of the protocol spoken by this crate.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// The oldest revision the host still accepts. Plugins which do not report a
+/// protocol version are treated as revision 0.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
 
 pub enum MessageType {
     Request,
@@ -18,13 +31,18 @@ pub enum MessageFromHost {
     OtxPoolStart,
     OtxPoolStop,
     DeleteOtx(Id),
+    ConflictOtx((Id, Vec<Id>)),
 
     // Request
     GetPluginInfo,
+    GetPluginStatus,
+    GetProposedTx,
 
     // Response
     Ok,
     Error(String),
+    OtxId(Id),
+    TxHash(H256),
 }
 
 impl MessageFromHost {
@@ -34,8 +52,25 @@ impl MessageFromHost {
             | Self::NewInterval
             | Self::OtxPoolStart
             | Self::OtxPoolStop
-            | Self::DeleteOtx(_) => MessageType::Notify,
-            Self::GetPluginInfo | Self::Ok | Self::Error(_) => MessageType::Request,
+            | Self::DeleteOtx(_)
+            | Self::ConflictOtx(_) => MessageType::Notify,
+            Self::GetPluginInfo | Self::GetPluginStatus | Self::GetProposedTx => {
+                MessageType::Request
+            }
+            Self::Ok | Self::Error(_) | Self::OtxId(_) | Self::TxHash(_) => MessageType::Response,
+        }
+    }
+
+    /// Returns the event type of a notification.
+    pub fn get_event_type(&self) -> Option<EventType> {
+        match self {
+            Self::NewOtx(_) => Some(EventType::NewOtx),
+            Self::NewInterval => Some(EventType::NewInterval),
+            Self::OtxPoolStart => Some(EventType::OtxPoolStart),
+            Self::OtxPoolStop => Some(EventType::OtxPoolStop),
+            Self::DeleteOtx(_) => Some(EventType::DeleteOtx),
+            Self::ConflictOtx(_) => Some(EventType::ConflictOtx),
+            _ => None,
         }
     }
 }
@@ -46,6 +81,8 @@ pub enum MessageFromPlugin {
     Ok,
     Error(String),
     PluginInfo(PluginInfo),
+    PluginStatus(String),
+    ProposedTx(Option<OpenTransaction>),
 
     // Request
     NewOtx(OpenTransaction),
@@ -57,12 +94,47 @@ pub enum MessageFromPlugin {
 impl MessageFromPlugin {
     pub fn get_message_type(&self) -> MessageType {
         match self {
-            Self::Ok | Self::Error(_) | Self::PluginInfo(_) => MessageType::Response,
+            Self::Ok
+            | Self::Error(_)
+            | Self::PluginInfo(_)
+            | Self::PluginStatus(_)
+            | Self::ProposedTx(_) => MessageType::Response,
             Self::NewOtx(_) | Self::DiscardOtx(_) | Self::ModifyOtx(_) | Self::SendCkbTx(_) => {
                 MessageType::Request
             }
         }
     }
+
+    /// Returns the permission a plugin must hold to send the request.
+    pub fn get_required_permission(&self) -> Option<Permission> {
+        match self {
+            Self::NewOtx(_) => Some(Permission::NewOtx),
+            Self::DiscardOtx(_) => Some(Permission::DiscardOtx),
+            Self::ModifyOtx(_) => Some(Permission::ModifyOtx),
+            Self::SendCkbTx(_) => Some(Permission::SendCkbTx),
+            _ => None,
+        }
+    }
+}
+
+/// The notifications a plugin can subscribe to.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum EventType {
+    NewOtx,
+    NewInterval,
+    OtxPoolStart,
+    OtxPoolStop,
+    DeleteOtx,
+    ConflictOtx,
+}
+
+/// The requests a plugin is allowed to send.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum Permission {
+    NewOtx,
+    DiscardOtx,
+    ModifyOtx,
+    SendCkbTx,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -70,4 +142,27 @@ pub struct PluginInfo {
     pub name: String,
     pub description: String,
     pub version: String,
+    #[serde(default)]
+    pub protocol_version: u32,
+    #[serde(default)]
+    pub subscriptions: Vec<EventType>,
+    #[serde(default)]
+    pub permissions: Vec<Permission>,
+    /// The framing the plugin speaks after answering `GetPluginInfo`.
+    #[serde(default)]
+    pub framing: Framing,
+}
+
+impl PluginInfo {
+    pub fn is_compatible(&self) -> bool {
+        (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&self.protocol_version)
+    }
+
+    pub fn is_subscribed(&self, event_type: EventType) -> bool {
+        self.subscriptions.contains(&event_type)
+    }
+
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
 }
@@ -0,0 +1,152 @@
+//! The request/response correlation shared by the host and the plugins. Each
+//! request gets a new id, and the response with the same id is handed to the
+//! request waiting for it, so that multiple requests can be in flight at the
+//! same time.
+
+use crate::{MessageFromHost, MessageFromPlugin};
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The requests waiting for the responses of type `R`.
+pub struct PendingRequests<R> {
+    // request id -> the sender of the response
+    requests: Arc<Mutex<HashMap<u64, Sender<R>>>>,
+    // 0 is reserved for notifications
+    next_id: Arc<AtomicU64>,
+}
+
+impl<R> Clone for PendingRequests<R> {
+    fn clone(&self) -> Self {
+        PendingRequests {
+            requests: self.requests.clone(),
+            next_id: self.next_id.clone(),
+        }
+    }
+}
+
+impl<R> Default for PendingRequests<R> {
+    fn default() -> Self {
+        PendingRequests {
+            requests: Arc::default(),
+            next_id: Arc::new(AtomicU64::new(1)),
+        }
+    }
+}
+
+impl<R> PendingRequests<R> {
+    /// Send a request with `send`, which is given the new request id, and
+    /// wait for the response up to `timeout`.
+    pub fn request<F>(&self, send: F, timeout: Duration) -> Result<R, String>
+    where
+        F: FnOnce(u64) -> Result<(), String>,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (response_sender, response_receiver) = channel();
+        self.requests
+            .lock()
+            .expect("acquire lock")
+            .insert(id, response_sender);
+        if let Err(err) = send(id) {
+            self.requests.lock().expect("acquire lock").remove(&id);
+            return Err(err);
+        }
+        let response = response_receiver.recv_timeout(timeout);
+        // a late response is dropped by `deliver`
+        self.requests.lock().expect("acquire lock").remove(&id);
+        response.map_err(|err| match err {
+            RecvTimeoutError::Timeout => format!("request {} timed out", id),
+            RecvTimeoutError::Disconnected => format!("request {} dropped", id),
+        })
+    }
+
+    /// Hand the response to the request waiting with the same id. Returns
+    /// `false` if no request is waiting for it.
+    pub fn deliver(&self, id: u64, response: R) -> bool {
+        match self.requests.lock().expect("acquire lock").remove(&id) {
+            Some(responder) => {
+                let _ = responder.send(response);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.lock().expect("acquire lock").is_empty()
+    }
+}
+
+/// A response which may carry the error of the request.
+pub trait Response: Debug {
+    fn error(&self) -> Option<&str>;
+}
+
+impl Response for MessageFromHost {
+    fn error(&self) -> Option<&str> {
+        match self {
+            MessageFromHost::Error(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl Response for MessageFromPlugin {
+    fn error(&self) -> Option<&str> {
+        match self {
+            MessageFromPlugin::Error(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// The error of a response which does not answer the request.
+pub fn unexpected_response<R: Response>(response: R) -> String {
+    match response.error() {
+        Some(err) => err.to_owned(),
+        None => format!("unexpected response {:?}", response),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn test_deliver() {
+        let pending = PendingRequests::<u64>::default();
+        let (id_sender, id_receiver) = channel();
+        let request = {
+            let pending = pending.clone();
+            thread::spawn(move || {
+                pending.request(
+                    |id| id_sender.send(id).map_err(|err| err.to_string()),
+                    Duration::from_secs(5),
+                )
+            })
+        };
+        let id = id_receiver.recv().unwrap();
+        assert!(!pending.deliver(id + 1, 0));
+        assert!(pending.deliver(id, id * 10));
+        assert_eq!(request.join().unwrap(), Ok(id * 10));
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn test_unexpected_response() {
+        assert_eq!(
+            unexpected_response(MessageFromPlugin::Error(String::from("denied"))),
+            "denied"
+        );
+        assert_eq!(
+            unexpected_response(MessageFromHost::Ok),
+            "unexpected response Ok"
+        );
+    }
+}
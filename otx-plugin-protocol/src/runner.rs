@@ -0,0 +1,455 @@
+//! A small SDK for writing stdio plugins. `PluginRunner` owns the stdio loop,
+//! dispatches the host messages to a `PluginHandler`, and `Host` sends
+//! requests to the host and waits for the responses with the same id.
+
+use crate::framing::Framing;
+use crate::request::{unexpected_response, PendingRequests};
+use crate::{Id, MessageFromHost, MessageFromPlugin, MessageType, PluginInfo};
+
+use otx_format::jsonrpc_types::OpenTransaction;
+
+use ckb_types::H256;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How long `Host::request` waits for the response of the host.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Callbacks of a plugin, all of them are optional except `info`.
+pub trait PluginHandler {
+    fn info(&self) -> PluginInfo;
+
+    fn on_start(&mut self, _host: &Host) {}
+
+    fn on_stop(&mut self, _host: &Host) {}
+
+    fn on_new_otx(&mut self, _host: &Host, _id: Id, _otx: OpenTransaction) {}
+
+    fn on_delete_otx(&mut self, _host: &Host, _id: Id) {}
+
+    fn on_conflict_otx(&mut self, _host: &Host, _id: Id, _conflicts: Vec<Id>) {}
+
+    fn on_interval(&mut self, _host: &Host) {}
+
+    fn status(&self) -> String {
+        String::new()
+    }
+
+    fn proposed_tx(&self) -> Option<OpenTransaction> {
+        None
+    }
+}
+
+struct Output {
+    stdout: Box<dyn Write + Send>,
+    framing: Framing,
+}
+
+/// The client of the host, it is safe to use from the plugin callbacks.
+#[derive(Clone)]
+pub struct Host {
+    output: Arc<Mutex<Output>>,
+    pending: PendingRequests<MessageFromHost>,
+}
+
+impl Host {
+    fn new() -> Self {
+        Self::with_output(Box::new(io::stdout()))
+    }
+
+    fn with_output(stdout: Box<dyn Write + Send>) -> Self {
+        Host {
+            output: Arc::new(Mutex::new(Output {
+                stdout,
+                framing: Framing::JsonLines,
+            })),
+            pending: PendingRequests::default(),
+        }
+    }
+
+    fn send(&self, id: u64, msg: MessageFromPlugin) -> Result<(), String> {
+        let mut output = self.output.lock().expect("acquire lock");
+        let output = &mut *output;
+        output
+            .framing
+            .write_message(&mut output.stdout, id, &msg)
+            .map_err(|err| err.to_string())?;
+        // the host switches to the framing of the plugin once it gets the info
+        if let MessageFromPlugin::PluginInfo(plugin_info) = &msg {
+            output.framing = plugin_info.framing;
+        }
+        Ok(())
+    }
+
+    /// Send a request to the host, and wait for the response up to
+    /// `DEFAULT_REQUEST_TIMEOUT`.
+    pub fn request(&self, request: MessageFromPlugin) -> Result<MessageFromHost, String> {
+        self.request_with_timeout(request, DEFAULT_REQUEST_TIMEOUT)
+    }
+
+    /// Send a request to the host, and wait for the response up to `timeout`.
+    pub fn request_with_timeout(
+        &self,
+        request: MessageFromPlugin,
+        timeout: Duration,
+    ) -> Result<MessageFromHost, String> {
+        self.pending.request(|id| self.send(id, request), timeout)
+    }
+
+    pub fn new_otx(&self, otx: OpenTransaction) -> Result<Id, String> {
+        match self.request(MessageFromPlugin::NewOtx(otx))? {
+            MessageFromHost::OtxId(id) => Ok(id),
+            response => Err(unexpected_response(response)),
+        }
+    }
+
+    pub fn discard_otx(&self, id: Id) -> Result<(), String> {
+        match self.request(MessageFromPlugin::DiscardOtx(id))? {
+            MessageFromHost::Ok => Ok(()),
+            response => Err(unexpected_response(response)),
+        }
+    }
+
+    pub fn modify_otx(&self, id: Id, otx: OpenTransaction) -> Result<Id, String> {
+        match self.request(MessageFromPlugin::ModifyOtx((id, otx)))? {
+            MessageFromHost::OtxId(id) => Ok(id),
+            response => Err(unexpected_response(response)),
+        }
+    }
+
+    pub fn send_ckb_tx(&self, otx: OpenTransaction) -> Result<H256, String> {
+        match self.request(MessageFromPlugin::SendCkbTx(otx))? {
+            MessageFromHost::TxHash(tx_hash) => Ok(tx_hash),
+            response => Err(unexpected_response(response)),
+        }
+    }
+}
+
+pub struct PluginRunner<H: PluginHandler> {
+    handler: H,
+    host: Host,
+}
+
+impl<H: PluginHandler> PluginRunner<H> {
+    pub fn new(handler: H) -> Self {
+        PluginRunner {
+            handler,
+            host: Host::new(),
+        }
+    }
+
+    /// Run until the host closes stdin.
+    pub fn run(self) {
+        self.run_with(BufReader::new(io::stdin()))
+    }
+
+    /// Run until `input` is closed, the responses go to the output of the host.
+    fn run_with<R: BufRead + Send + 'static>(mut self, mut input: R) {
+        // the reader thread delivers the responses to the waiting requests,
+        // so that callbacks can send requests to the host
+        let (msg_sender, msg_receiver) = channel();
+        let pending = self.host.pending.clone();
+        let plugin_framing = self.handler.info().framing;
+        thread::spawn(move || {
+            let mut framing = Framing::JsonLines;
+            loop {
+                let (id, msg): (u64, MessageFromHost) = match framing.read_message(&mut input) {
+                    Ok(Some(msg)) => msg,
+                    Ok(None) => break,
+                    Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                        log::warn!("parse message error: {}", err);
+                        continue;
+                    }
+                    Err(err) => {
+                        log::error!("read stdin error: {}", err);
+                        break;
+                    }
+                };
+                if let MessageFromHost::GetPluginInfo = msg {
+                    // the host sends nothing else until it gets the info
+                    framing = plugin_framing;
+                }
+                if let MessageType::Response = msg.get_message_type() {
+                    if !pending.deliver(id, msg) {
+                        log::debug!("unexpected response: {}", id);
+                    }
+                } else if msg_sender.send((id, msg)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        while let Ok((id, msg)) = msg_receiver.recv() {
+            if let Some(response) = self.handle(msg) {
+                if let Err(err) = self.host.send(id, response) {
+                    log::error!("write stdout error: {}", err);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, msg: MessageFromHost) -> Option<MessageFromPlugin> {
+        let host = &self.host;
+        match msg {
+            // requests
+            MessageFromHost::GetPluginInfo => {
+                Some(MessageFromPlugin::PluginInfo(self.handler.info()))
+            }
+            MessageFromHost::GetPluginStatus => {
+                Some(MessageFromPlugin::PluginStatus(self.handler.status()))
+            }
+            MessageFromHost::GetProposedTx => {
+                Some(MessageFromPlugin::ProposedTx(self.handler.proposed_tx()))
+            }
+
+            // notifications
+            MessageFromHost::OtxPoolStart => {
+                self.handler.on_start(host);
+                None
+            }
+            MessageFromHost::OtxPoolStop => {
+                self.handler.on_stop(host);
+                None
+            }
+            MessageFromHost::NewOtx((id, otx)) => {
+                self.handler.on_new_otx(host, id, otx);
+                None
+            }
+            MessageFromHost::DeleteOtx(id) => {
+                self.handler.on_delete_otx(host, id);
+                None
+            }
+            MessageFromHost::ConflictOtx((id, conflicts)) => {
+                self.handler.on_conflict_otx(host, id, conflicts);
+                None
+            }
+            MessageFromHost::NewInterval => {
+                self.handler.on_interval(host);
+                None
+            }
+
+            // responses are delivered by the reader thread
+            MessageFromHost::Ok
+            | MessageFromHost::Error(_)
+            | MessageFromHost::OtxId(_)
+            | MessageFromHost::TxHash(_) => None,
+        }
+    }
+}
+
+/// Writes logs to stderr, since stdout is reserved for the protocol.
+struct StderrLogger {
+    level: LevelFilter,
+}
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let _ = writeln!(
+                io::stderr(),
+                "[{} {}] {}",
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+/// Init the `log` facade to write to stderr. The level is `Info` if `None`.
+pub fn init_logger(level: Option<Level>) {
+    let level = level
+        .map(|level| level.to_level_filter())
+        .unwrap_or(LevelFilter::Info);
+    if log::set_boxed_logger(Box::new(StderrLogger { level })).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+    use std::sync::mpsc::Sender;
+
+    /// Collects what the plugin writes to the host.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn lines(&self) -> usize {
+            self.0
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|b| **b == b'\n')
+                .count()
+        }
+
+        fn messages(&self) -> Vec<(u64, MessageFromPlugin)> {
+            let mut reader = Cursor::new(self.0.lock().unwrap().clone());
+            let mut messages = vec![];
+            while let Some(msg) = Framing::JsonLines.read_message(&mut reader).unwrap() {
+                messages.push(msg);
+            }
+            messages
+        }
+    }
+
+    #[test]
+    fn test_request_response_correlation() {
+        let buffer = SharedBuffer::default();
+        let host = Host::with_output(Box::new(buffer.clone()));
+
+        let requests: Vec<_> = (0..2u8)
+            .map(|i| {
+                let host = host.clone();
+                thread::spawn(move || host.request(MessageFromPlugin::DiscardOtx(H256([i; 32]))))
+            })
+            .collect();
+        // wait for both requests to be written out
+        while buffer.lines() < 2 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let sent = buffer.messages();
+
+        // answer in the reverse order, each request gets the response by its id
+        for (id, msg) in sent.into_iter().rev() {
+            let otx_id = match msg {
+                MessageFromPlugin::DiscardOtx(otx_id) => otx_id,
+                msg => panic!("unexpected request {:?}", msg),
+            };
+            assert!(host.pending.deliver(id, MessageFromHost::OtxId(otx_id)));
+        }
+        for (i, request) in requests.into_iter().enumerate() {
+            let response = request.join().unwrap().unwrap();
+            assert!(
+                matches!(response, MessageFromHost::OtxId(otx_id) if otx_id == H256([i as u8; 32]))
+            );
+        }
+        assert!(host.pending.is_empty());
+    }
+
+    #[test]
+    fn test_request_timeout() {
+        let host = Host::with_output(Box::new(SharedBuffer::default()));
+        let err = host
+            .request_with_timeout(
+                MessageFromPlugin::DiscardOtx(H256::default()),
+                Duration::from_millis(50),
+            )
+            .unwrap_err();
+        assert!(err.contains("timed out"));
+        assert!(host.pending.is_empty());
+    }
+
+    struct EchoPlugin {
+        new_otx_ids: Sender<Result<Id, String>>,
+    }
+
+    impl PluginHandler for EchoPlugin {
+        fn info(&self) -> PluginInfo {
+            PluginInfo {
+                name: "echo".to_string(),
+                description: String::new(),
+                version: String::new(),
+                protocol_version: crate::PROTOCOL_VERSION,
+                subscriptions: vec![],
+                permissions: vec![],
+                framing: Framing::LengthPrefixed,
+            }
+        }
+
+        fn on_new_otx(&mut self, host: &Host, _id: Id, otx: OpenTransaction) {
+            let _ = self.new_otx_ids.send(host.new_otx(otx));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_length_prefixed_handshake() {
+        use std::os::unix::net::UnixStream;
+
+        let (mut to_plugin, plugin_input) = UnixStream::pair().unwrap();
+        let (plugin_output, from_plugin) = UnixStream::pair().unwrap();
+        let mut from_plugin = BufReader::new(from_plugin);
+        let (new_otx_ids, new_otx_id_receiver) = channel();
+        let runner = PluginRunner {
+            handler: EchoPlugin { new_otx_ids },
+            host: Host::with_output(Box::new(plugin_output)),
+        };
+        let runner = thread::spawn(move || runner.run_with(BufReader::new(plugin_input)));
+
+        // the info is exchanged in json lines
+        Framing::JsonLines
+            .write_message(&mut to_plugin, 1, &MessageFromHost::GetPluginInfo)
+            .unwrap();
+        let (id, msg): (u64, MessageFromPlugin) = Framing::JsonLines
+            .read_message(&mut from_plugin)
+            .unwrap()
+            .unwrap();
+        assert_eq!(id, 1);
+        match msg {
+            MessageFromPlugin::PluginInfo(info) => {
+                assert_eq!(info.framing, Framing::LengthPrefixed)
+            }
+            msg => panic!("unexpected message {:?}", msg),
+        }
+
+        // then in the framing of the plugin, both ways
+        let framing = Framing::LengthPrefixed;
+        let otx = OpenTransaction::default();
+        framing
+            .write_message(
+                &mut to_plugin,
+                0,
+                &MessageFromHost::NewOtx((H256([1; 32]), otx.clone())),
+            )
+            .unwrap();
+        let (request_id, msg): (u64, MessageFromPlugin) =
+            framing.read_message(&mut from_plugin).unwrap().unwrap();
+        assert!(matches!(msg, MessageFromPlugin::NewOtx(sent) if sent == otx));
+        framing
+            .write_message(
+                &mut to_plugin,
+                request_id,
+                &MessageFromHost::OtxId(H256([2; 32])),
+            )
+            .unwrap();
+        assert_eq!(
+            new_otx_id_receiver
+                .recv_timeout(Duration::from_secs(5))
+                .unwrap(),
+            Ok(H256([2; 32]))
+        );
+
+        // the runner quits once the host closes the input
+        drop(to_plugin);
+        runner.join().unwrap();
+    }
+}
@@ -0,0 +1,30 @@
+//! Blocking calls made from the async runtime.
+//!
+//! The rpc client of ckb-sdk runs on a blocking http client, which panics when
+//! it is driven from a thread of the async runtime. Such calls run on a thread
+//! of their own through `run_blocking`.
+
+use std::thread;
+
+/// Run `f` on a new thread and wait for its result. Returns an error if `f`
+/// panics.
+pub fn run_blocking<T, F>(f: F) -> Result<T, String>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    thread::spawn(f)
+        .join()
+        .map_err(|_| String::from("blocking thread panicked"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_blocking() {
+        assert_eq!(run_blocking(|| 1 + 1), Ok(2));
+        assert!(run_blocking(|| panic!("boom")).is_err());
+    }
+}
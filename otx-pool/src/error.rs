@@ -35,16 +35,98 @@ impl From<VerificationError> for OtxRpcError {
     }
 }
 
+impl From<OtxPoolError> for OtxRpcError {
+    fn from(err: OtxPoolError) -> Self {
+        OtxRpcError(Box::new(err))
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Display, Hash, PartialEq, Eq)]
 pub enum OtxPoolError {
     #[display(fmt = "Otx already exists")]
     OtxAlreadyExists,
+
+    #[display(fmt = "Otx store error: {}", _0)]
+    StoreError(String),
+
+    #[display(fmt = "Otx has no inputs")]
+    NoInputs,
+
+    #[display(fmt = "Illegal key type {} in {} map", _1, _0)]
+    IllegalKeyType(String, u32),
+
+    #[display(fmt = "Otx has {} inputs but only {} witnesses", _0, _1)]
+    WitnessCountMismatch(usize, usize),
+
+    #[display(fmt = "Otx can not be converted to a transaction: {}", _0)]
+    ConvertToTxFailed(String),
+
+    #[display(fmt = "Output {} has zero capacity", _0)]
+    ZeroCapacityOutput(usize),
+
+    #[display(fmt = "Output {} capacity is less than its occupied capacity", _0)]
+    InsufficientOutputCapacity(usize),
+
+    #[display(fmt = "Input {} is not a live cell", _0)]
+    DeadInput(String),
+
+    #[display(fmt = "CKB rpc error: {}", _0)]
+    CkbRpcError(String),
+
+    #[display(fmt = "Otx offers no asset")]
+    OtxOffersNothing,
+
+    #[display(fmt = "Otx conflicts with the pooled otx {}", _0)]
+    OtxConflict(String),
+
+    #[display(
+        fmt = "Otx fee {} is not higher than the fee {} of conflicting otxs",
+        _0,
+        _1
+    )]
+    InsufficientReplaceFee(u64, u64),
+
+    #[display(fmt = "Otx expired at {}", _0)]
+    OtxExpired(u64),
+
+    #[display(fmt = "Otx pool is full: {}", _0)]
+    PoolFull(String),
+
+    #[display(fmt = "Invalid ownership proof: {}", _0)]
+    InvalidOwnershipProof(String),
+
+    #[display(fmt = "Otx {} not found", _0)]
+    OtxNotFound(String),
+
+    #[display(fmt = "Plugin manager error: {}", _0)]
+    PluginManagerError(String),
+
+    #[display(fmt = "Invalid intent: {}", _0)]
+    InvalidIntent(String),
 }
 
 impl OtxError for OtxPoolError {
     fn err_code(&self) -> i64 {
         match self {
             OtxPoolError::OtxAlreadyExists => -13100,
+            OtxPoolError::StoreError(_) => -13101,
+            OtxPoolError::NoInputs => -13102,
+            OtxPoolError::IllegalKeyType(_, _) => -13103,
+            OtxPoolError::WitnessCountMismatch(_, _) => -13104,
+            OtxPoolError::ConvertToTxFailed(_) => -13105,
+            OtxPoolError::ZeroCapacityOutput(_) => -13106,
+            OtxPoolError::InsufficientOutputCapacity(_) => -13107,
+            OtxPoolError::DeadInput(_) => -13108,
+            OtxPoolError::CkbRpcError(_) => -13109,
+            OtxPoolError::OtxOffersNothing => -13110,
+            OtxPoolError::OtxConflict(_) => -13111,
+            OtxPoolError::InsufficientReplaceFee(_, _) => -13112,
+            OtxPoolError::OtxExpired(_) => -13113,
+            OtxPoolError::PoolFull(_) => -13114,
+            OtxPoolError::InvalidOwnershipProof(_) => -13115,
+            OtxPoolError::OtxNotFound(_) => -13116,
+            OtxPoolError::PluginManagerError(_) => -13117,
+            OtxPoolError::InvalidIntent(_) => -13118,
         }
     }
 
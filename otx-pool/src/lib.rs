@@ -1,5 +1,7 @@
+pub mod blocking;
 pub mod error;
 pub mod notify;
 pub mod plugin;
 pub mod pool;
 pub mod rpc;
+pub mod store;
@@ -1,3 +1,5 @@
+use crate::pool::Id;
+
 use ckb_async_runtime::Handle;
 use ckb_stop_handler::{SignalSender, StopHandler};
 use otx_format::jsonrpc_types::OpenTransaction;
@@ -36,6 +38,9 @@ pub const NOTIFY_CHANNEL_SIZE: usize = 128;
 
 pub type NotifyRegister<M> = Sender<Request<String, Receiver<M>>>;
 
+/// The id of a new otx, and the ids of the pooled otxs it conflicts with.
+pub type OtxConflict = (Id, Vec<Id>);
+
 #[derive(Clone)]
 pub struct NotifyController {
     stop: StopHandler<()>,
@@ -43,6 +48,8 @@ pub struct NotifyController {
     new_open_tx_notifier: Sender<OpenTransaction>,
     delete_open_tx_register: NotifyRegister<OpenTransaction>,
     delete_open_tx_notifier: Sender<OpenTransaction>,
+    conflict_open_tx_register: NotifyRegister<OtxConflict>,
+    conflict_open_tx_notifier: Sender<OtxConflict>,
     interval_register: NotifyRegister<()>,
     interval_notifier: Sender<()>,
     start_register: NotifyRegister<()>,
@@ -61,6 +68,7 @@ impl Drop for NotifyController {
 pub struct NotifyService {
     new_open_tx_subscribers: HashMap<String, Sender<OpenTransaction>>,
     delete_open_tx_subscribers: HashMap<String, Sender<OpenTransaction>>,
+    conflict_open_tx_subscribers: HashMap<String, Sender<OtxConflict>>,
     interval_subscribers: HashMap<String, Sender<()>>,
     start_subscribers: HashMap<String, Sender<()>>,
     stop_subscribers: HashMap<String, Sender<()>>,
@@ -77,6 +85,7 @@ impl NotifyService {
         Self {
             new_open_tx_subscribers: HashMap::default(),
             delete_open_tx_subscribers: HashMap::default(),
+            conflict_open_tx_subscribers: HashMap::default(),
             interval_subscribers: HashMap::default(),
             start_subscribers: HashMap::default(),
             stop_subscribers: HashMap::default(),
@@ -96,6 +105,11 @@ impl NotifyService {
         let (delete_open_tx_sender, mut delete_open_tx_receiver) =
             mpsc::channel(NOTIFY_CHANNEL_SIZE);
 
+        let (conflict_open_tx_register, mut conflict_open_tx_register_receiver) =
+            mpsc::channel(REGISTER_CHANNEL_SIZE);
+        let (conflict_open_tx_sender, mut conflict_open_tx_receiver) =
+            mpsc::channel(NOTIFY_CHANNEL_SIZE);
+
         let (interval_register, mut interval_register_receiver) =
             mpsc::channel(REGISTER_CHANNEL_SIZE);
         let (interval_sender, mut interval_receiver) = mpsc::channel(NOTIFY_CHANNEL_SIZE);
@@ -116,6 +130,8 @@ impl NotifyService {
                     Some(msg) = new_open_tx_receiver.recv() => { self.handle_notify_new_open_tx(msg).await },
                     Some(msg) = delete_open_tx_register_receiver.recv() => { self.handle_register_delete_open_tx(msg) },
                     Some(msg) = delete_open_tx_receiver.recv() => { self.handle_notify_delete_open_tx(msg).await },
+                    Some(msg) = conflict_open_tx_register_receiver.recv() => { self.handle_register_conflict_open_tx(msg) },
+                    Some(msg) = conflict_open_tx_receiver.recv() => { self.handle_notify_conflict_open_tx(msg).await },
                     Some(msg) = interval_register_receiver.recv() => { self.handle_register_interval(msg) },
                     Some(()) = interval_receiver.recv() => { self.handle_notify_interval().await },
                     Some(msg) = start_register_receiver.recv() => { self.handle_register_start(msg) },
@@ -132,6 +148,8 @@ impl NotifyService {
             new_open_tx_notifier: new_open_tx_sender,
             delete_open_tx_register,
             delete_open_tx_notifier: delete_open_tx_sender,
+            conflict_open_tx_register,
+            conflict_open_tx_notifier: conflict_open_tx_sender,
             interval_register,
             interval_notifier: interval_sender,
             start_register,
@@ -185,6 +203,25 @@ impl NotifyService {
         }
     }
 
+    fn handle_register_conflict_open_tx(&mut self, msg: Request<String, Receiver<OtxConflict>>) {
+        let Request {
+            responder,
+            arguments: name,
+        } = msg;
+        log::debug!("Register conflict_open_tx {:?}", name);
+        let (sender, receiver) = mpsc::channel(NOTIFY_CHANNEL_SIZE);
+        self.conflict_open_tx_subscribers.insert(name, sender);
+        let _ = responder.send(receiver);
+    }
+
+    async fn handle_notify_conflict_open_tx(&mut self, conflict: OtxConflict) {
+        log::trace!("event conflict open tx {:?}", conflict);
+        // notify all subscribers
+        for subscriber in self.conflict_open_tx_subscribers.values() {
+            let _ = subscriber.send(conflict.clone()).await;
+        }
+    }
+
     fn handle_register_interval(&mut self, msg: Request<String, Receiver<()>>) {
         let Request {
             responder,
@@ -273,6 +310,19 @@ impl NotifyController {
         });
     }
 
+    pub async fn subscribe_conflict_open_tx<S: ToString>(&self, name: S) -> Receiver<OtxConflict> {
+        Request::call(&self.conflict_open_tx_register, name.to_string())
+            .await
+            .expect("Subscribe conflict open tx should be OK")
+    }
+
+    pub fn notify_conflict_open_tx(&self, conflict: OtxConflict) {
+        let conflict_open_tx_notifier = self.conflict_open_tx_notifier.clone();
+        self.handle.spawn(async move {
+            let _ = conflict_open_tx_notifier.send(conflict).await;
+        });
+    }
+
     pub async fn subscribe_interval<S: ToString>(&self, name: S) -> Receiver<()> {
         Request::call(&self.interval_register, name.to_string())
             .await
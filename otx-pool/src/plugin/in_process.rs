@@ -0,0 +1,278 @@
+use super::plugin_proxy::MsgHandler;
+use super::service::ServiceHandler;
+use crate::pool::{Id, OtxPool};
+
+use otx_format::jsonrpc_types::OpenTransaction;
+use otx_plugin_protocol::request::unexpected_response;
+use otx_plugin_protocol::{MessageFromHost, MessageFromPlugin, PluginInfo};
+
+use async_trait::async_trait;
+use ckb_async_runtime::Handle;
+use ckb_types::{core::service::Request, H256};
+use crossbeam_channel::unbounded;
+
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// A plugin running inside the service process. It receives the same pool
+/// events as the stdio plugins.
+#[async_trait]
+pub trait Plugin: Send + Sync {
+    fn info(&self) -> PluginInfo;
+
+    async fn on_start(&self, _context: &PluginContext) {}
+
+    async fn on_stop(&self, _context: &PluginContext) {}
+
+    async fn on_new_otx(&self, _context: &PluginContext, _id: Id, _otx: OpenTransaction) {}
+
+    async fn on_delete_otx(&self, _context: &PluginContext, _id: Id) {}
+
+    async fn on_conflict_otx(&self, _context: &PluginContext, _id: Id, _conflicts: Vec<Id>) {}
+
+    async fn on_interval(&self, _context: &PluginContext) {}
+
+    /// Served by the `get_plugin_status` rpc.
+    fn status(&self) -> String {
+        String::new()
+    }
+}
+
+/// The handle of an in-process plugin back into the service.
+#[derive(Clone)]
+pub struct PluginContext {
+    pub pool: Arc<OtxPool>,
+    name: String,
+    service_handler: ServiceHandler,
+}
+
+impl PluginContext {
+    pub fn new(name: String, pool: Arc<OtxPool>, service_handler: ServiceHandler) -> Self {
+        PluginContext {
+            pool,
+            name,
+            service_handler,
+        }
+    }
+
+    /// Send a request to the ServiceProvider, as a stdio plugin does.
+    pub fn request(&self, request: MessageFromPlugin) -> Result<MessageFromHost, String> {
+        Request::call(&self.service_handler, (self.name.clone(), request))
+            .ok_or_else(|| String::from("Send request to ServiceProvider failed"))
+    }
+
+    pub fn new_otx(&self, otx: OpenTransaction) -> Result<Id, String> {
+        match self.request(MessageFromPlugin::NewOtx(otx))? {
+            MessageFromHost::OtxId(id) => Ok(id),
+            response => Err(unexpected_response(response)),
+        }
+    }
+
+    pub fn discard_otx(&self, id: Id) -> Result<(), String> {
+        match self.request(MessageFromPlugin::DiscardOtx(id))? {
+            MessageFromHost::Ok => Ok(()),
+            response => Err(unexpected_response(response)),
+        }
+    }
+
+    pub fn send_ckb_tx(&self, otx: OpenTransaction) -> Result<H256, String> {
+        match self.request(MessageFromPlugin::SendCkbTx(otx))? {
+            MessageFromHost::TxHash(tx_hash) => Ok(tx_hash),
+            response => Err(unexpected_response(response)),
+        }
+    }
+}
+
+pub struct InProcessPlugin {
+    info: PluginInfo,
+    plugin: Arc<dyn Plugin>,
+    msg_handler: MsgHandler,
+    _thread: JoinHandle<()>,
+}
+
+impl InProcessPlugin {
+    /// Start a thread dispatching the pool events to the plugin. The thread
+    /// quits once all the msg handlers are dropped.
+    pub fn start(handle: Handle, plugin: Arc<dyn Plugin>, context: PluginContext) -> Self {
+        let info = plugin.info();
+        let (msg_handler, msg_receiver) = unbounded();
+        let dispatched = plugin.clone();
+        let thread = thread::spawn(move || {
+            while let Ok((_, msg)) = msg_receiver.recv() {
+                handle.block_on(dispatch(dispatched.as_ref(), &context, msg));
+            }
+        });
+        InProcessPlugin {
+            info,
+            plugin,
+            msg_handler,
+            _thread: thread,
+        }
+    }
+
+    pub fn info(&self) -> &PluginInfo {
+        &self.info
+    }
+
+    pub fn status(&self) -> String {
+        self.plugin.status()
+    }
+
+    pub fn msg_handler(&self) -> MsgHandler {
+        self.msg_handler.clone()
+    }
+}
+
+async fn dispatch(plugin: &dyn Plugin, context: &PluginContext, msg: MessageFromHost) {
+    match msg {
+        MessageFromHost::OtxPoolStart => plugin.on_start(context).await,
+        MessageFromHost::OtxPoolStop => plugin.on_stop(context).await,
+        MessageFromHost::NewOtx((id, otx)) => plugin.on_new_otx(context, id, otx).await,
+        MessageFromHost::DeleteOtx(id) => plugin.on_delete_otx(context, id).await,
+        MessageFromHost::ConflictOtx((id, conflicts)) => {
+            plugin.on_conflict_otx(context, id, conflicts).await
+        }
+        MessageFromHost::NewInterval => plugin.on_interval(context).await,
+        // the requests and responses are not sent to in-process plugins
+        MessageFromHost::GetPluginInfo
+        | MessageFromHost::GetPluginStatus
+        | MessageFromHost::GetProposedTx
+        | MessageFromHost::Ok
+        | MessageFromHost::Error(_)
+        | MessageFromHost::OtxId(_)
+        | MessageFromHost::TxHash(_) => {}
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::notify::NotifyService;
+    use crate::pool::cell_provider::MockCellProvider;
+    use crate::pool::config::OtxPoolConfig;
+    use crate::store::MemoryStore;
+
+    use otx_plugin_protocol::framing::Framing;
+    use otx_plugin_protocol::{EventType, PROTOCOL_VERSION};
+
+    use ckb_async_runtime::new_background_runtime;
+    use crossbeam_channel::{bounded, Sender};
+
+    use std::time::Duration;
+
+    pub(crate) fn plugin_info(name: &str, subscriptions: Vec<EventType>) -> PluginInfo {
+        PluginInfo {
+            name: name.to_string(),
+            description: String::new(),
+            version: String::new(),
+            protocol_version: PROTOCOL_VERSION,
+            subscriptions,
+            permissions: vec![],
+            framing: Framing::default(),
+        }
+    }
+
+    /// Forwards the events it receives to the test.
+    pub(crate) struct RecordingPlugin {
+        pub(crate) info: PluginInfo,
+        pub(crate) events: Sender<String>,
+    }
+
+    #[async_trait]
+    impl Plugin for RecordingPlugin {
+        fn info(&self) -> PluginInfo {
+            self.info.clone()
+        }
+
+        async fn on_start(&self, _context: &PluginContext) {
+            let _ = self.events.send("start".to_string());
+        }
+
+        async fn on_interval(&self, _context: &PluginContext) {
+            let _ = self.events.send("interval".to_string());
+        }
+
+        async fn on_delete_otx(&self, _context: &PluginContext, id: Id) {
+            let _ = self.events.send(format!("delete {:#x}", id));
+        }
+
+        async fn on_conflict_otx(&self, _context: &PluginContext, id: Id, conflicts: Vec<Id>) {
+            let _ = self
+                .events
+                .send(format!("conflict {:#x} {}", id, conflicts.len()));
+        }
+    }
+
+    fn context(service_handler: ServiceHandler) -> PluginContext {
+        let handle = new_background_runtime();
+        let pool = OtxPool::new(
+            NotifyService::new().start(handle),
+            Box::new(MemoryStore::new()),
+            Box::new(MockCellProvider::new()),
+            OtxPoolConfig::default(),
+        );
+        PluginContext::new("recording".to_string(), Arc::new(pool), service_handler)
+    }
+
+    #[test]
+    fn test_dispatch_events() {
+        let (service_handler, _) = bounded(1);
+        let (events, event_receiver) = bounded(3);
+        let plugin = InProcessPlugin::start(
+            new_background_runtime(),
+            Arc::new(RecordingPlugin {
+                info: plugin_info("recording", vec![]),
+                events,
+            }),
+            context(service_handler),
+        );
+        let msg_handler = plugin.msg_handler();
+        let id = H256([1; 32]);
+        for msg in [
+            MessageFromHost::OtxPoolStart,
+            MessageFromHost::DeleteOtx(id.clone()),
+            MessageFromHost::ConflictOtx((id.clone(), vec![H256([2; 32])])),
+        ] {
+            msg_handler.send((0, msg)).unwrap();
+        }
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(event_receiver.recv_timeout(timeout).unwrap(), "start");
+        assert_eq!(
+            event_receiver.recv_timeout(timeout).unwrap(),
+            format!("delete {:#x}", id)
+        );
+        assert_eq!(
+            event_receiver.recv_timeout(timeout).unwrap(),
+            format!("conflict {:#x} 1", id)
+        );
+    }
+
+    #[test]
+    fn test_discard_otx_round_trip() {
+        let (service_handler, service_receiver) = bounded(1);
+        let context = context(service_handler);
+        let id = H256([1; 32]);
+
+        // the service answers the request sent by the context
+        let expected = id.clone();
+        let service = thread::spawn(move || {
+            let Request {
+                responder,
+                arguments: (plugin_name, request),
+            } = service_receiver.recv().unwrap();
+            assert_eq!(plugin_name, "recording");
+            assert!(matches!(request, MessageFromPlugin::DiscardOtx(id) if id == expected));
+            responder.send(MessageFromHost::Ok).unwrap();
+
+            let Request { responder, .. } = service_receiver.recv().unwrap();
+            responder
+                .send(MessageFromHost::Error("not found".to_string()))
+                .unwrap();
+        });
+
+        assert!(context.discard_otx(id.clone()).is_ok());
+        assert_eq!(context.discard_otx(id).unwrap_err(), "not found");
+        service.join().unwrap();
+    }
+}
@@ -1,32 +1,59 @@
+use super::in_process::{InProcessPlugin, Plugin, PluginContext};
 use super::plugin_proxy::MsgHandler;
-use super::plugin_proxy::{PluginProxy, PluginState};
+use super::plugin_proxy::{PluginProxy, PluginRequester, PluginState};
+use super::policy::{PluginPolicy, POLICY_FILE_SUFFIX};
 use super::service::ServiceProvider;
+use super::supervisor::PluginHealth;
+use super::tx_sender::TxSender;
 use crate::notify::NotifyController;
+use crate::pool::OtxPool;
 
-use otx_plugin_protocol::MessageFromHost;
+use otx_plugin_protocol::{EventType, MessageFromHost, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
 
 use ckb_async_runtime::Handle;
 use otx_plugin_protocol::PluginInfo;
+use serde::{Deserialize, Serialize};
 use tokio::task::JoinHandle;
 
 use std::collections::HashMap;
 use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 
 pub const PLUGINS_DIRNAME: &str = "plugins";
 pub const INACTIVE_DIRNAME: &str = "plugins_inactive";
 
+type MsgHandlers = Arc<RwLock<HashMap<String, (Vec<EventType>, MsgHandler)>>>;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PluginStatus {
+    pub info: PluginInfo,
+    pub is_active: bool,
+    pub health: Option<PluginHealth>,
+}
+
 pub struct PluginManager {
-    _plugin_dir: PathBuf,
+    host_dir: PathBuf,
+    handle: Handle,
+
+    // the only dir plugins may be installed from, installing is disabled without it
+    install_dir: Option<PathBuf>,
 
     // information about all plugins, including inactive ones
     plugin_configs: HashMap<String, (PluginState, PluginInfo)>,
 
     // proxies for activated plugin processes
-    _plugin_proxies: HashMap<String, PluginProxy>,
+    plugin_proxies: HashMap<String, PluginProxy>,
+
+    // plugins running inside the service process
+    in_process_plugins: HashMap<String, InProcessPlugin>,
+
+    // the handlers which pool events are forwarded to, shared with the notify thread
+    msg_handlers: MsgHandlers,
 
-    _service_provider: ServiceProvider,
+    otx_pool: Arc<OtxPool>,
+    service_provider: ServiceProvider,
     _notify_thread: JoinHandle<()>,
 }
 
@@ -47,9 +74,19 @@ impl PluginManager {
         for (dir, is_active) in &[(&plugin_dir, true), (&inactive_plugin_dir, false)] {
             for entry in fs::read_dir(dir)? {
                 let path = entry?.path();
-                if path.is_file() {
+                // the policy files of the operator are not plugins
+                let is_policy = path.to_string_lossy().ends_with(POLICY_FILE_SUFFIX);
+                if path.is_file() && !is_policy {
                     let plugin_state = PluginState::new(path.clone(), *is_active);
                     match PluginProxy::get_plugin_info(path.clone()) {
+                        Ok(plugin_info) if !plugin_info.is_compatible() => {
+                            log::warn!(
+                                "plugin {} speaks protocol version {}, which is not supported, path: {:?}",
+                                plugin_info.name,
+                                plugin_info.protocol_version,
+                                path
+                            );
+                        }
                         Ok(plugin_info) => {
                             log::info!("Loaded plugin: {}", plugin_info.name);
                             plugin_configs.insert(
@@ -70,58 +107,515 @@ impl PluginManager {
     pub fn init(
         handle: Handle,
         notify_ctrl: NotifyController,
+        otx_pool: Arc<OtxPool>,
+        tx_sender: Box<dyn TxSender>,
         host_dir: &Path,
     ) -> Result<PluginManager, String> {
-        let plugin_dir = host_dir.join(PLUGINS_DIRNAME);
         let plugin_configs = Self::load_plugin_configs(host_dir).map_err(|err| err.to_string())?;
 
         let mut plugin_proxies = HashMap::new();
 
         // Make sure ServiceProvider start before all daemon processes
-        let service_provider = ServiceProvider::start()?;
+        let service_provider = ServiceProvider::start(otx_pool.clone(), tx_sender)?;
 
         for (plugin_name, (plugin_state, plugin_info)) in plugin_configs.iter() {
             if plugin_state.is_active {
+                let policy =
+                    PluginPolicy::load(&host_dir.join(PLUGINS_DIRNAME), &plugin_state.binary_path)?;
                 let plugin_proxy = PluginProxy::start_process(
-                    handle.clone(),
                     plugin_state.to_owned(),
                     plugin_info.to_owned(),
+                    policy.clone(),
                     service_provider.handler().clone(),
                 )?;
+                service_provider.register_plugin(plugin_info, &policy);
                 plugin_proxies.insert(plugin_name.to_owned(), plugin_proxy);
             }
         }
 
-        let plugins: Vec<(String, MsgHandler)> = plugin_proxies
+        let msg_handlers: HashMap<String, (Vec<EventType>, MsgHandler)> = plugin_proxies
             .iter()
-            .map(|(name, p)| (name.to_owned(), p.msg_handler()))
+            .map(|(name, p)| {
+                let subscriptions = plugin_configs[name].1.subscriptions.clone();
+                (name.to_owned(), (subscriptions, p.msg_handler()))
+            })
             .collect();
+        let msg_handlers = Arc::new(RwLock::new(msg_handlers));
 
         // subscribe pool event
+        let mut new_open_tx_receiver =
+            handle.block_on(notify_ctrl.subscribe_new_open_tx("plugin manager"));
+        let mut start_receiver = handle.block_on(notify_ctrl.subscribe_start("plugin manager"));
+        let mut stop_receiver = handle.block_on(notify_ctrl.subscribe_stop("plugin manager"));
         let mut interval_receiver =
             handle.block_on(notify_ctrl.subscribe_interval("plugin manager"));
+        let mut delete_open_tx_receiver =
+            handle.block_on(notify_ctrl.subscribe_delete_open_tx("plugin manager"));
+        let mut conflict_open_tx_receiver =
+            handle.block_on(notify_ctrl.subscribe_conflict_open_tx("plugin manager"));
+        let plugins = msg_handlers.clone();
         let notify_thread = handle.spawn(async move {
             loop {
-                tokio::select! {
-                    Some(()) = interval_receiver.recv() => {
-                        plugins.iter().for_each(|(_, notify_handler)| {
-                            let _ = notify_handler.send((0, MessageFromHost::NewInterval));
-                        })
+                let msg = tokio::select! {
+                    Some(otx) = new_open_tx_receiver.recv() => {
+                        MessageFromHost::NewOtx((otx.get_otx_hash(), otx))
                     }
-                }
+                    Some(()) = start_receiver.recv() => MessageFromHost::OtxPoolStart,
+                    Some(()) = stop_receiver.recv() => MessageFromHost::OtxPoolStop,
+                    Some(()) = interval_receiver.recv() => MessageFromHost::NewInterval,
+                    Some(otx) = delete_open_tx_receiver.recv() => {
+                        MessageFromHost::DeleteOtx(otx.get_otx_hash())
+                    }
+                    Some(conflict) = conflict_open_tx_receiver.recv() => {
+                        MessageFromHost::ConflictOtx(conflict)
+                    }
+                    else => break,
+                };
+                // only the plugins subscribing to the event are notified
+                let event_type = match msg.get_event_type() {
+                    Some(event_type) => event_type,
+                    None => continue,
+                };
+                plugins
+                    .read()
+                    .expect("acquire lock")
+                    .values()
+                    .filter(|(subscriptions, _)| subscriptions.contains(&event_type))
+                    .for_each(|(_, notify_handler)| {
+                        let _ = notify_handler.send((0, msg.clone()));
+                    });
             }
         });
 
         Ok(PluginManager {
-            _plugin_dir: plugin_dir,
+            host_dir: host_dir.to_path_buf(),
+            handle,
+            install_dir: None,
             plugin_configs,
-            _plugin_proxies: plugin_proxies,
-            _service_provider: service_provider,
+            plugin_proxies,
+            in_process_plugins: HashMap::new(),
+            msg_handlers,
+            otx_pool,
+            service_provider,
             _notify_thread: notify_thread,
         })
     }
 
+    /// Allow installing the plugin binaries in the dir.
+    pub fn set_install_dir(&mut self, install_dir: &Path) -> Result<(), String> {
+        let install_dir = install_dir
+            .canonicalize()
+            .map_err(|err| format!("invalid install dir {:?}: {}", install_dir, err))?;
+        self.install_dir = Some(install_dir);
+        Ok(())
+    }
+
+    /// Checks the binary is a file right in the install dir, returns its
+    /// canonical path.
+    pub fn check_install_path(&self, binary_path: &Path) -> Result<PathBuf, String> {
+        let install_dir = self
+            .install_dir
+            .as_ref()
+            .ok_or_else(|| String::from("installing plugins is disabled"))?;
+        let binary_path = binary_path
+            .canonicalize()
+            .map_err(|err| format!("invalid plugin path {:?}: {}", binary_path, err))?;
+        if binary_path.parent() != Some(install_dir.as_path()) || !binary_path.is_file() {
+            return Err(format!(
+                "plugin binary {:?} is not in the install dir {:?}",
+                binary_path, install_dir
+            ));
+        }
+        Ok(binary_path)
+    }
+
     pub fn plugin_configs(&self) -> &HashMap<String, (PluginState, PluginInfo)> {
         &self.plugin_configs
     }
+
+    pub fn plugin_health(&self) -> HashMap<String, PluginHealth> {
+        self.plugin_proxies
+            .iter()
+            .map(|(name, proxy)| (name.to_owned(), proxy.health()))
+            .collect()
+    }
+
+    /// Returns a requester of the active plugin, requests can be sent without
+    /// holding the manager.
+    pub fn plugin_requester(&self, name: &str) -> Option<PluginRequester> {
+        self.plugin_proxies.get(name).map(|proxy| proxy.requester())
+    }
+
+    /// The status of the in-process plugin, which is asked without a request.
+    pub fn in_process_plugin_status(&self, name: &str) -> Option<String> {
+        self.in_process_plugins
+            .get(name)
+            .map(|plugin| plugin.status())
+    }
+
+    pub fn list_plugins(&self) -> Vec<PluginStatus> {
+        let in_process_plugins = self.in_process_plugins.values().map(|plugin| PluginStatus {
+            info: plugin.info().to_owned(),
+            is_active: true,
+            health: None,
+        });
+        self.plugin_configs
+            .iter()
+            .map(|(name, (plugin_state, plugin_info))| PluginStatus {
+                info: plugin_info.to_owned(),
+                is_active: plugin_state.is_active,
+                health: self.plugin_proxies.get(name).map(|proxy| proxy.health()),
+            })
+            .chain(in_process_plugins)
+            .collect()
+    }
+
+    /// Register a plugin running inside the service process, it receives the
+    /// pool events along with the stdio plugins.
+    pub fn register_plugin(&mut self, plugin: Arc<dyn Plugin>) -> Result<(), String> {
+        let plugin_info = plugin.info();
+        let name = plugin_info.name.clone();
+        if self.plugin_configs.contains_key(&name) || self.in_process_plugins.contains_key(&name) {
+            return Err(format!("plugin {} already exists", name));
+        }
+        check_compatible(&plugin_info)?;
+        let context = PluginContext::new(
+            name.clone(),
+            self.otx_pool.clone(),
+            self.service_provider.handler().clone(),
+        );
+        let plugin = InProcessPlugin::start(self.handle.clone(), plugin, context);
+        // the in-process plugins are compiled into the service, so trusted
+        let policy = PluginPolicy::trusted(plugin_info.permissions.clone());
+        self.add_msg_handler(&plugin_info, &policy, plugin.msg_handler());
+        self.in_process_plugins.insert(name.clone(), plugin);
+        log::info!("plugin {} registered", name);
+        Ok(())
+    }
+
+    /// Stop dispatching the pool events to the in-process plugin.
+    pub fn unregister_plugin(&mut self, name: &str) -> Result<(), String> {
+        self.in_process_plugins
+            .remove(name)
+            .ok_or_else(|| format!("plugin {} not found", name))?;
+        self.remove_msg_handler(name);
+        log::info!("plugin {} unregistered", name);
+        Ok(())
+    }
+
+    /// Move the plugin binary into the plugins dir, and start the plugin.
+    pub fn activate_plugin(&mut self, name: &str) -> Result<(), String> {
+        let (plugin_state, plugin_info) = self
+            .plugin_configs
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("plugin {} not found", name))?;
+        if plugin_state.is_active {
+            return Err(format!("plugin {} is already active", name));
+        }
+        let policy = PluginPolicy::load(
+            &self.host_dir.join(PLUGINS_DIRNAME),
+            &plugin_state.binary_path,
+        )?;
+        let binary_path = self.move_binary(&plugin_state.binary_path, PLUGINS_DIRNAME)?;
+        let plugin_state = PluginState::new(binary_path.clone(), true);
+        let plugin_proxy = match PluginProxy::start_process(
+            plugin_state.clone(),
+            plugin_info.clone(),
+            policy.clone(),
+            self.service_provider.handler().clone(),
+        ) {
+            Ok(plugin_proxy) => plugin_proxy,
+            Err(err) => {
+                // keep the binary in the inactive dir as before
+                let _ = self.move_binary(&binary_path, INACTIVE_DIRNAME);
+                return Err(err);
+            }
+        };
+        self.add_msg_handler(&plugin_info, &policy, plugin_proxy.msg_handler());
+        self.plugin_proxies.insert(name.to_owned(), plugin_proxy);
+        self.plugin_configs
+            .insert(name.to_owned(), (plugin_state, plugin_info));
+        log::info!("plugin {} activated", name);
+        Ok(())
+    }
+
+    /// Stop the plugin, and move its binary into the inactive plugins dir.
+    pub fn deactivate_plugin(&mut self, name: &str) -> Result<(), String> {
+        let (plugin_state, plugin_info) = self
+            .plugin_configs
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("plugin {} not found", name))?;
+        if !plugin_state.is_active {
+            return Err(format!("plugin {} is not active", name));
+        }
+        self.remove_msg_handler(name);
+        // dropping the proxy terminates the plugin process
+        self.plugin_proxies.remove(name);
+        let binary_path = self.move_binary(&plugin_state.binary_path, INACTIVE_DIRNAME)?;
+        self.plugin_configs.insert(
+            name.to_owned(),
+            (PluginState::new(binary_path, false), plugin_info),
+        );
+        log::info!("plugin {} deactivated", name);
+        Ok(())
+    }
+
+    /// Copy the plugin binary into the inactive plugins dir. Returns the
+    /// plugin name, which is used to activate it.
+    ///
+    /// The binary must pass `check_install_path`, and its info is fetched by
+    /// `PluginProxy::get_plugin_info` beforehand, so that the manager is not
+    /// held while the binary runs.
+    pub fn install_plugin(
+        &mut self,
+        binary_path: &Path,
+        plugin_info: PluginInfo,
+    ) -> Result<String, String> {
+        let binary_path = self.check_install_path(binary_path)?;
+        let binary_path = binary_path.as_path();
+        check_compatible(&plugin_info)?;
+        if self.plugin_configs.contains_key(&plugin_info.name)
+            || self.in_process_plugins.contains_key(&plugin_info.name)
+        {
+            return Err(format!("plugin {} already exists", plugin_info.name));
+        }
+        let file_name = binary_path
+            .file_name()
+            .ok_or_else(|| format!("invalid plugin path {:?}", binary_path))?;
+        let target_path = self.host_dir.join(INACTIVE_DIRNAME).join(file_name);
+        if target_path.exists() {
+            return Err(format!("plugin binary {:?} already exists", target_path));
+        }
+        // the policy is up to the operator, a policy file next to the binary
+        // is not installed
+        fs::copy(binary_path, &target_path).map_err(|err| err.to_string())?;
+        let name = plugin_info.name.clone();
+        self.plugin_configs.insert(
+            name.clone(),
+            (PluginState::new(target_path, false), plugin_info),
+        );
+        log::info!("plugin {} installed", name);
+        Ok(name)
+    }
+
+    /// Stop the plugin if it is active, and delete its binary. The policy file
+    /// is left to the operator.
+    pub fn remove_plugin(&mut self, name: &str) -> Result<(), String> {
+        let is_active = self
+            .plugin_configs
+            .get(name)
+            .map(|(plugin_state, _)| plugin_state.is_active)
+            .ok_or_else(|| format!("plugin {} not found", name))?;
+        if is_active {
+            self.deactivate_plugin(name)?;
+        }
+        if let Some((plugin_state, _)) = self.plugin_configs.remove(name) {
+            fs::remove_file(plugin_state.binary_path).map_err(|err| err.to_string())?;
+        }
+        log::info!("plugin {} removed", name);
+        Ok(())
+    }
+
+    fn add_msg_handler(
+        &self,
+        plugin_info: &PluginInfo,
+        policy: &PluginPolicy,
+        msg_handler: MsgHandler,
+    ) {
+        self.service_provider.register_plugin(plugin_info, policy);
+        self.msg_handlers.write().expect("acquire lock").insert(
+            plugin_info.name.clone(),
+            (plugin_info.subscriptions.clone(), msg_handler),
+        );
+    }
+
+    fn remove_msg_handler(&self, name: &str) {
+        self.msg_handlers
+            .write()
+            .expect("acquire lock")
+            .remove(name);
+        self.service_provider.unregister_plugin(name);
+    }
+
+    fn move_binary(&self, binary_path: &Path, dirname: &str) -> Result<PathBuf, String> {
+        let file_name = binary_path
+            .file_name()
+            .ok_or_else(|| format!("invalid plugin path {:?}", binary_path))?;
+        let target_path = self.host_dir.join(dirname).join(file_name);
+        fs::rename(binary_path, &target_path).map_err(|err| err.to_string())?;
+        Ok(target_path)
+    }
+}
+
+fn check_compatible(plugin_info: &PluginInfo) -> Result<(), String> {
+    if plugin_info.is_compatible() {
+        Ok(())
+    } else {
+        Err(format!(
+            "plugin {} speaks protocol version {}, supported versions are {} to {}",
+            plugin_info.name, plugin_info.protocol_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::InnerResult;
+    use crate::notify::NotifyService;
+    use crate::plugin::in_process::tests::{plugin_info, RecordingPlugin};
+    use crate::pool::cell_provider::MockCellProvider;
+    use crate::pool::config::OtxPoolConfig;
+    use crate::store::MemoryStore;
+
+    use ckb_async_runtime::new_background_runtime;
+    use ckb_jsonrpc_types::Transaction;
+    use ckb_types::H256;
+    use crossbeam_channel::unbounded;
+    use tempfile::TempDir;
+
+    use std::time::Duration;
+
+    struct NoopTxSender;
+
+    impl TxSender for NoopTxSender {
+        fn send_transaction(&self, _tx: Transaction) -> InnerResult<H256> {
+            Ok(H256::default())
+        }
+    }
+
+    fn new_manager(host_dir: &Path) -> (PluginManager, NotifyController) {
+        let handle = new_background_runtime();
+        let notify_ctrl = NotifyService::new().start(handle.clone());
+        let pool = OtxPool::new(
+            notify_ctrl.clone(),
+            Box::new(MemoryStore::new()),
+            Box::new(MockCellProvider::new()),
+            OtxPoolConfig::default(),
+        );
+        let manager = PluginManager::init(
+            handle,
+            notify_ctrl.clone(),
+            Arc::new(pool),
+            Box::new(NoopTxSender),
+            host_dir,
+        )
+        .unwrap();
+        (manager, notify_ctrl)
+    }
+
+    fn touch(path: &Path) {
+        fs::write(path, b"").unwrap();
+    }
+
+    #[test]
+    fn test_check_install_path() {
+        let host_dir = TempDir::new().unwrap();
+        let (mut manager, _) = new_manager(host_dir.path());
+        let install_dir = host_dir.path().join("install");
+        fs::create_dir_all(install_dir.join("nested")).unwrap();
+        touch(&install_dir.join("plugin"));
+        touch(&install_dir.join("nested").join("plugin"));
+        touch(&host_dir.path().join("outside"));
+
+        let err = manager
+            .check_install_path(&install_dir.join("plugin"))
+            .unwrap_err();
+        assert!(err.contains("disabled"));
+
+        manager.set_install_dir(&install_dir).unwrap();
+        let binary_path = manager
+            .check_install_path(&install_dir.join("plugin"))
+            .unwrap();
+        assert_eq!(
+            binary_path,
+            install_dir.join("plugin").canonicalize().unwrap()
+        );
+
+        for path in [
+            host_dir.path().join("outside"),
+            install_dir.join("..").join("outside"),
+            install_dir.join("nested").join("plugin"),
+            install_dir.join("nested"),
+            install_dir.join("missing"),
+        ] {
+            assert!(manager.check_install_path(&path).is_err(), "{:?}", path);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_install_path_symlink() {
+        let host_dir = TempDir::new().unwrap();
+        let (mut manager, _) = new_manager(host_dir.path());
+        let install_dir = host_dir.path().join("install");
+        fs::create_dir_all(&install_dir).unwrap();
+        touch(&host_dir.path().join("outside"));
+        std::os::unix::fs::symlink(host_dir.path().join("outside"), install_dir.join("link"))
+            .unwrap();
+
+        manager.set_install_dir(&install_dir).unwrap();
+        assert!(manager
+            .check_install_path(&install_dir.join("link"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_refuse_incompatible_protocol_version() {
+        let host_dir = TempDir::new().unwrap();
+        let (mut manager, _) = new_manager(host_dir.path());
+        let mut info = plugin_info("old", vec![]);
+        info.protocol_version = MIN_PROTOCOL_VERSION - 1;
+
+        let (events, _) = unbounded();
+        let plugin = RecordingPlugin {
+            info: info.clone(),
+            events,
+        };
+        let err = manager.register_plugin(Arc::new(plugin)).unwrap_err();
+        assert!(err.contains("protocol version"));
+
+        let install_dir = host_dir.path().join("install");
+        fs::create_dir_all(&install_dir).unwrap();
+        touch(&install_dir.join("old"));
+        manager.set_install_dir(&install_dir).unwrap();
+        let err = manager
+            .install_plugin(&install_dir.join("old"), info)
+            .unwrap_err();
+        assert!(err.contains("protocol version"));
+        assert!(manager.plugin_configs().is_empty());
+        assert!(!host_dir.path().join(INACTIVE_DIRNAME).join("old").exists());
+    }
+
+    #[test]
+    fn test_deliver_subscribed_events_only() {
+        let host_dir = TempDir::new().unwrap();
+        let (mut manager, notify_ctrl) = new_manager(host_dir.path());
+        let (start_events, start_receiver) = unbounded();
+        let (interval_events, interval_receiver) = unbounded();
+        manager
+            .register_plugin(Arc::new(RecordingPlugin {
+                info: plugin_info("start", vec![EventType::OtxPoolStart]),
+                events: start_events,
+            }))
+            .unwrap();
+        manager
+            .register_plugin(Arc::new(RecordingPlugin {
+                info: plugin_info("interval", vec![EventType::NewInterval]),
+                events: interval_events,
+            }))
+            .unwrap();
+
+        let timeout = Duration::from_secs(5);
+        notify_ctrl.notify_start();
+        assert_eq!(start_receiver.recv_timeout(timeout).unwrap(), "start");
+        notify_ctrl.notify_interval();
+        // the events of a plugin come in order, so the start is not delivered
+        assert_eq!(interval_receiver.recv_timeout(timeout).unwrap(), "interval");
+        assert!(start_receiver
+            .recv_timeout(Duration::from_millis(100))
+            .is_err());
+    }
 }
@@ -1,3 +1,9 @@
+pub mod in_process;
 pub mod manager;
 pub mod plugin_proxy;
+pub mod policy;
 pub mod service;
+pub mod supervisor;
+pub mod tx_sender;
+
+pub use in_process::{Plugin, PluginContext};
@@ -1,17 +1,25 @@
+use super::policy::PluginPolicy;
 use super::service::ServiceHandler;
+use super::supervisor::{PluginHealth, Supervisor, SupervisorConfig};
 
-use ckb_async_runtime::Handle;
+use otx_plugin_protocol::framing::Framing;
+use otx_plugin_protocol::request::PendingRequests;
 use otx_plugin_protocol::{MessageFromHost, MessageFromPlugin, MessageType, PluginInfo};
 
 use ckb_types::core::service::Request;
-use crossbeam_channel::{bounded, select, unbounded, Sender};
-use tokio::task::JoinHandle;
+use crossbeam_channel::{bounded, select, unbounded, Receiver, Sender};
 
-use std::io::{BufRead, BufReader, Write};
+use std::io::BufReader;
 use std::path::PathBuf;
-use std::process::{Child, ChildStdin, Command, Stdio};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a starting plugin may take to answer `GetPluginInfo`, it is
+/// killed afterwards.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
 
-pub type RequestHandler = Sender<Request<(u64, MessageFromHost), (u64, MessageFromPlugin)>>;
 pub type MsgHandler = Sender<(u64, MessageFromHost)>;
 
 #[derive(Clone, Debug)]
@@ -29,19 +37,117 @@ impl PluginState {
     }
 }
 
+/// The thread writing the host messages to the plugin stdin. The host
+/// messages are taken from a receiver shared by the processes of a plugin
+/// across restarts.
+struct StdinWorker {
+    // dropping it stops the thread
+    stop_sender: Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl StdinWorker {
+    fn spawn(
+        plugin_name: String,
+        framing: Framing,
+        mut stdin: ChildStdin,
+        host_msg_receiver: Receiver<(u64, MessageFromHost)>,
+    ) -> Self {
+        let (stop_sender, stop_receiver) = bounded::<()>(0);
+        let thread = thread::spawn(move || loop {
+            select! {
+                // request/repsonse/notification from host to plugin
+                recv(host_msg_receiver) -> msg => {
+                    let (id, msg) = match msg {
+                        Ok(msg) => msg,
+                        Err(err) => {
+                            log::error!("plugin {} stdin error: {}", plugin_name, err);
+                            break;
+                        }
+                    };
+                    log::debug!("Send request/response/notification to plugin: {:?}", msg);
+                    if let Err(err) = framing.write_message(&mut stdin, id, &msg) {
+                        log::error!("plugin {} stdin error: {}", plugin_name, err);
+                        break;
+                    }
+                }
+                recv(stop_receiver) -> _ => break,
+            }
+        });
+        StdinWorker {
+            stop_sender,
+            thread,
+        }
+    }
+
+    /// Stop taking the host messages, and wait for the thread to quit.
+    fn stop(self) {
+        drop(self.stop_sender);
+        let _ = self.thread.join();
+    }
+}
+
 pub struct PluginProcess {
-    _plugin_process: Child,
-    _stdin_thread: JoinHandle<()>,
-    _stdout_thread: JoinHandle<()>,
+    pub(super) child: Child,
+    stdin_worker: Option<StdinWorker>,
+    _stdout_thread: Option<JoinHandle<()>>,
+}
+
+impl PluginProcess {
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Stop the stdin worker of the exited process, so that it does not take
+    /// the host messages meant for the restarted one.
+    pub(super) fn stop_io(&mut self) {
+        if let Some(stdin_worker) = self.stdin_worker.take() {
+            stdin_worker.stop();
+        }
+    }
+
+    #[cfg(test)]
+    pub(super) fn without_io(child: Child) -> Self {
+        PluginProcess {
+            child,
+            stdin_worker: None,
+            _stdout_thread: None,
+        }
+    }
+}
+
+/// Sends requests to a plugin, and matches the responses by request id, so
+/// that multiple requests can be in flight at the same time.
+#[derive(Clone)]
+pub struct PluginRequester {
+    msg_handler: MsgHandler,
+    pending: PendingRequests<MessageFromPlugin>,
+}
+
+impl PluginRequester {
+    pub fn request(
+        &self,
+        msg: MessageFromHost,
+        timeout: Duration,
+    ) -> Result<MessageFromPlugin, String> {
+        self.pending.request(
+            |id| {
+                self.msg_handler
+                    .send((id, msg))
+                    .map_err(|err| err.to_string())
+            },
+            timeout,
+        )
+    }
 }
 
 pub struct PluginProxy {
     _state: PluginState,
     _info: PluginInfo,
-    _process: PluginProcess,
+    supervisor: Supervisor,
 
     /// Send request to stdin thread, and expect a response from stdout thread.
-    _request_handler: RequestHandler,
+    requester: PluginRequester,
 
     /// Send notifaction/response to stdin thread.
     msg_handler: MsgHandler,
@@ -52,6 +158,23 @@ impl PluginProxy {
         self.msg_handler.clone()
     }
 
+    pub fn requester(&self) -> PluginRequester {
+        self.requester.clone()
+    }
+
+    /// Send a request to the plugin and wait for its response.
+    pub fn request(
+        &self,
+        msg: MessageFromHost,
+        timeout: Duration,
+    ) -> Result<MessageFromPlugin, String> {
+        self.requester.request(msg, timeout)
+    }
+
+    pub fn health(&self) -> PluginHealth {
+        self.supervisor.health()
+    }
+
     /// This function will create a temporary plugin process to fetch plugin information.
     pub fn get_plugin_info(binary_path: PathBuf) -> Result<PluginInfo, String> {
         let mut child = Command::new(&binary_path)
@@ -59,192 +182,123 @@ impl PluginProxy {
             .stdout(Stdio::piped())
             .spawn()
             .map_err(|err| err.to_string())?;
-        let mut stdin = child
-            .stdin
-            .take()
-            .ok_or_else(|| String::from("Get stdin failed"))?;
-        let stdout = child
-            .stdout
-            .take()
-            .ok_or_else(|| String::from("Get stdout failed"))?;
-
-        // request from host to plugin
-        let request = (0u64, MessageFromHost::GetPluginInfo);
-        let request_string = serde_json::to_string(&request).expect("Serialize request error");
-        log::debug!("Send request to plugin: {}", request_string);
-        stdin
-            .write_all(format!("{}\n", request_string).as_bytes())
-            .map_err(|err| err.to_string())?;
-        stdin.flush().map_err(|err| err.to_string())?;
-
-        // get response from plugin
-        let mut buf_reader = BufReader::new(stdout);
-        let mut response_string = String::new();
-        buf_reader
-            .read_line(&mut response_string)
-            .map_err(|err| err.to_string())?;
-        log::debug!("Receive response from plugin: {}", response_string.trim());
-        let (id, response): (u64, MessageFromPlugin) =
-            serde_json::from_str(&response_string).map_err(|err| err.to_string())?;
-
-        if let (0u64, MessageFromPlugin::PluginInfo(plugin_info)) = (id, response) {
-            Ok(plugin_info)
-        } else {
-            Err(format!(
-                "Invalid response for get_info call to plugin {:?}, response: {}",
-                binary_path, response_string
-            ))
-        }
+        let plugin_info = handshake(&mut child, HANDSHAKE_TIMEOUT)
+            .map(|(plugin_info, _, _)| plugin_info)
+            .map_err(|err| format!("get_info call to plugin {:?} failed: {}", binary_path, err));
+        let _ = child.kill();
+        let _ = child.wait();
+        plugin_info
     }
 
+    /// Start the plugin process under a supervisor, the channels to the plugin
+    /// stay the same across restarts.
     pub fn start_process(
-        runtime: Handle,
         plugin_state: PluginState,
         plugin_info: PluginInfo,
+        policy: PluginPolicy,
         service_handler: ServiceHandler,
     ) -> Result<PluginProxy, String> {
-        let mut child = Command::new(plugin_state.binary_path.clone())
+        // the channel sends requests, notifications or responses from the host to plugin
+        let (host_msg_sender, host_msg_receiver) = unbounded();
+
+        // the requests from host to plugin waiting for responses
+        let pending = PendingRequests::default();
+
+        let supervisor = {
+            let plugin_state = plugin_state.clone();
+            let plugin_info = plugin_info.clone();
+            let host_msg_sender = host_msg_sender.clone();
+            let pending = pending.clone();
+            Supervisor::start(
+                plugin_info.name.clone(),
+                SupervisorConfig::default(),
+                move || {
+                    let mut command = Command::new(&plugin_state.binary_path);
+                    policy.apply(&mut command);
+                    Self::spawn_process(
+                        command,
+                        &plugin_info,
+                        service_handler.clone(),
+                        pending.clone(),
+                        host_msg_sender.clone(),
+                        host_msg_receiver.clone(),
+                    )
+                },
+            )?
+        };
+
+        Ok(PluginProxy {
+            _state: plugin_state,
+            _info: plugin_info,
+            supervisor,
+            requester: PluginRequester {
+                msg_handler: host_msg_sender.clone(),
+                pending,
+            },
+            msg_handler: host_msg_sender,
+        })
+    }
+
+    fn spawn_process(
+        mut command: Command,
+        plugin_info: &PluginInfo,
+        service_handler: ServiceHandler,
+        pending: PendingRequests<MessageFromPlugin>,
+        host_msg_sender: MsgHandler,
+        host_msg_receiver: Receiver<(u64, MessageFromHost)>,
+    ) -> Result<PluginProcess, String> {
+        let mut child = command
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .spawn()
             .map_err(|err| err.to_string())?;
-        let mut stdin = child
-            .stdin
-            .take()
-            .ok_or_else(|| String::from("Get stdin failed"))?;
-        let stdout = child
-            .stdout
-            .take()
-            .ok_or_else(|| String::from("Get stdout failed"))?;
-
-        // the host request channel receives request from host to plugin
-        let (host_request_sender, host_request_receiver) = bounded(1);
-
-        // the plugin response channel receives response from plugin,
-        // it cooperates with the host request channel to complete the request-response pair
-        let (plugin_response_sender, plugin_response_receiver) = bounded(1);
-
-        // the channel sends notifications or responses from the host to plugin
-        let (host_msg_sender, host_msg_receiver) = unbounded();
-
-        let plugin_name = plugin_info.name.clone();
-        // this thread processes stdin information from host to plugin
-        let stdin_thread = runtime.spawn(async move  {
-            let handle_host_msg =
-                |stdin: &mut ChildStdin, (id, response)| -> Result<bool, String> {
-                    let response_string =
-                        serde_json::to_string(&(id, response)).expect("Serialize response error");
-                    log::debug!("Send response/notification to plugin: {}", response_string);
-                    stdin
-                        .write_all(format!("{}\n", response_string).as_bytes())
-                        .map_err(|err| err.to_string())?;
-                    stdin.flush().map_err(|err| err.to_string())?;
-                    Ok(false)
-                };
 
-            let mut do_select = || -> Result<bool, String> {
-                select! {
-                    // request from host to plugin
-                    recv(host_request_receiver) -> msg => {
-                        match msg {
-                            Ok(Request { responder, arguments }) => {
-                                let request_string = serde_json::to_string(&arguments).expect("Serialize request error");
-                                log::debug!("Send request to plugin: {}", request_string);
-                                stdin.write_all(format!("{}\n", request_string).as_bytes()).map_err(|err| err.to_string())?;
-                                stdin.flush().map_err(|err| err.to_string())?;
-                                loop {
-                                    select!{
-                                        recv(plugin_response_receiver) -> msg => {
-                                            match msg {
-                                                Ok(response) => {
-                                                    responder.send(response).map_err(|err| err.to_string())?;
-                                                    return Ok(false);
-                                                }
-                                                Err(err) => {
-                                                    return Err(err.to_string());
-                                                }
-                                            }
-                                        },
-                                        recv(host_msg_receiver) -> msg => {
-                                            match msg {
-                                                Ok(msg) => {
-                                                    handle_host_msg(&mut stdin, msg)?;
-                                                },
-                                                Err(err) => {
-                                                    return Err(err.to_string());
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                            Err(err) => Err(err.to_string())
-                        }
-                    }
-                    // repsonse/notification from host to plugin
-                    recv(host_msg_receiver) -> msg => {
-                        match msg {
-                            Ok(msg) => handle_host_msg(&mut stdin, msg),
-                            Err(err) => Err(err.to_string())
-                        }
-                    }
-                    // ignore the unexpected response from plugin
-                    recv(plugin_response_receiver) -> msg => {
-                        log::debug!("Received unexpected response/notification to plugin: {:?}", msg);
-                        match msg {
-                            Ok(_) => Ok(false),
-                            Err(err) => Err(err.to_string())
-                        }
-                    }
-                }
-            };
-            loop {
-                match do_select() {
-                    Ok(true) => {
-                        break;
-                    }
-                    Ok(false) => (),
-                    Err(err) => {
-                        log::error!("plugin {} stdin error: {}", plugin_name, err);
-                        break;
-                    }
-                }
+        // the plugin tells its framing before any other message is sent
+        let (framing, stdin, mut buf_reader) = match handshake(&mut child, HANDSHAKE_TIMEOUT) {
+            Ok((info, stdin, buf_reader)) => (info.framing, stdin, buf_reader),
+            Err(err) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!(
+                    "plugin {} handshake failed: {}",
+                    plugin_info.name, err
+                ));
             }
-        });
+        };
+        log::debug!("plugin {} speaks {:?}", plugin_info.name, framing);
+
+        let stdin_worker =
+            StdinWorker::spawn(plugin_info.name.clone(), framing, stdin, host_msg_receiver);
 
         let plugin_name = plugin_info.name.clone();
-        let msg_sender = host_msg_sender.clone();
-        let mut buf_reader = BufReader::new(stdout);
-        let stdout_thread = runtime.spawn(async move {
+        let msg_sender = host_msg_sender;
+        let stdout_thread = thread::spawn(move || {
             let mut do_recv = || -> Result<bool, String> {
-                let mut content = String::new();
-                if buf_reader
-                    .read_line(&mut content)
+                let (id, message_from_plugin): (u64, MessageFromPlugin) = match framing
+                    .read_message(&mut buf_reader)
                     .map_err(|err| err.to_string())?
-                    == 0
                 {
+                    Some(msg) => msg,
                     // EOF
-                    return Ok(true);
-                }
-
-                let (id, message_from_plugin): (u64, MessageFromPlugin) =
-                    serde_json::from_str(&content).map_err(|err| err.to_string())?;
+                    None => return Ok(true),
+                };
                 match message_from_plugin.get_message_type() {
                     MessageType::Response => {
                         // Receive response from plugin
-                        log::debug!("Receive response from plugin: {}", content.trim());
-                        plugin_response_sender
-                            .send((id, message_from_plugin))
-                            .map_err(|err| err.to_string())?;
+                        log::debug!("Receive response from plugin: {:?}", message_from_plugin);
+                        if !pending.deliver(id, message_from_plugin) {
+                            log::debug!("Received unexpected response from plugin: {}", id);
+                        }
                     }
                     MessageType::Request => {
                         // Handle request from plugin
-                        log::debug!("Receive request from plugin: {}", content.trim());
+                        log::debug!("Receive request from plugin: {:?}", message_from_plugin);
                         log::debug!("Sending request to ServiceProvider");
-                        let message_from_host =
-                            Request::call(&service_handler, message_from_plugin).ok_or_else(
-                                || String::from("Send request to ServiceProvider failed"),
-                            )?;
+                        let message_from_host = Request::call(
+                            &service_handler,
+                            (plugin_name.clone(), message_from_plugin),
+                        )
+                        .ok_or_else(|| String::from("Send request to ServiceProvider failed"))?;
                         log::debug!(
                             "Received response from ServiceProvider: {:?}",
                             message_from_host
@@ -275,24 +329,131 @@ impl PluginProxy {
             }
         });
 
-        let process = PluginProcess {
-            _plugin_process: child,
-            _stdin_thread: stdin_thread,
-            _stdout_thread: stdout_thread,
-        };
-
-        Ok(PluginProxy {
-            _state: plugin_state,
-            _info: plugin_info,
-            _process: process,
-            _request_handler: host_request_sender,
-            msg_handler: host_msg_sender,
+        Ok(PluginProcess {
+            child,
+            stdin_worker: Some(stdin_worker),
+            _stdout_thread: Some(stdout_thread),
         })
     }
 }
 
-impl Drop for PluginProxy {
-    fn drop(&mut self) {
-        // TODO: send term signal to the process
+/// Ask the freshly spawned plugin for its info on another thread, so that a
+/// plugin which does not answer in time can be given up. Returns the pipes
+/// to the plugin along with the info.
+fn handshake(
+    child: &mut Child,
+    timeout: Duration,
+) -> Result<(PluginInfo, ChildStdin, BufReader<ChildStdout>), String> {
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| String::from("Get stdin failed"))?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| String::from("Get stdout failed"))?;
+    let mut buf_reader = BufReader::new(stdout);
+
+    let (sender, receiver) = bounded(1);
+    thread::spawn(move || {
+        let plugin_info = request_plugin_info(&mut stdin, &mut buf_reader);
+        let _ = sender.send((plugin_info, stdin, buf_reader));
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok((plugin_info, stdin, buf_reader)) => Ok((plugin_info?, stdin, buf_reader)),
+        Err(_) => Err(format!("no answer in {:?}", timeout)),
+    }
+}
+
+/// Ask the freshly spawned plugin for its info, the messages are json lines
+/// until the plugin answers.
+fn request_plugin_info(
+    stdin: &mut ChildStdin,
+    stdout: &mut BufReader<ChildStdout>,
+) -> Result<PluginInfo, String> {
+    let framing = Framing::JsonLines;
+    log::debug!(
+        "Send request to plugin: {:?}",
+        MessageFromHost::GetPluginInfo
+    );
+    framing
+        .write_message(stdin, 0, &MessageFromHost::GetPluginInfo)
+        .map_err(|err| err.to_string())?;
+    let response: Option<(u64, MessageFromPlugin)> = framing
+        .read_message(stdout)
+        .map_err(|err| err.to_string())?;
+    log::debug!("Receive response from plugin: {:?}", response);
+    match response {
+        Some((0, MessageFromPlugin::PluginInfo(plugin_info))) => Ok(plugin_info),
+        Some((_, response)) => Err(format!("invalid response {:?}", response)),
+        None => Err(String::from("plugin quit")),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn spawn_cat() -> Child {
+        Command::new("cat")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_restarted_stdin_worker() {
+        let (host_msg_sender, host_msg_receiver) = unbounded();
+        let mut exited = spawn_cat();
+        StdinWorker::spawn(
+            "exited".to_string(),
+            Framing::JsonLines,
+            exited.stdin.take().unwrap(),
+            host_msg_receiver.clone(),
+        )
+        .stop();
+        let mut restarted = spawn_cat();
+        let _stdin_worker = StdinWorker::spawn(
+            "restarted".to_string(),
+            Framing::JsonLines,
+            restarted.stdin.take().unwrap(),
+            host_msg_receiver,
+        );
+
+        // all the messages go to the restarted process
+        for id in 1..=3 {
+            host_msg_sender
+                .send((id, MessageFromHost::NewInterval))
+                .unwrap();
+        }
+        let mut reader = BufReader::new(restarted.stdout.take().unwrap());
+        for id in 1..=3 {
+            let (received, msg): (u64, MessageFromHost) = Framing::JsonLines
+                .read_message(&mut reader)
+                .unwrap()
+                .unwrap();
+            assert_eq!(received, id);
+            assert!(matches!(msg, MessageFromHost::NewInterval));
+        }
+
+        for mut child in [exited, restarted] {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+    #[test]
+    fn test_handshake_timeout() {
+        let mut silent = Command::new("sleep")
+            .arg("10")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let started = std::time::Instant::now();
+        assert!(handshake(&mut silent, Duration::from_millis(200)).is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let _ = silent.kill();
+        let _ = silent.wait();
     }
 }
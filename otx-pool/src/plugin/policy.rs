@@ -0,0 +1,199 @@
+use otx_plugin_protocol::Permission;
+
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+pub const POLICY_FILE_SUFFIX: &str = ".policy.json";
+
+/// The operator-controlled policy of a plugin, loaded from the json file named
+/// after the plugin binary in the plugins dir, e.g. `plugins/demo.policy.json`
+/// for both `plugins/demo` and `plugins_inactive/demo`. The policy files are
+/// managed by the operator only, they are never installed along with a plugin.
+///
+/// A plugin without a policy file may not send any request to the host, and
+/// runs with the environment of the service.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct PluginPolicy {
+    /// The requests the plugin may send, the plugin still has to ask for them
+    /// in its `PluginInfo`.
+    pub allowed_requests: Vec<Permission>,
+    pub rate_limit: Option<RateLimit>,
+    pub resource_limits: Option<ResourceLimits>,
+    pub working_dir: Option<PathBuf>,
+    /// Start the plugin with an empty environment except `env_allowlist`.
+    pub clear_env: bool,
+    pub env_allowlist: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct RateLimit {
+    pub max_requests: u32,
+    pub per_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(default)]
+pub struct ResourceLimits {
+    pub max_memory_bytes: Option<u64>,
+    pub max_cpu_secs: Option<u64>,
+    pub max_open_files: Option<u64>,
+}
+
+impl PluginPolicy {
+    /// Load the policy of the plugin binary from the plugins dir, or the
+    /// default policy if there is none.
+    pub fn load(plugin_dir: &Path, binary_path: &Path) -> Result<PluginPolicy, String> {
+        let path = policy_path(plugin_dir, binary_path)?;
+        if !path.exists() {
+            log::warn!(
+                "no policy file for plugin {:?}, requests are not allowed",
+                binary_path
+            );
+            return Ok(PluginPolicy::default());
+        }
+        let content = fs::read_to_string(&path).map_err(|err| err.to_string())?;
+        serde_json::from_str(&content)
+            .map_err(|err| format!("invalid policy file {:?}: {}", path, err))
+    }
+
+    /// A policy allowing the given requests without any limit, for the
+    /// plugins shipped with the service.
+    pub fn trusted(allowed_requests: Vec<Permission>) -> PluginPolicy {
+        PluginPolicy {
+            allowed_requests,
+            ..Default::default()
+        }
+    }
+
+    /// Apply the working dir, environment and resource limits to the command
+    /// spawning the plugin.
+    pub fn apply(&self, command: &mut Command) {
+        if let Some(working_dir) = &self.working_dir {
+            command.current_dir(working_dir);
+        }
+        if self.clear_env {
+            command.env_clear();
+            for key in &self.env_allowlist {
+                if let Some(value) = std::env::var_os(key) {
+                    command.env(key, value);
+                }
+            }
+        }
+        if let Some(resource_limits) = self.resource_limits {
+            resource_limits.apply(command);
+        }
+    }
+}
+
+impl ResourceLimits {
+    #[cfg(unix)]
+    fn apply(self, command: &mut Command) {
+        use std::io;
+        use std::os::unix::process::CommandExt;
+
+        macro_rules! set_rlimit {
+            ($resource:expr, $limit:expr) => {
+                if let Some(limit) = $limit {
+                    let rlimit = libc::rlimit {
+                        rlim_cur: limit as libc::rlim_t,
+                        rlim_max: limit as libc::rlim_t,
+                    };
+                    // SAFETY: setrlimit is async-signal-safe
+                    if unsafe { libc::setrlimit($resource, &rlimit) } != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+            };
+        }
+
+        // SAFETY: the closure only calls async-signal-safe functions
+        unsafe {
+            command.pre_exec(move || {
+                set_rlimit!(libc::RLIMIT_AS, self.max_memory_bytes);
+                set_rlimit!(libc::RLIMIT_CPU, self.max_cpu_secs);
+                set_rlimit!(libc::RLIMIT_NOFILE, self.max_open_files);
+                Ok(())
+            });
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn apply(self, _command: &mut Command) {
+        log::warn!("resource limits are only supported on unix");
+    }
+}
+
+pub fn policy_path(plugin_dir: &Path, binary_path: &Path) -> Result<PathBuf, String> {
+    let mut file_name = binary_path
+        .file_name()
+        .ok_or_else(|| format!("invalid plugin path {:?}", binary_path))?
+        .to_owned();
+    file_name.push(POLICY_FILE_SUFFIX);
+    Ok(plugin_dir.join(file_name))
+}
+
+/// Counts the requests in a fixed window.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    window_start: Instant,
+    count: u32,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        RateLimiter {
+            limit,
+            window_start: Instant::now(),
+            count: 0,
+        }
+    }
+
+    /// Returns false if the request exceeds the limit.
+    pub fn check(&mut self) -> bool {
+        if self.window_start.elapsed() >= Duration::from_secs(self.limit.per_secs) {
+            self.window_start = Instant::now();
+            self.count = 0;
+        }
+        if self.count >= self.limit.max_requests {
+            return false;
+        }
+        self.count += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn test_policy_path() {
+        assert_eq!(
+            policy_path(Path::new("plugins"), Path::new("plugins_inactive/demo")).unwrap(),
+            Path::new("plugins/demo.policy.json")
+        );
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut rate_limiter = RateLimiter::new(RateLimit {
+            max_requests: 2,
+            per_secs: 1,
+        });
+        assert!(rate_limiter.check());
+        assert!(rate_limiter.check());
+        assert!(!rate_limiter.check());
+
+        // a new window
+        thread::sleep(Duration::from_millis(1100));
+        assert!(rate_limiter.check());
+    }
+}
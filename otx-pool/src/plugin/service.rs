@@ -1,21 +1,67 @@
-use otx_plugin_protocol::{MessageFromHost, MessageFromPlugin};
+use super::policy::{PluginPolicy, RateLimiter};
+use super::tx_sender::TxSender;
+use crate::error::InnerResult;
+use crate::pool::OtxPool;
+
+use otx_format::jsonrpc_types::tx_view::otx_to_tx_view;
+use otx_plugin_protocol::{MessageFromHost, MessageFromPlugin, Permission, PluginInfo};
 
 use ckb_types::core::service::Request;
 use crossbeam_channel::{bounded, Sender};
 
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
 
-pub type ServiceHandler = Sender<Request<MessageFromPlugin, MessageFromHost>>;
+/// The requests are sent along with the name of the plugin.
+pub type ServiceHandler = Sender<Request<(String, MessageFromPlugin), MessageFromHost>>;
+
+/// What a plugin is allowed to do, by both its `PluginInfo` and its policy.
+#[derive(Debug)]
+struct Grant {
+    permissions: HashSet<Permission>,
+    rate_limiter: Option<RateLimiter>,
+}
+
+impl Grant {
+    fn new(plugin_info: &PluginInfo, policy: &PluginPolicy) -> Self {
+        let (permissions, denied): (HashSet<Permission>, HashSet<Permission>) = plugin_info
+            .permissions
+            .iter()
+            .cloned()
+            .partition(|permission| policy.allowed_requests.contains(permission));
+        if !denied.is_empty() {
+            log::warn!(
+                "permissions {:?} of plugin {} are denied by the policy",
+                denied,
+                plugin_info.name
+            );
+        }
+        Grant {
+            permissions,
+            rate_limiter: policy.rate_limit.map(RateLimiter::new),
+        }
+    }
+}
+
+type Grants = Arc<Mutex<HashMap<String, Grant>>>;
 
 #[derive(Debug)]
 pub struct ServiceProvider {
     handler: ServiceHandler,
+    // plugin name -> the grant of the plugin
+    grants: Grants,
     _thread: JoinHandle<()>,
 }
 
 impl ServiceProvider {
-    pub fn start() -> Result<ServiceProvider, String> {
+    pub fn start(
+        otx_pool: Arc<OtxPool>,
+        tx_sender: Box<dyn TxSender>,
+    ) -> Result<ServiceProvider, String> {
         let (sender, receiver) = bounded(5);
+        let grants: Grants = Arc::default();
+        let granted = grants.clone();
 
         let handle = thread::spawn(move || loop {
             match receiver.recv() {
@@ -25,21 +71,26 @@ impl ServiceProvider {
                 }
                 Ok(Request {
                     responder,
-                    arguments,
+                    arguments: (plugin_name, request),
                 }) => {
-                    log::debug!("ServiceProvider received a request: {:?}", arguments);
-                    match arguments {
-                        MessageFromPlugin::DiscardOtx(_id) => {
-                            let _ = responder.send(MessageFromHost::Ok);
-                        }
-                        _ => unreachable!(),
-                    }
+                    log::debug!(
+                        "ServiceProvider received a request from {}: {:?}",
+                        plugin_name,
+                        request
+                    );
+                    let response = match check_permission(&granted, &plugin_name, &request) {
+                        Ok(()) => handle_request(&otx_pool, tx_sender.as_ref(), request)
+                            .unwrap_or_else(|err| MessageFromHost::Error(err.to_string())),
+                        Err(err) => MessageFromHost::Error(err),
+                    };
+                    let _ = responder.send(response);
                 }
             }
         });
 
         Ok(ServiceProvider {
             _thread: handle,
+            grants,
             handler: sender,
         })
     }
@@ -47,4 +98,134 @@ impl ServiceProvider {
     pub fn handler(&self) -> &ServiceHandler {
         &self.handler
     }
+
+    /// Grant the permissions requested by the plugin and allowed by the policy.
+    pub fn register_plugin(&self, plugin_info: &PluginInfo, policy: &PluginPolicy) {
+        self.grants
+            .lock()
+            .expect("acquire lock")
+            .insert(plugin_info.name.clone(), Grant::new(plugin_info, policy));
+    }
+
+    pub fn unregister_plugin(&self, name: &str) {
+        self.grants.lock().expect("acquire lock").remove(name);
+    }
+}
+
+fn check_permission(
+    grants: &Grants,
+    plugin_name: &str,
+    request: &MessageFromPlugin,
+) -> Result<(), String> {
+    let permission = match request.get_required_permission() {
+        Some(permission) => permission,
+        None => return Ok(()),
+    };
+    let mut grants = grants.lock().expect("acquire lock");
+    let grant = grants
+        .get_mut(plugin_name)
+        .ok_or_else(|| format!("plugin {} is not registered", plugin_name))?;
+    if !grant.permissions.contains(&permission) {
+        return Err(format!(
+            "plugin {} has no permission {:?}",
+            plugin_name, permission
+        ));
+    }
+    if let Some(rate_limiter) = grant.rate_limiter.as_mut() {
+        if !rate_limiter.check() {
+            return Err(format!("plugin {} exceeds the rate limit", plugin_name));
+        }
+    }
+    Ok(())
+}
+
+fn handle_request(
+    otx_pool: &OtxPool,
+    tx_sender: &dyn TxSender,
+    request: MessageFromPlugin,
+) -> InnerResult<MessageFromHost> {
+    match request {
+        MessageFromPlugin::NewOtx(otx) => otx_pool.insert_otx(otx).map(MessageFromHost::OtxId),
+        MessageFromPlugin::DiscardOtx(id) => match otx_pool.remove(&id)? {
+            Some(_) => Ok(MessageFromHost::Ok),
+            None => Ok(MessageFromHost::Error(format!("Otx {:#x} not found", id))),
+        },
+        MessageFromPlugin::ModifyOtx((id, otx)) => {
+            otx_pool.replace_otx(&id, otx).map(MessageFromHost::OtxId)
+        }
+        MessageFromPlugin::SendCkbTx(otx) => {
+            let tx_view = otx_to_tx_view(otx)?;
+            tx_sender
+                .send_transaction(tx_view.inner)
+                .map(MessageFromHost::TxHash)
+        }
+        MessageFromPlugin::Ok
+        | MessageFromPlugin::Error(_)
+        | MessageFromPlugin::PluginInfo(_)
+        | MessageFromPlugin::PluginStatus(_)
+        | MessageFromPlugin::ProposedTx(_) => Ok(MessageFromHost::Error(
+            "Unexpected response sent as a request".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::policy::RateLimit;
+
+    use otx_plugin_protocol::framing::Framing;
+
+    use ckb_types::H256;
+
+    fn grant_demo(permissions: Vec<Permission>, policy: PluginPolicy) -> Grants {
+        let plugin_info = PluginInfo {
+            name: "demo".to_string(),
+            description: String::new(),
+            version: String::new(),
+            protocol_version: 0,
+            subscriptions: vec![],
+            permissions,
+            framing: Framing::default(),
+        };
+        let grants: Grants = Arc::default();
+        grants
+            .lock()
+            .unwrap()
+            .insert(plugin_info.name.clone(), Grant::new(&plugin_info, &policy));
+        grants
+    }
+
+    #[test]
+    fn test_check_permission_denied() {
+        let discard = MessageFromPlugin::DiscardOtx(H256::default());
+
+        // the default policy allows nothing
+        let grants = grant_demo(vec![Permission::DiscardOtx], PluginPolicy::default());
+        assert!(check_permission(&grants, "demo", &discard).is_err());
+
+        // allowed by the policy, but not asked for by the plugin
+        let grants = grant_demo(vec![], PluginPolicy::trusted(vec![Permission::DiscardOtx]));
+        assert!(check_permission(&grants, "demo", &discard).is_err());
+        assert!(check_permission(&grants, "unknown", &discard).is_err());
+
+        // the requests without a permission are always allowed
+        assert!(check_permission(&grants, "demo", &MessageFromPlugin::Ok).is_ok());
+    }
+
+    #[test]
+    fn test_check_permission_rate_limit() {
+        let discard = MessageFromPlugin::DiscardOtx(H256::default());
+        let policy = PluginPolicy {
+            rate_limit: Some(RateLimit {
+                max_requests: 1,
+                per_secs: 60,
+            }),
+            ..PluginPolicy::trusted(vec![Permission::DiscardOtx])
+        };
+        let grants = grant_demo(vec![Permission::DiscardOtx], policy);
+        assert!(check_permission(&grants, "demo", &discard).is_ok());
+        let err = check_permission(&grants, "demo", &discard).unwrap_err();
+        assert!(err.contains("rate limit"));
+    }
 }
@@ -0,0 +1,311 @@
+use super::plugin_proxy::PluginProcess;
+
+use serde::{Deserialize, Serialize};
+
+use std::process::Child;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug)]
+pub struct SupervisorConfig {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// The plugin is given up after restarting this many times in a row.
+    pub max_restarts: u32,
+    /// The restart count is reset once the plugin has been running this long.
+    pub stable_after: Duration,
+    /// How long to wait for the plugin to exit after SIGTERM before killing it.
+    pub terminate_timeout: Duration,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        SupervisorConfig {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            max_restarts: 5,
+            stable_after: Duration::from_secs(300),
+            terminate_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthState {
+    Running,
+    Restarting,
+    Failed,
+    Stopped,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PluginHealth {
+    pub state: HealthState,
+    pub pid: Option<u32>,
+    pub restarts: u32,
+    pub last_exit: Option<String>,
+}
+
+/// Watches a plugin process, restarts it with exponential backoff when it
+/// exits, and terminates it on shutdown.
+pub struct Supervisor {
+    health: Arc<RwLock<PluginHealth>>,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Supervisor {
+    pub fn start<F>(name: String, config: SupervisorConfig, mut spawn: F) -> Result<Self, String>
+    where
+        F: FnMut() -> Result<PluginProcess, String> + Send + 'static,
+    {
+        let process = spawn()?;
+        let health = Arc::new(RwLock::new(PluginHealth {
+            state: HealthState::Running,
+            pid: Some(process.id()),
+            restarts: 0,
+            last_exit: None,
+        }));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let health = health.clone();
+            let shutdown = shutdown.clone();
+            thread::spawn(move || {
+                supervise(&name, &config, &mut spawn, process, &health, &shutdown)
+            })
+        };
+
+        Ok(Supervisor {
+            health,
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn health(&self) -> PluginHealth {
+        self.health.read().expect("acquire lock").clone()
+    }
+
+    /// Terminate the plugin process and wait for the supervisor to quit.
+    pub fn shutdown(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn supervise<F>(
+    name: &str,
+    config: &SupervisorConfig,
+    spawn: &mut F,
+    process: PluginProcess,
+    health: &RwLock<PluginHealth>,
+    shutdown: &AtomicBool,
+) where
+    F: FnMut() -> Result<PluginProcess, String>,
+{
+    let mut process = Some(process);
+    let mut restarts = 0u32;
+    loop {
+        if let Some(mut running) = process.take() {
+            let started_at = Instant::now();
+            let exit = match wait_exit(&mut running.child, shutdown) {
+                Some(exit) => exit,
+                None => {
+                    terminate(name, &mut running.child, config.terminate_timeout);
+                    running.stop_io();
+                    update_health(health, |health| {
+                        health.state = HealthState::Stopped;
+                        health.pid = None;
+                    });
+                    return;
+                }
+            };
+            // stop the io workers of the exited process before restarting
+            running.stop_io();
+            drop(running);
+            log::warn!("plugin {} exited: {}", name, exit);
+            if started_at.elapsed() >= config.stable_after {
+                restarts = 0;
+            }
+            update_health(health, |health| {
+                health.pid = None;
+                health.last_exit = Some(exit);
+            });
+        }
+
+        if restarts >= config.max_restarts {
+            log::error!("plugin {} restarted {} times, giving up", name, restarts);
+            update_health(health, |health| health.state = HealthState::Failed);
+            return;
+        }
+        restarts += 1;
+        update_health(health, |health| {
+            health.state = HealthState::Restarting;
+            health.restarts = health.restarts.saturating_add(1);
+        });
+
+        let backoff = config
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(restarts - 1))
+            .min(config.max_backoff);
+        if !sleep_unless_shutdown(backoff, shutdown) {
+            update_health(health, |health| health.state = HealthState::Stopped);
+            return;
+        }
+
+        log::info!("restarting plugin {}, attempt {}", name, restarts);
+        match spawn() {
+            Ok(new_process) => {
+                let pid = new_process.id();
+                update_health(health, |health| {
+                    health.state = HealthState::Running;
+                    health.pid = Some(pid);
+                });
+                process = Some(new_process);
+            }
+            Err(err) => {
+                log::warn!("restart plugin {} error: {}", name, err);
+                update_health(health, |health| health.last_exit = Some(err));
+            }
+        }
+    }
+}
+
+fn update_health(health: &RwLock<PluginHealth>, update: impl FnOnce(&mut PluginHealth)) {
+    update(&mut health.write().expect("acquire lock"));
+}
+
+/// Returns the exit status, or `None` if shutdown is requested first.
+fn wait_exit(child: &mut Child, shutdown: &AtomicBool) -> Option<String> {
+    loop {
+        if shutdown.load(Ordering::SeqCst) {
+            return None;
+        }
+        match child.try_wait() {
+            Ok(Some(status)) => return Some(status.to_string()),
+            Ok(None) => thread::sleep(POLL_INTERVAL),
+            Err(err) => return Some(err.to_string()),
+        }
+    }
+}
+
+/// Returns false if shutdown is requested before the duration elapses.
+fn sleep_unless_shutdown(duration: Duration, shutdown: &AtomicBool) -> bool {
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        if shutdown.load(Ordering::SeqCst) {
+            return false;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    !shutdown.load(Ordering::SeqCst)
+}
+
+/// Send SIGTERM to the plugin, and SIGKILL if it does not exit in time.
+fn terminate(name: &str, child: &mut Child, timeout: Duration) {
+    #[cfg(unix)]
+    {
+        // SAFETY: the pid belongs to a child process that has not been reaped
+        unsafe {
+            libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+        }
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = child.try_wait() {
+                log::info!("plugin {} terminated", name);
+                return;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = timeout;
+    if let Err(err) = child.kill() {
+        log::warn!("kill plugin {} error: {}", name, err);
+    }
+    let _ = child.wait();
+    log::info!("plugin {} killed", name);
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use std::process::Command;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_restart_with_backoff() {
+        let config = SupervisorConfig {
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_millis(400),
+            max_restarts: 3,
+            stable_after: Duration::from_secs(60),
+            terminate_timeout: Duration::from_secs(1),
+        };
+        let spawned_at = Arc::new(Mutex::new(vec![]));
+        let supervisor = {
+            let spawned_at = spawned_at.clone();
+            Supervisor::start("crashing".to_string(), config, move || {
+                spawned_at.lock().unwrap().push(Instant::now());
+                let child = Command::new("sh")
+                    .args(["-c", "exit 1"])
+                    .spawn()
+                    .map_err(|err| err.to_string())?;
+                Ok(PluginProcess::without_io(child))
+            })
+            .unwrap()
+        };
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while supervisor.health().state != HealthState::Failed {
+            assert!(Instant::now() < deadline, "the plugin is not given up");
+            thread::sleep(POLL_INTERVAL);
+        }
+        let health = supervisor.health();
+        assert_eq!(health.restarts, 3);
+        assert_eq!(health.pid, None);
+        assert!(health.last_exit.is_some());
+
+        // the initial spawn and 3 restarts, each after a doubled backoff up to the max
+        let spawned_at = spawned_at.lock().unwrap();
+        assert_eq!(spawned_at.len(), 4);
+        let backoffs = [200, 400, 400].map(Duration::from_millis);
+        for (spawned, backoff) in spawned_at.windows(2).zip(backoffs) {
+            assert!(spawned[1] - spawned[0] >= backoff);
+        }
+    }
+
+    #[test]
+    fn test_shutdown_terminates_plugin() {
+        let mut supervisor =
+            Supervisor::start("sleeping".to_string(), SupervisorConfig::default(), || {
+                let child = Command::new("sleep")
+                    .arg("60")
+                    .spawn()
+                    .map_err(|err| err.to_string())?;
+                Ok(PluginProcess::without_io(child))
+            })
+            .unwrap();
+        assert_eq!(supervisor.health().state, HealthState::Running);
+
+        supervisor.shutdown();
+        let health = supervisor.health();
+        assert_eq!(health.state, HealthState::Stopped);
+        assert_eq!(health.restarts, 0);
+    }
+}
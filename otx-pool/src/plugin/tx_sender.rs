@@ -0,0 +1,38 @@
+use crate::blocking::run_blocking;
+use crate::error::{InnerResult, OtxPoolError};
+
+use ckb_jsonrpc_types::{OutputsValidator, Transaction};
+use ckb_sdk::rpc::CkbRpcClient;
+use ckb_types::H256;
+
+/// Submits the transactions assembled by plugins.
+pub trait TxSender: Send + Sync {
+    fn send_transaction(&self, tx: Transaction) -> InnerResult<H256>;
+}
+
+/// Sends transactions to a CKB node.
+pub struct CkbTxSender {
+    ckb_uri: String,
+}
+
+impl CkbTxSender {
+    pub fn new(ckb_uri: &str) -> Self {
+        CkbTxSender {
+            ckb_uri: ckb_uri.to_string(),
+        }
+    }
+}
+
+impl TxSender for CkbTxSender {
+    fn send_transaction(&self, tx: Transaction) -> InnerResult<H256> {
+        let ckb_uri = self.ckb_uri.clone();
+        let tx_hash = run_blocking(move || {
+            CkbRpcClient::new(&ckb_uri)
+                .send_transaction(tx, Some(OutputsValidator::Passthrough))
+                .map_err(|err| err.to_string())
+        })
+        .map_err(OtxPoolError::CkbRpcError)?
+        .map_err(OtxPoolError::CkbRpcError)?;
+        Ok(tx_hash)
+    }
+}
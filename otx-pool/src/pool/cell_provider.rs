@@ -0,0 +1,216 @@
+use crate::blocking::run_blocking;
+use crate::error::{InnerResult, OtxPoolError};
+
+use ckb_jsonrpc_types::{CellInfo, OutPoint, TransactionView};
+use ckb_sdk::rpc::CkbRpcClient;
+use dashmap::DashMap;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Resolves the cells referenced by otx inputs, and the chain tip the block
+/// number expiry is checked against.
+pub trait CellProvider: Send + Sync {
+    /// Returns the cell if it is live, `None` if it is dead or unknown.
+    fn get_live_cell(&self, out_point: &OutPoint) -> InnerResult<Option<CellInfo>>;
+
+    /// Returns the cells in the order of `out_points`, `None` for the dead or
+    /// unknown ones.
+    fn get_live_cells(&self, out_points: &[OutPoint]) -> InnerResult<Vec<Option<CellInfo>>> {
+        out_points
+            .iter()
+            .map(|out_point| self.get_live_cell(out_point))
+            .collect()
+    }
+
+    fn get_tip_block_number(&self) -> InnerResult<u64>;
+}
+
+/// Queries live cells from a CKB node.
+pub struct CkbCellProvider {
+    ckb_uri: String,
+}
+
+impl CkbCellProvider {
+    pub fn new(ckb_uri: &str) -> Self {
+        CkbCellProvider {
+            ckb_uri: ckb_uri.to_string(),
+        }
+    }
+}
+
+impl CellProvider for CkbCellProvider {
+    fn get_live_cell(&self, out_point: &OutPoint) -> InnerResult<Option<CellInfo>> {
+        let mut cells = self.get_live_cells(std::slice::from_ref(out_point))?;
+        Ok(cells.pop().flatten())
+    }
+
+    fn get_live_cells(&self, out_points: &[OutPoint]) -> InnerResult<Vec<Option<CellInfo>>> {
+        if out_points.is_empty() {
+            return Ok(vec![]);
+        }
+        let ckb_uri = self.ckb_uri.clone();
+        let out_points = out_points.to_vec();
+        // one client serves the whole batch
+        let cells = run_blocking(move || {
+            let mut ckb_client = CkbRpcClient::new(&ckb_uri);
+            out_points
+                .into_iter()
+                .map(|out_point| {
+                    ckb_client
+                        .get_live_cell(out_point, true)
+                        .map(|cell_with_status| cell_with_status.cell)
+                        .map_err(|err| err.to_string())
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(OtxPoolError::CkbRpcError)?
+        .map_err(OtxPoolError::CkbRpcError)?;
+        Ok(cells)
+    }
+
+    fn get_tip_block_number(&self) -> InnerResult<u64> {
+        let ckb_uri = self.ckb_uri.clone();
+        let block_number = run_blocking(move || {
+            CkbRpcClient::new(&ckb_uri)
+                .get_tip_block_number()
+                .map_err(|err| err.to_string())
+        })
+        .map_err(OtxPoolError::CkbRpcError)?
+        .map_err(OtxPoolError::CkbRpcError)?;
+        Ok(block_number.value())
+    }
+}
+
+/// An in-memory cell set, mainly for tests.
+#[derive(Default)]
+pub struct MockCellProvider {
+    cells: DashMap<OutPoint, CellInfo>,
+    tip_block_number: AtomicU64,
+}
+
+impl MockCellProvider {
+    pub fn new() -> Self {
+        MockCellProvider::default()
+    }
+
+    pub fn add_cell(&self, out_point: OutPoint, cell: CellInfo) {
+        self.cells.insert(out_point, cell);
+    }
+
+    pub fn consume_cell(&self, out_point: &OutPoint) {
+        self.cells.remove(out_point);
+    }
+
+    pub fn set_tip_block_number(&self, block_number: u64) {
+        self.tip_block_number.store(block_number, Ordering::SeqCst);
+    }
+}
+
+impl CellProvider for MockCellProvider {
+    fn get_live_cell(&self, out_point: &OutPoint) -> InnerResult<Option<CellInfo>> {
+        Ok(self.cells.get(out_point).map(|cell| cell.value().clone()))
+    }
+
+    fn get_tip_block_number(&self) -> InnerResult<u64> {
+        Ok(self.tip_block_number.load(Ordering::SeqCst))
+    }
+}
+
+/// Resolve all inputs of the transaction, fails if any of them is not live.
+pub fn resolve_inputs(
+    provider: &dyn CellProvider,
+    tx_view: &TransactionView,
+) -> InnerResult<Vec<CellInfo>> {
+    let out_points = input_out_points(tx_view);
+    let cells = provider.get_live_cells(&out_points)?;
+    out_points
+        .iter()
+        .zip(cells)
+        .map(|(out_point, cell)| {
+            cell.ok_or_else(|| {
+                OtxPoolError::DeadInput(format!(
+                    "{:#x}-{}",
+                    out_point.tx_hash,
+                    out_point.index.value()
+                ))
+                .into()
+            })
+        })
+        .collect()
+}
+
+/// Returns true if any input of the transaction has been spent or never existed.
+pub fn has_dead_input(provider: &dyn CellProvider, tx_view: &TransactionView) -> InnerResult<bool> {
+    let cells = provider.get_live_cells(&input_out_points(tx_view))?;
+    Ok(cells.iter().any(Option::is_none))
+}
+
+fn input_out_points(tx_view: &TransactionView) -> Vec<OutPoint> {
+    tx_view
+        .inner
+        .inputs
+        .iter()
+        .map(|input| input.previous_output.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ckb_jsonrpc_types::CellOutput;
+    use ckb_types::{core::TransactionBuilder, packed, H256};
+
+    fn out_point(index: u32) -> OutPoint {
+        OutPoint {
+            tx_hash: H256::default(),
+            index: index.into(),
+        }
+    }
+
+    fn tx_view_spending(out_points: Vec<OutPoint>) -> TransactionView {
+        TransactionBuilder::default()
+            .inputs(
+                out_points
+                    .into_iter()
+                    .map(|out_point| packed::CellInput::new(out_point.into(), 0)),
+            )
+            .build()
+            .into()
+    }
+
+    #[test]
+    fn test_resolve_inputs() {
+        let provider = MockCellProvider::new();
+        let cell = CellInfo {
+            output: CellOutput::default(),
+            data: None,
+        };
+        provider.add_cell(out_point(0), cell.clone());
+        provider.add_cell(out_point(1), cell);
+
+        let tx_view = tx_view_spending(vec![out_point(0), out_point(1)]);
+        assert_eq!(resolve_inputs(&provider, &tx_view).unwrap().len(), 2);
+
+        provider.consume_cell(&out_point(1));
+        assert!(resolve_inputs(&provider, &tx_view).is_err());
+        assert!(has_dead_input(&provider, &tx_view).unwrap());
+    }
+
+    #[test]
+    fn test_get_live_cells() {
+        let provider = MockCellProvider::new();
+        let cell = CellInfo {
+            output: CellOutput::default(),
+            data: None,
+        };
+        provider.add_cell(out_point(1), cell);
+
+        let cells = provider
+            .get_live_cells(&[out_point(0), out_point(1)])
+            .unwrap();
+        assert_eq!(cells.len(), 2);
+        assert!(cells[0].is_none());
+        assert!(cells[1].is_some());
+    }
+}
@@ -0,0 +1,99 @@
+use ckb_sdk::constants::SIGHASH_TYPE_HASH;
+use ckb_types::{h256, H256};
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::Path;
+
+/// How the pool handles a new otx spending an out point which is already
+/// spent by a pooled otx.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Reject the new otx.
+    RejectNew,
+    /// Accept the new otx only if it pays more fee than every conflicting otx,
+    /// the conflicting otxs are then removed.
+    ReplaceByFee,
+    /// Accept the new otx, and mark it conflicting with the pooled ones.
+    KeepBoth,
+}
+
+/// Which pooled otxs are evicted first when the pool is full.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    OldestFirst,
+    /// A new otx is rejected if it does not pay more fee than the evicted ones.
+    LowestFeeFirst,
+}
+
+/// The time to live of otxs which do not declare an expire timestamp.
+pub const DEFAULT_OTX_TTL_SECS: u64 = 24 * 60 * 60;
+pub const DEFAULT_MAX_OTXS: usize = 10_000;
+pub const DEFAULT_MAX_TOTAL_BYTES: usize = 64 * 1024 * 1024;
+pub const DEFAULT_MAX_OTXS_PER_SUBMITTER: usize = 100;
+/// The type hash of the omnilock deployed on the dev chain.
+pub const DEFAULT_OMNILOCK_CODE_HASH: H256 =
+    h256!("0xbb4469004225b39e983929db71fe2253cba1d49a76223e9e1d212cdca1f79f28");
+
+pub const POOL_CONFIG_FILENAME: &str = "otx-pool.json";
+
+/// The operator-controlled config of the pool, loaded from a json file. The
+/// missing fields take the default values.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct OtxPoolConfig {
+    pub conflict_policy: ConflictPolicy,
+    pub default_ttl_secs: u64,
+    pub max_otxs: usize,
+    pub max_total_bytes: usize,
+    pub max_otxs_per_submitter: usize,
+    pub eviction_policy: EvictionPolicy,
+    /// The type hashes of the locks whose owners may delete their otxs with
+    /// a signature.
+    pub secp256k1_blake160_code_hash: H256,
+    pub omnilock_code_hash: H256,
+}
+
+impl Default for OtxPoolConfig {
+    fn default() -> Self {
+        OtxPoolConfig {
+            conflict_policy: ConflictPolicy::RejectNew,
+            default_ttl_secs: DEFAULT_OTX_TTL_SECS,
+            max_otxs: DEFAULT_MAX_OTXS,
+            max_total_bytes: DEFAULT_MAX_TOTAL_BYTES,
+            max_otxs_per_submitter: DEFAULT_MAX_OTXS_PER_SUBMITTER,
+            eviction_policy: EvictionPolicy::LowestFeeFirst,
+            secp256k1_blake160_code_hash: SIGHASH_TYPE_HASH,
+            omnilock_code_hash: DEFAULT_OMNILOCK_CODE_HASH,
+        }
+    }
+}
+
+impl OtxPoolConfig {
+    /// Load the config file, or the default config if there is none.
+    pub fn load(path: &Path) -> Result<OtxPoolConfig, String> {
+        if !path.exists() {
+            log::info!("no pool config file {:?}, use the default config", path);
+            return Ok(OtxPoolConfig::default());
+        }
+        let content = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        serde_json::from_str(&content)
+            .map_err(|err| format!("invalid pool config file {:?}: {}", path, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config: OtxPoolConfig = serde_json::from_str(
+            r#"{"conflict_policy": "ReplaceByFee", "eviction_policy": "OldestFirst"}"#,
+        )
+        .unwrap();
+        assert_eq!(config.conflict_policy, ConflictPolicy::ReplaceByFee);
+        assert_eq!(config.eviction_policy, EvictionPolicy::OldestFirst);
+        assert_eq!(config.max_otxs, DEFAULT_MAX_OTXS);
+    }
+}
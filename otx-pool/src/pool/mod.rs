@@ -1,51 +1,896 @@
-use crate::error::InnerResult;
+pub mod cell_provider;
+pub mod config;
+pub mod validator;
+
+#[cfg(test)]
+mod tests;
+
+use crate::error::{InnerResult, OtxPoolError};
 use crate::notify::NotifyController;
+use crate::store::OtxStore;
+use cell_provider::{resolve_inputs, CellProvider};
+use config::{ConflictPolicy, EvictionPolicy, OtxPoolConfig};
+use validator::{
+    asset_balance, check_balance, check_intent, check_residual, validate_otx, AssetBalance,
+};
 
-use otx_format::{jsonrpc_types::OpenTransaction, types::packed};
+use otx_format::{
+    error::OtxError,
+    jsonrpc_types::{tx_view::otx_to_tx_view, OpenTransaction},
+    types::{packed, OtxHash},
+};
 
-use ckb_jsonrpc_types::JsonBytes;
-use ckb_types::prelude::Entity;
+use ckb_crypto::secp::Signature;
+use ckb_hash::blake2b_256;
+use ckb_jsonrpc_types::{CellInfo, JsonBytes, OutPoint, Script, ScriptHashType, TransactionView};
+use ckb_types::{
+    prelude::{Entity, Unpack},
+    H256,
+};
 use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
 
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub type Id = OtxHash;
+
+/// The domain tag of the message signed to delete an otx, so that the
+/// signature is not valid for any other use of the otx id.
+pub const DELETE_OTX_DOMAIN: &[u8] = b"otx-pool/delete-otx";
+/// How long a deletion proof may be valid, bounding its replay.
+pub const MAX_DELETE_PROOF_SECS: u64 = 10 * 60;
+
+#[derive(Clone, Debug)]
+pub struct OtxEntry {
+    pub otx: OpenTransaction,
+    pub inputs: Vec<OutPoint>,
+    pub fee: u64,
+    pub expire_at: u64,
+    /// The block number declared by the otx from which on it is dropped.
+    pub expire_block_number: Option<u64>,
+    pub inserted_at: u64,
+    pub size: usize,
+    /// The lock hash of the first input cell, if the inputs are resolved.
+    pub submitter: Option<H256>,
+    /// The lock scripts of the resolved input cells.
+    pub input_locks: Vec<Script>,
+    /// The lock script hashes of the resolved input cells and the outputs.
+    pub lock_hashes: HashSet<H256>,
+    /// The type script hashes of the resolved input cells and the outputs.
+    pub type_hashes: HashSet<H256>,
+    /// The assets given away and asked for by the otx.
+    pub balance: AssetBalance,
+    /// The otx this one is the residual of, after a partial fill.
+    pub parent_id: Option<Id>,
+}
+
+impl OtxEntry {
+    fn new(
+        otx: OpenTransaction,
+        tx_view: &TransactionView,
+        input_cells: &[CellInfo],
+        expire_at: u64,
+        inserted_at: u64,
+    ) -> Self {
+        let inputs = tx_view
+            .inner
+            .inputs
+            .iter()
+            .map(|input| input.previous_output.clone())
+            .collect();
+        let input_capacity: u64 = input_cells
+            .iter()
+            .map(|cell| cell.output.capacity.value())
+            .sum();
+        let output_capacity: u64 = tx_view
+            .inner
+            .outputs
+            .iter()
+            .map(|output| output.capacity.value())
+            .sum();
+        let size = packed::OpenTransaction::from(otx.clone()).as_slice().len();
+        let submitter = input_cells
+            .first()
+            .map(|cell| script_hash(&cell.output.lock));
+        let input_locks = input_cells
+            .iter()
+            .map(|cell| cell.output.lock.clone())
+            .collect();
+        let cell_outputs = input_cells
+            .iter()
+            .map(|cell| &cell.output)
+            .chain(tx_view.inner.outputs.iter());
+        let mut lock_hashes = HashSet::new();
+        let mut type_hashes = HashSet::new();
+        for output in cell_outputs {
+            lock_hashes.insert(script_hash(&output.lock));
+            if let Some(type_) = &output.type_ {
+                type_hashes.insert(script_hash(type_));
+            }
+        }
+        OtxEntry {
+            inputs,
+            fee: input_capacity.saturating_sub(output_capacity),
+            expire_at,
+            expire_block_number: otx.get_expire_block_number().ok().flatten(),
+            inserted_at,
+            size,
+            submitter,
+            input_locks,
+            lock_hashes,
+            type_hashes,
+            balance: asset_balance(tx_view, input_cells),
+            parent_id: otx.get_parent_id().ok().flatten(),
+            otx,
+        }
+    }
+}
 
-pub type Id = u64;
+/// The unsigned residual otx left by the partial fill of a settled otx. It is
+/// kept out of the pool until the owner signs and submits it.
+#[derive(Clone, Debug)]
+pub struct ResidualTemplate {
+    pub otx: OpenTransaction,
+    /// The first input lock of the parent, which the residual must spend.
+    pub owner_lock: Script,
+    /// The expiry of the parent, the template is dropped afterwards.
+    pub expire_at: u64,
+}
 
 pub struct OtxPool {
-    raw_otxs: DashMap<Id, OpenTransaction>,
+    raw_otxs: DashMap<Id, OtxEntry>,
+
+    // out point -> ids of the pooled otxs spending it
+    out_point_index: DashMap<OutPoint, HashSet<Id>>,
+
+    // lock script hash -> ids of the pooled otxs with inputs or outputs using it
+    lock_script_index: DashMap<H256, HashSet<Id>>,
+
+    // type script hash -> ids of the pooled otxs with inputs or outputs using it
+    type_script_index: DashMap<H256, HashSet<Id>>,
+
+    // parent id -> ids of the pooled residual otxs of it
+    parent_index: DashMap<Id, HashSet<Id>>,
+
+    // submitter lock hash -> number of the pooled otxs it submitted
+    submitter_counts: DashMap<H256, usize>,
+
+    // parent id -> the residual template of the settled parent
+    residual_templates: DashMap<Id, ResidualTemplate>,
+
+    // total molecule size of the pooled otxs
+    total_bytes: AtomicUsize,
+    started_at: u64,
+
+    // the current unix time in seconds
+    clock: Box<dyn Fn() -> u64 + Send + Sync>,
+
+    // serializes the updates of the otxs and the indexes
+    update_lock: Mutex<()>,
+
+    store: Box<dyn OtxStore>,
+    cell_provider: Box<dyn CellProvider>,
+    config: OtxPoolConfig,
     notify_ctrl: NotifyController,
 }
 
 impl OtxPool {
-    pub fn new(notify_ctrl: NotifyController) -> Self {
+    pub fn new(
+        notify_ctrl: NotifyController,
+        store: Box<dyn OtxStore>,
+        cell_provider: Box<dyn CellProvider>,
+        config: OtxPoolConfig,
+    ) -> Self {
         OtxPool {
             raw_otxs: DashMap::new(),
+            out_point_index: DashMap::new(),
+            lock_script_index: DashMap::new(),
+            type_script_index: DashMap::new(),
+            parent_index: DashMap::new(),
+            submitter_counts: DashMap::new(),
+            residual_templates: DashMap::new(),
+            total_bytes: AtomicUsize::new(0),
+            started_at: unix_timestamp(),
+            clock: Box::new(unix_timestamp),
+            update_lock: Mutex::new(()),
+            store,
+            cell_provider,
+            config,
             notify_ctrl,
         }
     }
 
+    /// Read the current unix time in seconds from `clock` instead of the
+    /// system time.
+    pub fn with_clock<F>(mut self, clock: F) -> Self
+    where
+        F: Fn() -> u64 + Send + Sync + 'static,
+    {
+        self.started_at = clock();
+        self.clock = Box::new(clock);
+        self
+    }
+
+    fn now(&self) -> u64 {
+        (self.clock)()
+    }
+
+    /// Reload the otxs saved in the store, and notify them again as new otxs,
+    /// so that subscribers can rebuild their state. The otxs beyond the pool
+    /// limits, which may have been lowered since, are dropped from the store.
+    pub fn recover(&self) -> InnerResult<usize> {
+        let otxs = self.store.load_all()?;
+        let mut count = 0;
+        let _guard = self.update_lock.lock().expect("acquire lock");
+        for (id, otx) in otxs {
+            let tx_view = otx_to_tx_view(otx.clone())?;
+            // the dead otxs will be evicted on the next interval, other errors
+            // would leave the entry without its input cells
+            let input_cells = match resolve_inputs(self.cell_provider.as_ref(), &tx_view) {
+                Ok(input_cells) => input_cells,
+                Err(err)
+                    if err.0.err_code() == OtxPoolError::DeadInput(String::new()).err_code() =>
+                {
+                    vec![]
+                }
+                Err(err) => return Err(err),
+            };
+            let expire_at = self
+                .get_expire_at(&otx)
+                .unwrap_or_else(|_| self.default_expire_at());
+            let entry = OtxEntry::new(otx.clone(), &tx_view, &input_cells, expire_at, self.now());
+            if let Err(err) = self.check_limits(&entry) {
+                log::warn!("drop recovered otx {:#x}: {}", id, err);
+                self.store.remove(&id)?;
+                continue;
+            }
+            self.index_entry(&id, &entry);
+            self.raw_otxs.insert(id, entry);
+            self.notify_ctrl.notify_new_open_tx(otx);
+            count += 1;
+        }
+        Ok(count)
+    }
+
     pub fn insert(&self, otx: JsonBytes) -> InnerResult<Id> {
-        let id = {
-            let mut s = DefaultHasher::new();
-            otx.hash(&mut s);
-            s.finish()
-        };
         let otx = parse_otx(otx)?;
-        match self.raw_otxs.entry(id) {
-            Entry::Vacant(entry) => {
-                entry.insert(otx.clone());
-                self.notify_ctrl.notify_new_open_tx(otx)
-            }
-            Entry::Occupied(_) => {}
-        };
+        self.insert_otx(otx)
+    }
+
+    pub fn insert_otx(&self, otx: OpenTransaction) -> InnerResult<Id> {
+        let (id, entry) = self.prepare_entry(otx)?;
+        self.insert_entry(id.clone(), entry, None)?;
         Ok(id)
     }
 
+    /// Replace a pooled otx with a new one in one step, the old otx is kept if
+    /// the new one is rejected.
+    pub fn replace_otx(&self, id: &Id, otx: OpenTransaction) -> InnerResult<Id> {
+        if !self.raw_otxs.contains_key(id) {
+            return Err(OtxPoolError::OtxNotFound(format!("{:#x}", id)).into());
+        }
+        let (new_id, entry) = self.prepare_entry(otx)?;
+        self.insert_entry(new_id.clone(), entry, Some(id))?;
+        Ok(new_id)
+    }
+
     pub fn get_otx_by_id(&self, id: Id) -> Option<OpenTransaction> {
-        self.raw_otxs.get(&id).map(|pair| pair.value().clone())
+        self.raw_otxs.get(&id).map(|pair| pair.value().otx.clone())
+    }
+
+    pub fn get_otx_entry(&self, id: &Id) -> Option<OtxEntry> {
+        self.raw_otxs.get(id).map(|pair| pair.value().clone())
+    }
+
+    /// Returns the ids of the pooled otxs spending any input of the otx.
+    pub fn get_conflicts_by_id(&self, id: &Id) -> Option<Vec<Id>> {
+        let inputs = self.raw_otxs.get(id)?.inputs.clone();
+        Some(self.get_conflicts(id, &inputs))
+    }
+
+    /// Returns a page of the pooled otxs in insertion order, and the total
+    /// number of the pooled otxs.
+    pub fn get_all_otxs(&self, offset: usize, limit: usize) -> (Vec<(Id, OpenTransaction)>, usize) {
+        let mut otxs: Vec<(u64, Id, OpenTransaction)> = self
+            .raw_otxs
+            .iter()
+            .map(|pair| {
+                let entry = pair.value();
+                (entry.inserted_at, pair.key().clone(), entry.otx.clone())
+            })
+            .collect();
+        let total = otxs.len();
+        otxs.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+        let page = otxs
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .map(|(_, id, otx)| (id, otx))
+            .collect();
+        (page, total)
+    }
+
+    pub fn get_otxs_by_lock_script(&self, lock: &Script) -> Vec<(Id, OpenTransaction)> {
+        self.get_otxs_by_index(&self.lock_script_index, &script_hash(lock))
+    }
+
+    pub fn get_otxs_by_type_script(&self, type_script: &Script) -> Vec<(Id, OpenTransaction)> {
+        self.get_otxs_by_index(&self.type_script_index, &script_hash(type_script))
+    }
+
+    pub fn get_otxs_by_input_out_point(&self, out_point: &OutPoint) -> Vec<(Id, OpenTransaction)> {
+        self.get_otxs_by_index(&self.out_point_index, out_point)
+    }
+
+    /// Returns the residual otxs left by the partial fills of the parent.
+    pub fn get_otxs_by_parent_id(&self, parent_id: &Id) -> Vec<(Id, OpenTransaction)> {
+        self.get_otxs_by_index(&self.parent_index, parent_id)
+    }
+
+    /// Keep the residual template of a pooled otx which is being settled,
+    /// until the owner signs it or the parent would have expired. The
+    /// residual is accepted after the parent is gone only if it has a
+    /// template.
+    pub fn add_residual_template(&self, parent_id: &Id, otx: OpenTransaction) -> InnerResult<()> {
+        let (owner_lock, expire_at) = self
+            .raw_otxs
+            .get(parent_id)
+            .and_then(|pair| {
+                let entry = pair.value();
+                entry
+                    .input_locks
+                    .first()
+                    .map(|lock| (lock.clone(), entry.expire_at))
+            })
+            .ok_or_else(|| OtxPoolError::OtxNotFound(format!("{:#x}", parent_id)))?;
+        self.residual_templates.insert(
+            parent_id.clone(),
+            ResidualTemplate {
+                otx,
+                owner_lock,
+                expire_at,
+            },
+        );
+        Ok(())
+    }
+
+    /// Returns the unsigned residual otx for the owner to sign.
+    pub fn get_residual_template(&self, parent_id: &Id) -> Option<OpenTransaction> {
+        self.residual_templates
+            .get(parent_id)
+            .map(|pair| pair.value().otx.clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.raw_otxs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.raw_otxs.is_empty()
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes.load(Ordering::SeqCst)
+    }
+
+    pub fn uptime_secs(&self) -> u64 {
+        self.now().saturating_sub(self.started_at)
+    }
+
+    pub fn config(&self) -> &OtxPoolConfig {
+        &self.config
+    }
+
+    pub fn remove(&self, id: &Id) -> InnerResult<Option<OpenTransaction>> {
+        let _guard = self.update_lock.lock().expect("acquire lock");
+        self.remove_entry(id)
+    }
+
+    /// Remove an otx on behalf of its owner. The signature is a recoverable
+    /// secp256k1 signature over `delete_otx_message`, and the blake160 of the
+    /// recovered pubkey must match the lock args of one of the otx input
+    /// cells. The proof is accepted until `expire_at`, which may be at most
+    /// `MAX_DELETE_PROOF_SECS` ahead.
+    pub fn remove_with_proof(
+        &self,
+        id: &Id,
+        expire_at: u64,
+        signature: &[u8],
+    ) -> InnerResult<Option<OpenTransaction>> {
+        let now = self.now();
+        if expire_at <= now {
+            return Err(OtxPoolError::InvalidOwnershipProof(format!(
+                "proof expired at {}",
+                expire_at
+            ))
+            .into());
+        }
+        if expire_at > now + MAX_DELETE_PROOF_SECS {
+            return Err(OtxPoolError::InvalidOwnershipProof(format!(
+                "proof expires more than {} seconds ahead",
+                MAX_DELETE_PROOF_SECS
+            ))
+            .into());
+        }
+        let _guard = self.update_lock.lock().expect("acquire lock");
+        let input_locks = match self.raw_otxs.get(id) {
+            Some(pair) => pair.value().input_locks.clone(),
+            None => return Ok(None),
+        };
+        let pubkey_hash = recover_pubkey_hash(&delete_otx_message(id, expire_at), signature)?;
+        let is_owner = input_locks
+            .iter()
+            .any(|lock| is_lock_owned_by(&self.config, lock, &pubkey_hash));
+        if !is_owner {
+            return Err(OtxPoolError::InvalidOwnershipProof(
+                "signer does not own any input of the otx".to_string(),
+            )
+            .into());
+        }
+        self.remove_entry(id)
+    }
+
+    /// Re-check the inputs of all pooled otxs, and evict those which can no
+    /// longer be committed. Returns the number of evicted otxs.
+    pub fn evict_dead_otxs(&self) -> usize {
+        // all pooled inputs are checked in one batch
+        let out_points: Vec<OutPoint> = self
+            .out_point_index
+            .iter()
+            .map(|pair| pair.key().clone())
+            .collect();
+        let cells = match self.cell_provider.get_live_cells(&out_points) {
+            Ok(cells) => cells,
+            Err(err) => {
+                log::warn!("check otx inputs error: {}", err);
+                return 0;
+            }
+        };
+        let dead: HashSet<Id> = out_points
+            .iter()
+            .zip(cells)
+            .filter(|(_, cell)| cell.is_none())
+            .filter_map(|(out_point, _)| self.out_point_index.get(out_point))
+            .flat_map(|ids| ids.value().clone())
+            .collect();
+        let mut evicted = 0;
+        for id in dead {
+            match self.remove(&id) {
+                Ok(_) => evicted += 1,
+                Err(err) => log::warn!("evict otx {:#x} error: {}", id, err),
+            }
+        }
+        evicted
+    }
+
+    /// Evict the otxs whose expire timestamp has passed or whose expire block
+    /// number is reached. Returns the number of evicted otxs.
+    ///
+    /// The chain tip is queried if any otx expires at a block number, so this
+    /// should not run on an async worker.
+    pub fn evict_expired_otxs(&self) -> usize {
+        let now = self.now();
+        let has_block_expiry = self
+            .raw_otxs
+            .iter()
+            .any(|pair| pair.value().expire_block_number.is_some());
+        let tip_block_number = if has_block_expiry {
+            self.cell_provider
+                .get_tip_block_number()
+                .map_err(|err| log::warn!("get tip block number error: {}", err))
+                .ok()
+        } else {
+            None
+        };
+        let expired: Vec<Id> = self
+            .raw_otxs
+            .iter()
+            .filter(|pair| {
+                let entry = pair.value();
+                entry.expire_at <= now
+                    || matches!(
+                        (entry.expire_block_number, tip_block_number),
+                        (Some(expire), Some(tip)) if expire <= tip
+                    )
+            })
+            .map(|pair| pair.key().clone())
+            .collect();
+        self.residual_templates
+            .retain(|_, template| template.expire_at > now);
+        let mut evicted = 0;
+        for id in expired {
+            match self.remove(&id) {
+                Ok(_) => evicted += 1,
+                Err(err) => log::warn!("evict otx {:#x} error: {}", id, err),
+            }
+        }
+        evicted
+    }
+
+    fn prepare_entry(&self, otx: OpenTransaction) -> InnerResult<(Id, OtxEntry)> {
+        let expire_at = self.get_expire_at(&otx)?;
+        if expire_at <= self.now() {
+            return Err(OtxPoolError::OtxExpired(expire_at).into());
+        }
+        if let Some(expire_block_number) = otx.get_expire_block_number()? {
+            if expire_block_number <= self.cell_provider.get_tip_block_number()? {
+                return Err(OtxPoolError::OtxExpired(expire_block_number).into());
+            }
+        }
+        let tx_view = validate_otx(&otx)?;
+        let input_cells = resolve_inputs(self.cell_provider.as_ref(), &tx_view)?;
+        let balance = check_balance(&tx_view, &input_cells)?;
+        check_intent(&otx, &balance)?;
+        check_residual(&otx)?;
+        self.check_parent(&otx, &input_cells)?;
+        let id = otx.get_otx_hash();
+        let entry = OtxEntry::new(otx, &tx_view, &input_cells, expire_at, self.now());
+        Ok((id, entry))
+    }
+
+    /// The parent of a residual otx must be pooled, or settled with a residual
+    /// template, and the residual may spend the cells of the parent owner only.
+    fn check_parent(&self, otx: &OpenTransaction, input_cells: &[CellInfo]) -> InnerResult<()> {
+        let parent_id = match otx.get_parent_id()? {
+            Some(parent_id) => parent_id,
+            None => return Ok(()),
+        };
+        let owner_lock = self
+            .raw_otxs
+            .get(&parent_id)
+            .and_then(|pair| pair.value().input_locks.first().cloned())
+            .or_else(|| {
+                self.residual_templates
+                    .get(&parent_id)
+                    .map(|pair| pair.value().owner_lock.clone())
+            })
+            .ok_or_else(|| {
+                OtxPoolError::InvalidIntent(format!("parent otx {:#x} is unknown", parent_id))
+            })?;
+        if input_cells
+            .iter()
+            .any(|cell| cell.output.lock != owner_lock)
+        {
+            return Err(OtxPoolError::InvalidIntent(
+                "residual otx spends cells not owned by the parent owner".to_owned(),
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    fn get_expire_at(&self, otx: &OpenTransaction) -> InnerResult<u64> {
+        let expire_at = otx.get_expire_timestamp()?;
+        Ok(expire_at.unwrap_or_else(|| self.default_expire_at()))
+    }
+
+    fn default_expire_at(&self) -> u64 {
+        self.now().saturating_add(self.config.default_ttl_secs)
     }
+
+    /// Insert the entry, and remove the otx it is replacing if any. All checks
+    /// are done before any update, so a rejected entry leaves the pool as is.
+    fn insert_entry(&self, id: Id, entry: OtxEntry, replacing: Option<&Id>) -> InnerResult<()> {
+        let _guard = self.update_lock.lock().expect("acquire lock");
+        if self.raw_otxs.contains_key(&id) {
+            return Ok(());
+        }
+        if let Some(replacing) = replacing {
+            if !self.raw_otxs.contains_key(replacing) {
+                return Err(OtxPoolError::OtxNotFound(format!("{:#x}", replacing)).into());
+            }
+        }
+
+        let conflicts: Vec<Id> = self
+            .get_conflicts(&id, &entry.inputs)
+            .into_iter()
+            .filter(|conflict| Some(conflict) != replacing)
+            .collect();
+        let mut replaced = if conflicts.is_empty() {
+            vec![]
+        } else {
+            match self.config.conflict_policy {
+                ConflictPolicy::RejectNew => {
+                    return Err(OtxPoolError::OtxConflict(format!("{:#x}", conflicts[0])).into());
+                }
+                ConflictPolicy::ReplaceByFee => {
+                    // the conflicts are ordered by fee, the first pays the most
+                    let max_fee = self
+                        .raw_otxs
+                        .get(&conflicts[0])
+                        .map(|pair| pair.value().fee)
+                        .unwrap_or_default();
+                    if entry.fee <= max_fee {
+                        return Err(OtxPoolError::InsufficientReplaceFee(entry.fee, max_fee).into());
+                    }
+                    conflicts.clone()
+                }
+                ConflictPolicy::KeepBoth => vec![],
+            }
+        };
+        replaced.extend(replacing.cloned());
+        self.check_submitter_limit(&entry, &replaced)?;
+        let evicted = self.select_evictions(&entry, &replaced)?;
+        for id in replaced.iter().chain(evicted.iter()) {
+            self.remove_entry(id)?;
+        }
+
+        self.store.insert(&id, &entry.otx)?;
+        self.index_entry(&id, &entry);
+        // the residual of a settled parent is signed and pooled
+        if let Some(parent_id) = &entry.parent_id {
+            self.residual_templates.remove(parent_id);
+        }
+        let otx = entry.otx.clone();
+        self.raw_otxs.insert(id.clone(), entry);
+        self.notify_ctrl.notify_new_open_tx(otx);
+        if self.config.conflict_policy == ConflictPolicy::KeepBoth && !conflicts.is_empty() {
+            self.notify_ctrl.notify_conflict_open_tx((id, conflicts));
+        }
+        Ok(())
+    }
+
+    fn remove_entry(&self, id: &Id) -> InnerResult<Option<OpenTransaction>> {
+        self.store.remove(id)?;
+        let removed = self.raw_otxs.remove(id).map(|(_, entry)| entry);
+        if let Some(entry) = &removed {
+            self.unindex_entry(id, entry);
+            self.notify_ctrl.notify_delete_open_tx(entry.otx.clone());
+        }
+        Ok(removed.map(|entry| entry.otx))
+    }
+
+    fn index_entry(&self, id: &Id, entry: &OtxEntry) {
+        for out_point in &entry.inputs {
+            add_to_index(&self.out_point_index, out_point, id);
+        }
+        for lock_hash in &entry.lock_hashes {
+            add_to_index(&self.lock_script_index, lock_hash, id);
+        }
+        for type_hash in &entry.type_hashes {
+            add_to_index(&self.type_script_index, type_hash, id);
+        }
+        if let Some(parent_id) = &entry.parent_id {
+            add_to_index(&self.parent_index, parent_id, id);
+        }
+        if let Some(submitter) = &entry.submitter {
+            *self.submitter_counts.entry(submitter.clone()).or_default() += 1;
+        }
+        self.total_bytes.fetch_add(entry.size, Ordering::SeqCst);
+    }
+
+    fn unindex_entry(&self, id: &Id, entry: &OtxEntry) {
+        for out_point in &entry.inputs {
+            remove_from_index(&self.out_point_index, out_point, id);
+        }
+        for lock_hash in &entry.lock_hashes {
+            remove_from_index(&self.lock_script_index, lock_hash, id);
+        }
+        for type_hash in &entry.type_hashes {
+            remove_from_index(&self.type_script_index, type_hash, id);
+        }
+        if let Some(parent_id) = &entry.parent_id {
+            remove_from_index(&self.parent_index, parent_id, id);
+        }
+        if let Some(submitter) = &entry.submitter {
+            if let Entry::Occupied(mut count) = self.submitter_counts.entry(submitter.clone()) {
+                *count.get_mut() -= 1;
+                if *count.get() == 0 {
+                    count.remove();
+                }
+            }
+        }
+        self.total_bytes.fetch_sub(entry.size, Ordering::SeqCst);
+    }
+
+    fn get_otxs_by_index<K: Eq + Hash>(
+        &self,
+        index: &DashMap<K, HashSet<Id>>,
+        key: &K,
+    ) -> Vec<(Id, OpenTransaction)> {
+        let ids: Vec<Id> = index
+            .get(key)
+            .map(|ids| ids.iter().cloned().collect())
+            .unwrap_or_default();
+        ids.into_iter()
+            .filter_map(|id| {
+                let otx = self.raw_otxs.get(&id)?.otx.clone();
+                Some((id, otx))
+            })
+            .collect()
+    }
+
+    fn check_submitter_limit(&self, entry: &OtxEntry, replaced: &[Id]) -> InnerResult<()> {
+        let submitter = match &entry.submitter {
+            Some(submitter) => submitter,
+            None => return Ok(()),
+        };
+        let pooled = self
+            .submitter_counts
+            .get(submitter)
+            .map(|count| *count.value())
+            .unwrap_or_default();
+        let replaced = replaced
+            .iter()
+            .filter_map(|id| self.raw_otxs.get(id))
+            .filter(|pair| pair.value().submitter.as_ref() == Some(submitter))
+            .count();
+        let count = pooled - replaced;
+        if count >= self.config.max_otxs_per_submitter {
+            return Err(OtxPoolError::PoolFull(format!(
+                "submitter {:#x} already has {} otxs",
+                submitter, count
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Checks the entry fits in the pool without evicting any pooled otx.
+    fn check_limits(&self, entry: &OtxEntry) -> InnerResult<()> {
+        self.check_submitter_limit(entry, &[])?;
+        if self.raw_otxs.len() >= self.config.max_otxs
+            || self.total_bytes.load(Ordering::SeqCst) + entry.size > self.config.max_total_bytes
+        {
+            return Err(OtxPoolError::PoolFull("the pool is full".to_string()).into());
+        }
+        Ok(())
+    }
+
+    /// Select the pooled otxs to evict according to the eviction policy, so
+    /// that the new entry fits in the pool.
+    fn select_evictions(&self, entry: &OtxEntry, replaced: &[Id]) -> InnerResult<Vec<Id>> {
+        if entry.size > self.config.max_total_bytes {
+            return Err(OtxPoolError::PoolFull("otx is larger than the pool".to_string()).into());
+        }
+        let replaced_bytes: usize = replaced
+            .iter()
+            .filter_map(|id| self.raw_otxs.get(id).map(|pair| pair.value().size))
+            .sum();
+        let mut count = self.raw_otxs.len() - replaced.len();
+        let mut bytes = self.total_bytes.load(Ordering::SeqCst) - replaced_bytes;
+        let is_full = |count: usize, bytes: usize| {
+            count >= self.config.max_otxs || bytes + entry.size > self.config.max_total_bytes
+        };
+        if !is_full(count, bytes) {
+            return Ok(vec![]);
+        }
+
+        let mut candidates: Vec<(Id, u64, u64, usize)> = self
+            .raw_otxs
+            .iter()
+            .filter(|pair| !replaced.contains(pair.key()))
+            .map(|pair| {
+                let candidate = pair.value();
+                (
+                    pair.key().clone(),
+                    candidate.fee,
+                    candidate.inserted_at,
+                    candidate.size,
+                )
+            })
+            .collect();
+        match self.config.eviction_policy {
+            EvictionPolicy::OldestFirst => {
+                candidates.sort_by_key(|(_, _, inserted_at, _)| *inserted_at)
+            }
+            EvictionPolicy::LowestFeeFirst => {
+                candidates.sort_by_key(|(_, fee, inserted_at, _)| (*fee, *inserted_at))
+            }
+        }
+
+        let mut evictions = vec![];
+        let mut candidates = candidates.into_iter();
+        while is_full(count, bytes) {
+            let (id, fee, _, size) = candidates
+                .next()
+                .ok_or_else(|| OtxPoolError::PoolFull("no otx can be evicted".to_string()))?;
+            if self.config.eviction_policy == EvictionPolicy::LowestFeeFirst && fee >= entry.fee {
+                return Err(OtxPoolError::PoolFull(format!(
+                    "otx fee {} is not higher than the lowest pooled fee {}",
+                    entry.fee, fee
+                ))
+                .into());
+            }
+            count -= 1;
+            bytes -= size;
+            evictions.push(id);
+        }
+        Ok(evictions)
+    }
+
+    /// Returns the pooled otxs spending any of the inputs, ordered by fee
+    /// from high to low and then by id, so that the first one is the same
+    /// whatever the order of the index.
+    fn get_conflicts(&self, id: &Id, inputs: &[OutPoint]) -> Vec<Id> {
+        let conflicts: HashSet<Id> = inputs
+            .iter()
+            .filter_map(|out_point| self.out_point_index.get(out_point))
+            .flat_map(|ids| ids.value().clone())
+            .filter(|conflict| conflict != id)
+            .collect();
+        let mut conflicts: Vec<(u64, Id)> = conflicts
+            .into_iter()
+            .map(|id| {
+                let fee = self
+                    .raw_otxs
+                    .get(&id)
+                    .map(|pair| pair.value().fee)
+                    .unwrap_or_default();
+                (fee, id)
+            })
+            .collect();
+        conflicts.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        conflicts.into_iter().map(|(_, id)| id).collect()
+    }
+}
+
+fn add_to_index<K: Eq + Hash + Clone>(index: &DashMap<K, HashSet<Id>>, key: &K, id: &Id) {
+    index.entry(key.clone()).or_default().insert(id.clone());
+}
+
+fn remove_from_index<K: Eq + Hash + Clone>(index: &DashMap<K, HashSet<Id>>, key: &K, id: &Id) {
+    if let Entry::Occupied(mut ids) = index.entry(key.clone()) {
+        ids.get_mut().remove(id);
+        if ids.get().is_empty() {
+            ids.remove();
+        }
+    }
+}
+
+fn script_hash(script: &Script) -> H256 {
+    ckb_types::packed::Script::from(script.clone())
+        .calc_script_hash()
+        .unpack()
+}
+
+/// The message signed by the owner to delete an otx, the blake2b-256 hash of
+/// the domain tag, the otx id and the expire timestamp of the proof.
+pub fn delete_otx_message(id: &Id, expire_at: u64) -> H256 {
+    let mut message = DELETE_OTX_DOMAIN.to_vec();
+    message.extend_from_slice(id.as_bytes());
+    message.extend_from_slice(&expire_at.to_le_bytes());
+    H256(blake2b_256(message))
+}
+
+fn recover_pubkey_hash(message: &H256, signature: &[u8]) -> InnerResult<[u8; 20]> {
+    let signature = Signature::from_slice(signature)
+        .map_err(|err| OtxPoolError::InvalidOwnershipProof(err.to_string()))?;
+    let pubkey = signature
+        .recover(message)
+        .map_err(|err| OtxPoolError::InvalidOwnershipProof(err.to_string()))?;
+    let mut pubkey_hash = [0u8; 20];
+    pubkey_hash.copy_from_slice(&blake2b_256(pubkey.serialize())[0..20]);
+    Ok(pubkey_hash)
+}
+
+/// Accepts secp256k1 blake160 locks, whose args are the pubkey hash, and
+/// omnilocks with pubkey hash auth, whose args are the auth flag followed by
+/// the pubkey hash.
+fn is_lock_owned_by(config: &OtxPoolConfig, lock: &Script, pubkey_hash: &[u8; 20]) -> bool {
+    if lock.hash_type != ScriptHashType::Type {
+        return false;
+    }
+    let args = lock.args.as_bytes();
+    if lock.code_hash == config.secp256k1_blake160_code_hash {
+        args == pubkey_hash
+    } else if lock.code_hash == config.omnilock_code_hash {
+        args.len() >= 21 && args[0] == 0 && &args[1..21] == pubkey_hash
+    } else {
+        false
+    }
+}
+
+/// The current unix time in seconds.
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
 }
 
 fn parse_otx(otx: JsonBytes) -> InnerResult<OpenTransaction> {
@@ -0,0 +1,563 @@
+use super::cell_provider::MockCellProvider;
+use super::*;
+use crate::notify::NotifyService;
+use crate::store::MemoryStore;
+
+use otx_format::error::OtxError;
+use otx_format::jsonrpc_types::tx_view::tx_view_to_otx;
+use otx_format::jsonrpc_types::{IntentAsset, OtxIntent};
+
+use ckb_async_runtime::new_background_runtime;
+use ckb_crypto::secp::Privkey;
+use ckb_jsonrpc_types::CellOutput;
+use ckb_types::{
+    core::TransactionBuilder,
+    h256,
+    packed::{self as core_packed, CellInput},
+    prelude::*,
+};
+
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+
+const CKB: u64 = 100_000_000;
+
+/// Shares the mock cells between the pool and the test.
+struct SharedCellProvider(Arc<MockCellProvider>);
+
+impl CellProvider for SharedCellProvider {
+    fn get_live_cell(&self, out_point: &OutPoint) -> InnerResult<Option<CellInfo>> {
+        self.0.get_live_cell(out_point)
+    }
+
+    fn get_tip_block_number(&self) -> InnerResult<u64> {
+        self.0.get_tip_block_number()
+    }
+}
+
+fn new_pool(config: OtxPoolConfig) -> (OtxPool, Arc<MockCellProvider>) {
+    let cells = Arc::new(MockCellProvider::new());
+    let pool = new_pool_with(config, MemoryStore::new(), cells.clone());
+    (pool, cells)
+}
+
+fn new_pool_with(
+    config: OtxPoolConfig,
+    store: MemoryStore,
+    cells: Arc<MockCellProvider>,
+) -> OtxPool {
+    let notify_ctrl = NotifyService::new().start(new_background_runtime());
+    OtxPool::new(
+        notify_ctrl,
+        Box::new(store),
+        Box::new(SharedCellProvider(cells)),
+        config,
+    )
+}
+
+/// A clock moved by hand, starting at `NOW`.
+#[derive(Clone)]
+struct TestClock(Arc<AtomicU64>);
+
+const NOW: u64 = 1_700_000_000;
+
+impl TestClock {
+    fn new() -> Self {
+        TestClock(Arc::new(AtomicU64::new(NOW)))
+    }
+
+    fn install(&self, pool: OtxPool) -> OtxPool {
+        let now = self.0.clone();
+        pool.with_clock(move || now.load(Ordering::SeqCst))
+    }
+
+    fn advance(&self, secs: u64) {
+        self.0.fetch_add(secs, Ordering::SeqCst);
+    }
+}
+
+fn pool_with_policy(conflict_policy: ConflictPolicy) -> (OtxPool, Arc<MockCellProvider>) {
+    new_pool(OtxPoolConfig {
+        conflict_policy,
+        ..Default::default()
+    })
+}
+
+fn out_point(index: u32) -> OutPoint {
+    OutPoint {
+        tx_hash: h256!("0x1"),
+        index: index.into(),
+    }
+}
+
+fn lock(owner: u8) -> Script {
+    Script {
+        args: JsonBytes::from_vec(vec![owner; 20]),
+        ..Default::default()
+    }
+}
+
+/// Add a live cell of 200 CKB, the lock args tell the owners apart.
+fn add_cell(cells: &MockCellProvider, out_point: &OutPoint, owner: u8) {
+    add_locked_cell(cells, out_point, lock(owner));
+}
+
+fn add_locked_cell(cells: &MockCellProvider, out_point: &OutPoint, lock: Script) {
+    let cell = CellInfo {
+        output: CellOutput {
+            capacity: (200 * CKB).into(),
+            lock,
+            type_: None,
+        },
+        data: None,
+    };
+    cells.add_cell(out_point.clone(), cell);
+}
+
+/// An otx spending the 200 CKB cells, which leaves `fee` to the aggregator.
+fn build_otx(inputs: &[OutPoint], fee: u64) -> OpenTransaction {
+    let output_capacity = 200 * CKB * inputs.len() as u64 - fee;
+    let tx = TransactionBuilder::default()
+        .inputs(
+            inputs
+                .iter()
+                .map(|out_point| CellInput::new(out_point.clone().into(), 0)),
+        )
+        .witnesses(inputs.iter().map(|_| Default::default()))
+        .output(
+            core_packed::CellOutput::new_builder()
+                .capacity(output_capacity.pack())
+                .build(),
+        )
+        .output_data(Default::default())
+        .build();
+    tx_view_to_otx(tx.into()).unwrap()
+}
+
+fn assert_error(result: InnerResult<Id>, expected: OtxPoolError) {
+    let err = result.unwrap_err();
+    assert_eq!(err.0.err_code(), expected.err_code());
+    assert_eq!(err.0.message(), expected.message());
+}
+
+fn assert_pool_full(result: InnerResult<Id>) {
+    let err = result.unwrap_err();
+    assert_eq!(
+        err.0.err_code(),
+        OtxPoolError::PoolFull(String::new()).err_code()
+    );
+}
+
+#[test]
+fn test_conflict_reject_new() {
+    let (pool, cells) = pool_with_policy(ConflictPolicy::RejectNew);
+    add_cell(&cells, &out_point(0), 0);
+    add_cell(&cells, &out_point(1), 1);
+
+    let low = pool.insert_otx(build_otx(&[out_point(0)], CKB)).unwrap();
+    let high = pool
+        .insert_otx(build_otx(&[out_point(1)], 2 * CKB))
+        .unwrap();
+    assert_error(
+        pool.insert_otx(build_otx(&[out_point(0)], 3 * CKB)),
+        OtxPoolError::OtxConflict(format!("{:#x}", low)),
+    );
+
+    // the reported conflict is the one paying the most fee
+    assert_error(
+        pool.insert_otx(build_otx(&[out_point(0), out_point(1)], 3 * CKB)),
+        OtxPoolError::OtxConflict(format!("{:#x}", high)),
+    );
+    assert_eq!(pool.len(), 2);
+}
+
+#[test]
+fn test_conflict_replace_by_fee() {
+    let (pool, cells) = pool_with_policy(ConflictPolicy::ReplaceByFee);
+    add_cell(&cells, &out_point(0), 0);
+    add_cell(&cells, &out_point(1), 1);
+
+    let low = pool.insert_otx(build_otx(&[out_point(0)], CKB)).unwrap();
+    let high = pool
+        .insert_otx(build_otx(&[out_point(1)], 2 * CKB))
+        .unwrap();
+    assert_error(
+        pool.insert_otx(build_otx(&[out_point(0), out_point(1)], 2 * CKB)),
+        OtxPoolError::InsufficientReplaceFee(2 * CKB, 2 * CKB),
+    );
+
+    let id = pool
+        .insert_otx(build_otx(&[out_point(0), out_point(1)], 3 * CKB))
+        .unwrap();
+    assert_eq!(pool.len(), 1);
+    assert!(pool.get_otx_by_id(low).is_none());
+    assert!(pool.get_otx_by_id(high).is_none());
+    assert!(pool.get_otx_by_id(id).is_some());
+}
+
+#[test]
+fn test_conflict_keep_both() {
+    let (pool, cells) = pool_with_policy(ConflictPolicy::KeepBoth);
+    add_cell(&cells, &out_point(0), 0);
+    add_cell(&cells, &out_point(1), 1);
+
+    let low = pool.insert_otx(build_otx(&[out_point(0)], CKB)).unwrap();
+    let high = pool
+        .insert_otx(build_otx(&[out_point(1)], 2 * CKB))
+        .unwrap();
+    let id = pool
+        .insert_otx(build_otx(&[out_point(0), out_point(1)], 3 * CKB))
+        .unwrap();
+    assert_eq!(pool.len(), 3);
+    assert_eq!(pool.get_conflicts_by_id(&id).unwrap(), vec![high, low]);
+}
+
+#[test]
+fn test_evict_expired_otxs() {
+    let (pool, cells) = new_pool(OtxPoolConfig::default());
+    let clock = TestClock::new();
+    let pool = clock.install(pool);
+    for index in 0..3 {
+        add_cell(&cells, &out_point(index), index as u8);
+    }
+    cells.set_tip_block_number(100);
+
+    let past = NOW - 1;
+    let mut expired = build_otx(&[out_point(0)], CKB);
+    expired.set_expire_timestamp(past);
+    assert_error(pool.insert_otx(expired), OtxPoolError::OtxExpired(past));
+    let mut expired = build_otx(&[out_point(0)], CKB);
+    expired.set_expire_block_number(100);
+    assert_error(pool.insert_otx(expired), OtxPoolError::OtxExpired(100));
+
+    let mut by_timestamp = build_otx(&[out_point(0)], CKB);
+    by_timestamp.set_expire_timestamp(NOW + 2);
+    let by_timestamp = pool.insert_otx(by_timestamp).unwrap();
+    let mut by_block_number = build_otx(&[out_point(1)], CKB);
+    by_block_number.set_expire_block_number(101);
+    let by_block_number = pool.insert_otx(by_block_number).unwrap();
+    let by_ttl = pool.insert_otx(build_otx(&[out_point(2)], CKB)).unwrap();
+    assert_eq!(pool.evict_expired_otxs(), 0);
+
+    cells.set_tip_block_number(101);
+    assert_eq!(pool.evict_expired_otxs(), 1);
+    assert!(pool.get_otx_by_id(by_block_number).is_none());
+
+    clock.advance(1);
+    assert_eq!(pool.evict_expired_otxs(), 0);
+    clock.advance(1);
+    assert_eq!(pool.evict_expired_otxs(), 1);
+    assert!(pool.get_otx_by_id(by_timestamp).is_none());
+    assert!(pool.get_otx_by_id(by_ttl).is_some());
+
+    clock.advance(OtxPoolConfig::default().default_ttl_secs);
+    assert_eq!(pool.evict_expired_otxs(), 1);
+    assert!(pool.get_otx_by_id(by_ttl).is_none());
+}
+
+#[test]
+fn test_submitter_limit() {
+    let (pool, cells) = new_pool(OtxPoolConfig {
+        max_otxs_per_submitter: 1,
+        ..Default::default()
+    });
+    add_cell(&cells, &out_point(0), 0);
+    add_cell(&cells, &out_point(1), 0);
+    add_cell(&cells, &out_point(2), 1);
+
+    let first = pool.insert_otx(build_otx(&[out_point(0)], CKB)).unwrap();
+    assert_pool_full(pool.insert_otx(build_otx(&[out_point(1)], CKB)));
+    pool.insert_otx(build_otx(&[out_point(2)], CKB)).unwrap();
+
+    pool.remove(&first).unwrap();
+    pool.insert_otx(build_otx(&[out_point(1)], CKB)).unwrap();
+    assert_eq!(pool.len(), 2);
+}
+
+#[test]
+fn test_evict_lowest_fee_first() {
+    let (pool, cells) = new_pool(OtxPoolConfig {
+        max_otxs: 2,
+        eviction_policy: EvictionPolicy::LowestFeeFirst,
+        ..Default::default()
+    });
+    for index in 0..4 {
+        add_cell(&cells, &out_point(index), index as u8);
+    }
+
+    let low = pool.insert_otx(build_otx(&[out_point(0)], CKB)).unwrap();
+    let high = pool
+        .insert_otx(build_otx(&[out_point(1)], 3 * CKB))
+        .unwrap();
+    let middle = pool
+        .insert_otx(build_otx(&[out_point(2)], 2 * CKB))
+        .unwrap();
+    assert_eq!(pool.len(), 2);
+    assert!(pool.get_otx_by_id(low).is_none());
+
+    // not paying more than the lowest pooled fee
+    assert_pool_full(pool.insert_otx(build_otx(&[out_point(3)], 2 * CKB)));
+    assert!(pool.get_otx_by_id(high).is_some());
+    assert!(pool.get_otx_by_id(middle).is_some());
+}
+
+#[test]
+fn test_evict_oldest_first() {
+    let (pool, cells) = new_pool(OtxPoolConfig {
+        max_otxs: 2,
+        eviction_policy: EvictionPolicy::OldestFirst,
+        ..Default::default()
+    });
+    let clock = TestClock::new();
+    let pool = clock.install(pool);
+    for index in 0..3 {
+        add_cell(&cells, &out_point(index), index as u8);
+    }
+
+    let oldest = pool
+        .insert_otx(build_otx(&[out_point(0)], 3 * CKB))
+        .unwrap();
+    // the insertion time is in seconds
+    clock.advance(1);
+    let older = pool.insert_otx(build_otx(&[out_point(1)], CKB)).unwrap();
+    let new = pool.insert_otx(build_otx(&[out_point(2)], CKB)).unwrap();
+    assert_eq!(pool.len(), 2);
+    assert!(pool.get_otx_by_id(oldest).is_none());
+    assert!(pool.get_otx_by_id(older).is_some());
+    assert!(pool.get_otx_by_id(new).is_some());
+}
+
+#[test]
+fn test_recover_within_limits() {
+    let cells = Arc::new(MockCellProvider::new());
+    let store = MemoryStore::new();
+    for index in 0..3 {
+        add_cell(&cells, &out_point(index), 0);
+        let otx = build_otx(&[out_point(index)], CKB);
+        store.insert(&otx.get_otx_hash(), &otx).unwrap();
+    }
+
+    let pool = new_pool_with(
+        OtxPoolConfig {
+            max_otxs_per_submitter: 2,
+            ..Default::default()
+        },
+        store,
+        cells,
+    );
+    assert_eq!(pool.recover().unwrap(), 2);
+    assert_eq!(pool.len(), 2);
+}
+
+/// Fails every query, as if the ckb node were unreachable.
+struct FailingCellProvider;
+
+impl CellProvider for FailingCellProvider {
+    fn get_live_cell(&self, _out_point: &OutPoint) -> InnerResult<Option<CellInfo>> {
+        Err(OtxPoolError::CkbRpcError(String::from("connection refused")).into())
+    }
+
+    fn get_tip_block_number(&self) -> InnerResult<u64> {
+        Err(OtxPoolError::CkbRpcError(String::from("connection refused")).into())
+    }
+}
+
+#[test]
+fn test_recover_dead_inputs() {
+    let store = MemoryStore::new();
+    let otx = build_otx(&[out_point(0)], CKB);
+    store.insert(&otx.get_otx_hash(), &otx).unwrap();
+
+    // the input is dead, the otx is kept until the next eviction
+    let pool = new_pool_with(
+        OtxPoolConfig::default(),
+        store,
+        Arc::new(MockCellProvider::new()),
+    );
+    assert_eq!(pool.recover().unwrap(), 1);
+    assert_eq!(pool.len(), 1);
+}
+
+#[test]
+fn test_recover_rpc_error() {
+    let store = MemoryStore::new();
+    let otx = build_otx(&[out_point(0)], CKB);
+    store.insert(&otx.get_otx_hash(), &otx).unwrap();
+
+    let notify_ctrl = NotifyService::new().start(new_background_runtime());
+    let pool = OtxPool::new(
+        notify_ctrl,
+        Box::new(store),
+        Box::new(FailingCellProvider),
+        OtxPoolConfig::default(),
+    );
+    let err = pool.recover().unwrap_err();
+    assert_eq!(
+        err.0.err_code(),
+        OtxPoolError::CkbRpcError(String::new()).err_code()
+    );
+    assert_eq!(pool.len(), 0);
+}
+
+fn owner_lock(code_hash: &H256, args: Vec<u8>) -> Script {
+    Script {
+        code_hash: code_hash.clone(),
+        hash_type: ScriptHashType::Type,
+        args: JsonBytes::from_vec(args),
+    }
+}
+
+fn sign_delete(privkey: &Privkey, id: &Id, expire_at: u64) -> Vec<u8> {
+    privkey
+        .sign_recoverable(&delete_otx_message(id, expire_at))
+        .unwrap()
+        .serialize()
+}
+
+#[test]
+fn test_remove_with_proof() {
+    let (pool, cells) = new_pool(OtxPoolConfig::default());
+    let config = OtxPoolConfig::default();
+    let owner = Privkey::from_slice(&[1u8; 32]);
+    let other = Privkey::from_slice(&[2u8; 32]);
+    let pubkey_hash = blake2b_256(owner.pubkey().unwrap().serialize())[0..20].to_vec();
+    let mut omnilock_args = vec![0u8];
+    omnilock_args.extend_from_slice(&pubkey_hash);
+    omnilock_args.push(0);
+    add_locked_cell(
+        &cells,
+        &out_point(0),
+        owner_lock(&config.secp256k1_blake160_code_hash, pubkey_hash.clone()),
+    );
+    add_locked_cell(
+        &cells,
+        &out_point(1),
+        owner_lock(&config.omnilock_code_hash, omnilock_args),
+    );
+    // the same args under another lock do not prove the ownership
+    add_locked_cell(
+        &cells,
+        &out_point(2),
+        owner_lock(&H256::default(), pubkey_hash),
+    );
+    let secp = pool.insert_otx(build_otx(&[out_point(0)], CKB)).unwrap();
+    let omnilock = pool.insert_otx(build_otx(&[out_point(1)], CKB)).unwrap();
+    let unknown = pool.insert_otx(build_otx(&[out_point(2)], CKB)).unwrap();
+
+    let expire_at = unix_timestamp() + 60;
+    let invalid_proof = OtxPoolError::InvalidOwnershipProof(String::new()).err_code();
+    let remove = |id: &Id, expire_at: u64, signature: &[u8]| {
+        pool.remove_with_proof(id, expire_at, signature)
+            .map_err(|err| err.0.err_code())
+    };
+    assert_eq!(
+        remove(&secp, expire_at, &sign_delete(&other, &secp, expire_at)).unwrap_err(),
+        invalid_proof
+    );
+    // a signature over another message
+    assert_eq!(
+        remove(&secp, expire_at, &sign_delete(&owner, &secp, expire_at + 1)).unwrap_err(),
+        invalid_proof
+    );
+    let expired = unix_timestamp() - 1;
+    assert_eq!(
+        remove(&secp, expired, &sign_delete(&owner, &secp, expired)).unwrap_err(),
+        invalid_proof
+    );
+    let too_late = unix_timestamp() + MAX_DELETE_PROOF_SECS + 60;
+    assert_eq!(
+        remove(&secp, too_late, &sign_delete(&owner, &secp, too_late)).unwrap_err(),
+        invalid_proof
+    );
+    assert_eq!(
+        remove(
+            &unknown,
+            expire_at,
+            &sign_delete(&owner, &unknown, expire_at)
+        )
+        .unwrap_err(),
+        invalid_proof
+    );
+
+    assert!(
+        remove(&secp, expire_at, &sign_delete(&owner, &secp, expire_at))
+            .unwrap()
+            .is_some()
+    );
+    assert!(remove(
+        &omnilock,
+        expire_at,
+        &sign_delete(&owner, &omnilock, expire_at)
+    )
+    .unwrap()
+    .is_some());
+    assert_eq!(pool.len(), 1);
+}
+
+/// A residual of the parent spending a 200 CKB cell, which gives away 1 CKB
+/// for 10 udt.
+fn build_residual(input: &OutPoint, parent_id: &Id) -> OpenTransaction {
+    let udt = core_packed::Script::new_builder()
+        .args(vec![9u8; 20].pack())
+        .build();
+    let tx = TransactionBuilder::default()
+        .input(CellInput::new(input.clone().into(), 0))
+        .witness(Default::default())
+        .output(
+            core_packed::CellOutput::new_builder()
+                .capacity((100 * CKB).pack())
+                .build(),
+        )
+        .output_data(Default::default())
+        .output(
+            core_packed::CellOutput::new_builder()
+                .capacity((99 * CKB).pack())
+                .type_(Some(udt.clone()).pack())
+                .build(),
+        )
+        .output_data(10u128.to_le_bytes().to_vec().pack())
+        .build();
+    let mut otx = tx_view_to_otx(tx.into()).unwrap();
+    let intent = OtxIntent::new(
+        IntentAsset::Ckb,
+        CKB as u128,
+        IntentAsset::Udt(udt.into()),
+        10,
+    )
+    .with_partial_fill(true);
+    otx.set_intent(&intent);
+    otx.set_parent_id(parent_id);
+    otx
+}
+
+#[test]
+fn test_residual_parent() {
+    let (pool, cells) = new_pool(OtxPoolConfig::default());
+    add_cell(&cells, &out_point(0), 0);
+    add_cell(&cells, &out_point(1), 1);
+    add_cell(&cells, &out_point(2), 0);
+    let parent = pool.insert_otx(build_otx(&[out_point(0)], CKB)).unwrap();
+
+    let unknown = h256!("0x2");
+    assert_error(
+        pool.insert_otx(build_residual(&out_point(2), &unknown)),
+        OtxPoolError::InvalidIntent(format!("parent otx {:#x} is unknown", unknown)),
+    );
+    assert_error(
+        pool.insert_otx(build_residual(&out_point(1), &parent)),
+        OtxPoolError::InvalidIntent(
+            "residual otx spends cells not owned by the parent owner".to_owned(),
+        ),
+    );
+
+    // the parent is settled, its residual is signed by the owner later
+    let template = build_residual(&out_point(2), &parent);
+    pool.add_residual_template(&parent, template.clone())
+        .unwrap();
+    pool.remove(&parent).unwrap();
+    assert_eq!(pool.get_residual_template(&parent), Some(template.clone()));
+    let residual = pool.insert_otx(template).unwrap();
+    assert_eq!(pool.get_otxs_by_parent_id(&parent).len(), 1);
+    assert_eq!(pool.get_otxs_by_parent_id(&parent)[0].0, residual);
+    assert!(pool.get_residual_template(&parent).is_none());
+}
@@ -0,0 +1,490 @@
+use crate::error::OtxPoolError;
+
+use otx_format::jsonrpc_types::constant::basic_keys::{
+    OTX_CELL_DEP_OUTPOINT_INDEX, OTX_CELL_DEP_OUTPOINT_TX_HASH, OTX_CELL_DEP_TYPE,
+    OTX_HEADER_DEP_HASH, OTX_INPUT_OUTPOINT_INDEX, OTX_INPUT_OUTPOINT_TX_HASH, OTX_INPUT_SINCE,
+    OTX_META_EXPIRE_BLOCK_NUMBER, OTX_META_EXPIRE_TIMESTAMP, OTX_META_VERSION, OTX_OUTPUT_CAPACITY,
+    OTX_OUTPUT_DATA, OTX_OUTPUT_LOCK_ARGS, OTX_OUTPUT_LOCK_CODE_HASH, OTX_OUTPUT_LOCK_HASH_TYPE,
+    OTX_OUTPUT_TYPE_ARGS, OTX_OUTPUT_TYPE_CODE_HASH, OTX_OUTPUT_TYPE_HASH_TYPE, OTX_WITNESS_ARGS,
+    OTX_WITNESS_RAW,
+};
+use otx_format::jsonrpc_types::constant::extra_keys::OTX_VERSIONING_META_OPEN_TX_VERSION;
+use otx_format::jsonrpc_types::{tx_view::otx_to_tx_view, OpenTransaction, OtxMap};
+
+use ckb_jsonrpc_types::{CellInfo, Script, TransactionView};
+use ckb_types::core::Capacity;
+use ckb_types::packed;
+
+use std::collections::HashMap;
+
+const META_KEYS: &[u32] = &[
+    OTX_META_VERSION,
+    OTX_META_EXPIRE_TIMESTAMP,
+    OTX_META_EXPIRE_BLOCK_NUMBER,
+];
+const CELL_DEP_KEYS: &[u32] = &[
+    OTX_CELL_DEP_OUTPOINT_TX_HASH,
+    OTX_CELL_DEP_OUTPOINT_INDEX,
+    OTX_CELL_DEP_TYPE,
+];
+const HEADER_DEP_KEYS: &[u32] = &[OTX_HEADER_DEP_HASH];
+const INPUT_KEYS: &[u32] = &[
+    OTX_INPUT_OUTPOINT_TX_HASH,
+    OTX_INPUT_OUTPOINT_INDEX,
+    OTX_INPUT_SINCE,
+];
+const WITNESS_KEYS: &[u32] = &[OTX_WITNESS_RAW, OTX_WITNESS_ARGS];
+const OUTPUT_KEYS: &[u32] = &[
+    OTX_OUTPUT_CAPACITY,
+    OTX_OUTPUT_LOCK_CODE_HASH,
+    OTX_OUTPUT_LOCK_HASH_TYPE,
+    OTX_OUTPUT_LOCK_ARGS,
+    OTX_OUTPUT_TYPE_CODE_HASH,
+    OTX_OUTPUT_TYPE_HASH_TYPE,
+    OTX_OUTPUT_TYPE_ARGS,
+    OTX_OUTPUT_DATA,
+];
+
+/// Checks an otx before it is accepted by the pool, the checks run in order
+/// and the first failure is returned. The balance is checked by
+/// `check_balance` once the input cells are resolved.
+pub fn validate_otx(otx: &OpenTransaction) -> Result<TransactionView, OtxPoolError> {
+    check_key_types(otx)?;
+    check_inputs(otx)?;
+    let tx_view = otx_to_tx_view(otx.to_owned())
+        .map_err(|err| OtxPoolError::ConvertToTxFailed(err.to_string()))?;
+    check_outputs_capacity(&tx_view)?;
+    Ok(tx_view)
+}
+
+fn check_key_types(otx: &OpenTransaction) -> Result<(), OtxPoolError> {
+    // extra keys are only allowed in the meta map
+    otx.meta
+        .iter()
+        .map(|pair| pair.key_type())
+        .filter(|key_type| *key_type < OTX_VERSIONING_META_OPEN_TX_VERSION)
+        .try_for_each(|key_type| check_key_type("meta", META_KEYS, key_type))?;
+
+    let maps = [
+        ("cell dep", CELL_DEP_KEYS, &otx.cell_deps),
+        ("header dep", HEADER_DEP_KEYS, &otx.header_deps),
+        ("input", INPUT_KEYS, &otx.inputs),
+        ("witness", WITNESS_KEYS, &otx.witnesses),
+        ("output", OUTPUT_KEYS, &otx.outputs),
+    ];
+    for (name, keys, map_vec) in maps {
+        map_vec
+            .iter()
+            .flat_map(OtxMap::iter)
+            .try_for_each(|pair| check_key_type(name, keys, pair.key_type()))?;
+    }
+    Ok(())
+}
+
+fn check_key_type(map_name: &str, legal_keys: &[u32], key_type: u32) -> Result<(), OtxPoolError> {
+    if legal_keys.contains(&key_type) {
+        Ok(())
+    } else {
+        Err(OtxPoolError::IllegalKeyType(map_name.to_owned(), key_type))
+    }
+}
+
+fn check_inputs(otx: &OpenTransaction) -> Result<(), OtxPoolError> {
+    if otx.inputs.is_empty() {
+        return Err(OtxPoolError::NoInputs);
+    }
+    if otx.witnesses.len() < otx.inputs.len() {
+        return Err(OtxPoolError::WitnessCountMismatch(
+            otx.inputs.len(),
+            otx.witnesses.len(),
+        ));
+    }
+    Ok(())
+}
+
+fn check_outputs_capacity(tx_view: &TransactionView) -> Result<(), OtxPoolError> {
+    let outputs = tx_view
+        .inner
+        .outputs
+        .iter()
+        .zip(tx_view.inner.outputs_data.iter());
+    for (index, (output, data)) in outputs.enumerate() {
+        if output.capacity.value() == 0 {
+            return Err(OtxPoolError::ZeroCapacityOutput(index));
+        }
+        let capacity = Capacity::shannons(output.capacity.value());
+        let output: packed::CellOutput = output.to_owned().into();
+        let occupied = Capacity::bytes(data.len())
+            .and_then(|data_capacity| output.occupied_capacity(data_capacity))
+            .map_err(|_| OtxPoolError::InsufficientOutputCapacity(index))?;
+        if occupied > capacity {
+            return Err(OtxPoolError::InsufficientOutputCapacity(index));
+        }
+    }
+    Ok(())
+}
+
+/// The assets an otx gives away (positive) and asks for (negative). The
+/// capacity is keyed by `None`, and the udts by their type scripts.
+pub type AssetBalance = HashMap<Option<Script>, i128>;
+
+/// An otx must give away some of its assets, either capacity or udt, in
+/// exchange for what it asks. Returns the balance for the later checks.
+pub fn check_balance(
+    tx_view: &TransactionView,
+    input_cells: &[CellInfo],
+) -> Result<AssetBalance, OtxPoolError> {
+    let balance = asset_balance(tx_view, input_cells);
+    if balance.values().any(|amount| *amount > 0) {
+        Ok(balance)
+    } else {
+        Err(OtxPoolError::OtxOffersNothing)
+    }
+}
+
+/// The intent declared in the meta map must be well formed, must not offer
+/// more than the otx gives away, and the outputs must ask for the requested
+/// asset, at least `min_receive` and at most `requested_amount` of it.
+pub fn check_intent(otx: &OpenTransaction, balance: &AssetBalance) -> Result<(), OtxPoolError> {
+    let intent = match otx
+        .get_intent()
+        .map_err(|err| OtxPoolError::InvalidIntent(err.to_string()))?
+    {
+        Some(intent) => intent,
+        None => return Ok(()),
+    };
+    if intent.offered_asset == intent.requested_asset {
+        return Err(OtxPoolError::InvalidIntent(
+            "offered and requested assets are the same".to_owned(),
+        ));
+    }
+    if intent.offered_amount == 0 || intent.requested_amount == 0 {
+        return Err(OtxPoolError::InvalidIntent(
+            "offered and requested amounts must be positive".to_owned(),
+        ));
+    }
+    if intent.get_min_receive() > intent.requested_amount {
+        return Err(OtxPoolError::InvalidIntent(
+            "min receive exceeds the requested amount".to_owned(),
+        ));
+    }
+    let given = balance
+        .get(&intent.offered_asset.type_script().cloned())
+        .copied()
+        .unwrap_or_default();
+    if given < i128::try_from(intent.offered_amount).unwrap_or(i128::MAX) {
+        return Err(OtxPoolError::InvalidIntent(format!(
+            "offers {} but gives away {}",
+            intent.offered_amount, given
+        )));
+    }
+    let asked = balance
+        .get(&intent.requested_asset.type_script().cloned())
+        .copied()
+        .unwrap_or_default()
+        .saturating_neg();
+    let min_receive = i128::try_from(intent.get_min_receive()).unwrap_or(i128::MAX);
+    let requested = i128::try_from(intent.requested_amount).unwrap_or(i128::MAX);
+    if asked < min_receive || asked > requested {
+        return Err(OtxPoolError::InvalidIntent(format!(
+            "requests {} but asks for {}",
+            intent.requested_amount, asked
+        )));
+    }
+    Ok(())
+}
+
+/// A residual otx is the rest of a divisible order, so it must declare an
+/// intent allowing partial fills.
+pub fn check_residual(otx: &OpenTransaction) -> Result<(), OtxPoolError> {
+    let parent_id = otx
+        .get_parent_id()
+        .map_err(|err| OtxPoolError::InvalidIntent(err.to_string()))?;
+    if parent_id.is_none() {
+        return Ok(());
+    }
+    match otx.get_intent() {
+        Ok(Some(intent)) if intent.allow_partial_fill => Ok(()),
+        _ => Err(OtxPoolError::InvalidIntent(
+            "residual otx must declare a divisible intent".to_owned(),
+        )),
+    }
+}
+
+/// Udt amounts are read from the first 16 bytes of the data of cells with a
+/// type script.
+pub fn asset_balance(tx_view: &TransactionView, input_cells: &[CellInfo]) -> AssetBalance {
+    let mut balance = AssetBalance::new();
+    for cell in input_cells {
+        let data = cell.data.as_ref().map(|data| data.content.as_bytes());
+        add_asset(&mut balance, &cell.output, data.unwrap_or_default(), 1);
+    }
+    let outputs = tx_view
+        .inner
+        .outputs
+        .iter()
+        .zip(tx_view.inner.outputs_data.iter());
+    for (output, data) in outputs {
+        add_asset(&mut balance, output, data.as_bytes(), -1);
+    }
+    balance
+}
+
+fn add_asset(
+    balance: &mut AssetBalance,
+    cell: &ckb_jsonrpc_types::CellOutput,
+    data: &[u8],
+    sign: i128,
+) {
+    let capacity = balance.entry(None).or_default();
+    *capacity = capacity.saturating_add(sign * cell.capacity.value() as i128);
+    if let (Some(type_), Some(amount)) = (&cell.type_, data.get(0..16)) {
+        let amount = u128::from_le_bytes(amount.try_into().expect("16 bytes"));
+        let amount = i128::try_from(amount).unwrap_or(i128::MAX);
+        let udt = balance.entry(Some(type_.to_owned())).or_default();
+        *udt = udt.saturating_add(sign * amount);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use otx_format::jsonrpc_types::{tx_view::tx_view_to_otx, IntentAsset, OtxIntent, OtxKeyPair};
+
+    use ckb_jsonrpc_types::{CellOutput, JsonBytes};
+    use ckb_types::core::{self, TransactionBuilder};
+    use ckb_types::prelude::*;
+
+    const CKB: u64 = 100_000_000;
+
+    fn tx_builder(output_capacity: u64) -> core::TransactionBuilder {
+        TransactionBuilder::default()
+            .input(packed::CellInput::new(packed::OutPoint::default(), 0))
+            .witness(Default::default())
+            .output(
+                packed::CellOutput::new_builder()
+                    .capacity(output_capacity.pack())
+                    .build(),
+            )
+            .output_data(Default::default())
+    }
+
+    fn to_otx(tx: core::TransactionView) -> OpenTransaction {
+        tx_view_to_otx(tx.into()).unwrap()
+    }
+
+    fn input_cell(capacity: u64) -> CellInfo {
+        CellInfo {
+            output: CellOutput {
+                capacity: capacity.into(),
+                ..Default::default()
+            },
+            data: None,
+        }
+    }
+
+    fn illegal_pair() -> OtxKeyPair {
+        OtxKeyPair::new(OTX_INPUT_SINCE.into(), None, JsonBytes::default())
+    }
+
+    #[test]
+    fn test_valid_otx() {
+        let otx = to_otx(tx_builder(100 * CKB).build());
+        assert!(validate_otx(&otx).is_ok());
+    }
+
+    #[test]
+    fn test_illegal_key_type() {
+        let mut otx = to_otx(tx_builder(100 * CKB).build());
+        let mut meta: Vec<OtxKeyPair> = otx.meta.iter().cloned().collect();
+        meta.push(illegal_pair());
+        otx.meta = meta.into();
+        assert_eq!(
+            validate_otx(&otx).unwrap_err(),
+            OtxPoolError::IllegalKeyType("meta".to_owned(), OTX_INPUT_SINCE)
+        );
+
+        let mut otx = to_otx(tx_builder(100 * CKB).build());
+        let mut output: Vec<OtxKeyPair> =
+            otx.outputs.iter().next().unwrap().iter().cloned().collect();
+        output.push(illegal_pair());
+        otx.outputs = vec![OtxMap::from(output)].into();
+        assert_eq!(
+            validate_otx(&otx).unwrap_err(),
+            OtxPoolError::IllegalKeyType("output".to_owned(), OTX_INPUT_SINCE)
+        );
+    }
+
+    #[test]
+    fn test_no_inputs() {
+        let otx = to_otx(TransactionBuilder::default().build());
+        assert_eq!(validate_otx(&otx).unwrap_err(), OtxPoolError::NoInputs);
+    }
+
+    #[test]
+    fn test_witness_count_mismatch() {
+        let tx = tx_builder(100 * CKB).set_witnesses(vec![]).build();
+        assert_eq!(
+            validate_otx(&to_otx(tx)).unwrap_err(),
+            OtxPoolError::WitnessCountMismatch(1, 0)
+        );
+    }
+
+    #[test]
+    fn test_convert_to_tx_failed() {
+        let mut otx = to_otx(tx_builder(100 * CKB).build());
+        // an out point index of a single byte
+        let input: Vec<OtxKeyPair> = otx
+            .inputs
+            .iter()
+            .next()
+            .unwrap()
+            .iter()
+            .map(|pair| match pair.key_type() {
+                OTX_INPUT_OUTPOINT_INDEX => OtxKeyPair::new(
+                    OTX_INPUT_OUTPOINT_INDEX.into(),
+                    None,
+                    JsonBytes::from_vec(vec![0]),
+                ),
+                _ => pair.clone(),
+            })
+            .collect();
+        otx.inputs = vec![OtxMap::from(input)].into();
+        assert!(matches!(
+            validate_otx(&otx),
+            Err(OtxPoolError::ConvertToTxFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_outputs_capacity() {
+        let otx = to_otx(tx_builder(0).build());
+        assert_eq!(
+            validate_otx(&otx).unwrap_err(),
+            OtxPoolError::ZeroCapacityOutput(0)
+        );
+
+        let otx = to_otx(tx_builder(1).build());
+        assert_eq!(
+            validate_otx(&otx).unwrap_err(),
+            OtxPoolError::InsufficientOutputCapacity(0)
+        );
+    }
+
+    #[test]
+    fn test_check_balance() {
+        let tx_view: TransactionView = tx_builder(100 * CKB).build().into();
+        assert_eq!(
+            check_balance(&tx_view, &[input_cell(100 * CKB)]).unwrap_err(),
+            OtxPoolError::OtxOffersNothing
+        );
+
+        let balance = check_balance(&tx_view, &[input_cell(101 * CKB)]).unwrap();
+        assert_eq!(balance.get(&None), Some(&(CKB as i128)));
+    }
+
+    fn udt_script() -> Script {
+        packed::Script::new_builder()
+            .args([1u8; 20].to_vec().pack())
+            .build()
+            .into()
+    }
+
+    // gives away 100 udt, and asks for 90 CKB
+    fn swap_balance() -> AssetBalance {
+        vec![(Some(udt_script()), 100), (None, -90 * CKB as i128)]
+            .into_iter()
+            .collect()
+    }
+
+    fn with_intent(intent: &OtxIntent) -> OpenTransaction {
+        let mut otx = to_otx(tx_builder(100 * CKB).build());
+        otx.set_intent(intent);
+        otx
+    }
+
+    fn assert_invalid_intent(intent: OtxIntent) {
+        assert!(matches!(
+            check_intent(&with_intent(&intent), &swap_balance()),
+            Err(OtxPoolError::InvalidIntent(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_intent() {
+        let udt = IntentAsset::Udt(udt_script());
+        let intent = OtxIntent::new(udt, 100, IntentAsset::Ckb, 90 * CKB as u128);
+        assert!(check_intent(&with_intent(&intent), &swap_balance()).is_ok());
+        // the outputs ask for less, which is still accepted
+        let lower = OtxIntent {
+            min_receive: Some(80 * CKB as u128),
+            requested_amount: 95 * CKB as u128,
+            ..intent
+        };
+        assert!(check_intent(&with_intent(&lower), &swap_balance()).is_ok());
+        // no intent
+        assert!(check_intent(&to_otx(tx_builder(100 * CKB).build()), &swap_balance()).is_ok());
+    }
+
+    #[test]
+    fn test_same_asset_intent() {
+        assert_invalid_intent(OtxIntent::new(IntentAsset::Ckb, 1, IntentAsset::Ckb, 1));
+    }
+
+    #[test]
+    fn test_zero_amount_intent() {
+        let udt = IntentAsset::Udt(udt_script());
+        assert_invalid_intent(OtxIntent::new(
+            udt.clone(),
+            0,
+            IntentAsset::Ckb,
+            90 * CKB as u128,
+        ));
+        assert_invalid_intent(OtxIntent::new(udt, 100, IntentAsset::Ckb, 0));
+    }
+
+    #[test]
+    fn test_min_receive_exceeds_requested() {
+        let intent = OtxIntent::new(
+            IntentAsset::Udt(udt_script()),
+            100,
+            IntentAsset::Ckb,
+            90 * CKB as u128,
+        )
+        .with_min_receive(91 * CKB as u128);
+        assert_invalid_intent(intent);
+    }
+
+    #[test]
+    fn test_over_offer_intent() {
+        assert_invalid_intent(OtxIntent::new(
+            IntentAsset::Udt(udt_script()),
+            101,
+            IntentAsset::Ckb,
+            90 * CKB as u128,
+        ));
+    }
+
+    #[test]
+    fn test_intent_not_asked_for() {
+        let udt = IntentAsset::Udt(udt_script());
+        // the outputs ask for 90 CKB only
+        assert_invalid_intent(OtxIntent::new(
+            udt.clone(),
+            100,
+            IntentAsset::Ckb,
+            80 * CKB as u128,
+        ));
+        assert_invalid_intent(
+            OtxIntent::new(udt.clone(), 100, IntentAsset::Ckb, 100 * CKB as u128)
+                .with_min_receive(95 * CKB as u128),
+        );
+        // nothing of the other udt is asked for
+        let other = IntentAsset::Udt(Script {
+            args: JsonBytes::from_vec(vec![2; 20]),
+            ..udt_script()
+        });
+        assert_invalid_intent(OtxIntent::new(udt, 100, other, 1));
+    }
+}
@@ -1,9 +1,11 @@
-use super::{OtxPoolRpc, OtxPoolRpcImpl};
+use super::types::{EncodedOtx, OtxEncoding, OtxWithId, PaginatedOtxs, PoolInfo};
+use super::{OtxPoolRpc, OtxPoolRpcImpl, DEFAULT_PAGE_LIMIT, MAX_PAGE_LIMIT};
+use crate::error::OtxRpcError;
 use crate::pool::Id;
 
 use otx_format::jsonrpc_types::OpenTransaction;
 
-use ckb_jsonrpc_types::JsonBytes;
+use ckb_jsonrpc_types::{JsonBytes, OutPoint, Script, Uint64};
 use jsonrpc_core::Result as RpcResult;
 
 impl OtxPoolRpc for OtxPoolRpcImpl {
@@ -11,7 +13,75 @@ impl OtxPoolRpc for OtxPoolRpcImpl {
         self.otx_pool.insert(otx).map_err(Into::into)
     }
 
-    fn query_otx_by_id(&self, id: Id) -> RpcResult<Option<OpenTransaction>> {
-        Ok(self.otx_pool.get_otx_by_id(id))
+    fn query_otx_by_id(
+        &self,
+        id: Id,
+        encoding: Option<OtxEncoding>,
+    ) -> RpcResult<Option<EncodedOtx>> {
+        let otx = match self.otx_pool.get_otx_by_id(id) {
+            Some(otx) => otx,
+            None => return Ok(None),
+        };
+        EncodedOtx::encode(otx, encoding.unwrap_or_default())
+            .map(Some)
+            .map_err(|err| OtxRpcError::from(err).into())
+    }
+
+    fn query_otx_conflicts(&self, id: Id) -> RpcResult<Option<Vec<Id>>> {
+        Ok(self.otx_pool.get_conflicts_by_id(&id))
+    }
+
+    fn get_all_otxs(
+        &self,
+        offset: Option<Uint64>,
+        limit: Option<Uint64>,
+    ) -> RpcResult<PaginatedOtxs> {
+        let offset: u64 = offset.map(Into::into).unwrap_or_default();
+        let limit: u64 = limit
+            .map(Into::into)
+            .unwrap_or(DEFAULT_PAGE_LIMIT)
+            .min(MAX_PAGE_LIMIT);
+        let (otxs, total) = self.otx_pool.get_all_otxs(offset as usize, limit as usize);
+        Ok(PaginatedOtxs {
+            otxs: otxs.into_iter().map(Into::into).collect(),
+            total: (total as u64).into(),
+        })
+    }
+
+    fn query_otxs_by_lock_script(&self, lock: Script) -> RpcResult<Vec<OtxWithId>> {
+        let otxs = self.otx_pool.get_otxs_by_lock_script(&lock);
+        Ok(otxs.into_iter().map(Into::into).collect())
+    }
+
+    fn query_otxs_by_type_script(&self, type_script: Script) -> RpcResult<Vec<OtxWithId>> {
+        let otxs = self.otx_pool.get_otxs_by_type_script(&type_script);
+        Ok(otxs.into_iter().map(Into::into).collect())
+    }
+
+    fn query_otxs_by_input_outpoint(&self, out_point: OutPoint) -> RpcResult<Vec<OtxWithId>> {
+        let otxs = self.otx_pool.get_otxs_by_input_out_point(&out_point);
+        Ok(otxs.into_iter().map(Into::into).collect())
+    }
+
+    fn get_pool_info(&self) -> RpcResult<PoolInfo> {
+        let config = self.otx_pool.config();
+        Ok(PoolInfo {
+            otx_count: (self.otx_pool.len() as u64).into(),
+            total_bytes: (self.otx_pool.total_bytes() as u64).into(),
+            max_otxs: (config.max_otxs as u64).into(),
+            max_total_bytes: (config.max_total_bytes as u64).into(),
+            uptime_secs: self.otx_pool.uptime_secs().into(),
+        })
+    }
+
+    fn get_residual_otx(&self, parent_id: Id) -> RpcResult<Option<OpenTransaction>> {
+        Ok(self.otx_pool.get_residual_template(&parent_id))
+    }
+
+    fn delete_otx(&self, id: Id, expire_at: Uint64, signature: JsonBytes) -> RpcResult<bool> {
+        self.otx_pool
+            .remove_with_proof(&id, expire_at.value(), signature.as_bytes())
+            .map(|removed| removed.is_some())
+            .map_err(Into::into)
     }
 }
@@ -1,30 +1,75 @@
 mod r#impl;
+pub mod plugin;
+pub mod subscription;
+pub mod types;
 
 use super::pool::{Id, OtxPool};
-use crate::notify::NotifyController;
-
 use otx_format::jsonrpc_types::OpenTransaction;
+use types::{EncodedOtx, OtxEncoding, OtxWithId, PaginatedOtxs, PoolInfo};
 
-use ckb_jsonrpc_types::JsonBytes;
+use ckb_jsonrpc_types::{JsonBytes, OutPoint, Script, Uint64};
 use jsonrpc_core::Result as RpcResult;
 use jsonrpc_derive::rpc;
 
+use std::sync::Arc;
+
 #[rpc(server)]
 pub trait OtxPoolRpc {
     #[rpc(name = "submit_otx")]
     fn submit_otx(&self, otx: JsonBytes) -> RpcResult<Id>;
 
+    /// The otx is returned as json by default, or encoded as molecule hex or
+    /// a ckb transaction view on request.
     #[rpc(name = "query_otx_by_id")]
-    fn query_otx_by_id(&self, id: Id) -> RpcResult<Option<OpenTransaction>>;
+    fn query_otx_by_id(
+        &self,
+        id: Id,
+        encoding: Option<OtxEncoding>,
+    ) -> RpcResult<Option<EncodedOtx>>;
+
+    #[rpc(name = "query_otx_conflicts")]
+    fn query_otx_conflicts(&self, id: Id) -> RpcResult<Option<Vec<Id>>>;
+
+    #[rpc(name = "get_all_otxs")]
+    fn get_all_otxs(
+        &self,
+        offset: Option<Uint64>,
+        limit: Option<Uint64>,
+    ) -> RpcResult<PaginatedOtxs>;
+
+    #[rpc(name = "query_otxs_by_lock_script")]
+    fn query_otxs_by_lock_script(&self, lock: Script) -> RpcResult<Vec<OtxWithId>>;
+
+    #[rpc(name = "query_otxs_by_type_script")]
+    fn query_otxs_by_type_script(&self, type_script: Script) -> RpcResult<Vec<OtxWithId>>;
+
+    #[rpc(name = "query_otxs_by_input_outpoint")]
+    fn query_otxs_by_input_outpoint(&self, out_point: OutPoint) -> RpcResult<Vec<OtxWithId>>;
+
+    #[rpc(name = "get_pool_info")]
+    fn get_pool_info(&self) -> RpcResult<PoolInfo>;
+
+    /// The unsigned residual otx left by the partial fill of the settled
+    /// parent. The owner signs it and submits it as a new otx.
+    #[rpc(name = "get_residual_otx")]
+    fn get_residual_otx(&self, parent_id: Id) -> RpcResult<Option<OpenTransaction>>;
+
+    /// Delete an otx with a recoverable signature over `delete_otx_message`
+    /// of the otx id and `expire_at`, made by the owner of one of its inputs.
+    /// Returns false if the otx is not found.
+    #[rpc(name = "delete_otx")]
+    fn delete_otx(&self, id: Id, expire_at: Uint64, signature: JsonBytes) -> RpcResult<bool>;
 }
 
+pub const DEFAULT_PAGE_LIMIT: u64 = 100;
+pub const MAX_PAGE_LIMIT: u64 = 1000;
+
 pub struct OtxPoolRpcImpl {
-    otx_pool: OtxPool,
+    otx_pool: Arc<OtxPool>,
 }
 
 impl OtxPoolRpcImpl {
-    pub fn new(notify_ctrl: NotifyController) -> Self {
-        let otx_pool = OtxPool::new(notify_ctrl);
+    pub fn new(otx_pool: Arc<OtxPool>) -> Self {
         OtxPoolRpcImpl { otx_pool }
     }
 }
@@ -0,0 +1,160 @@
+use crate::error::{OtxPoolError, OtxRpcError};
+use crate::plugin::manager::{PluginManager, PluginStatus};
+use crate::plugin::plugin_proxy::{PluginProxy, PluginRequester, DEFAULT_REQUEST_TIMEOUT};
+use crate::plugin::supervisor::PluginHealth;
+
+use otx_format::jsonrpc_types::OpenTransaction;
+use otx_plugin_protocol::request::unexpected_response;
+use otx_plugin_protocol::{MessageFromHost, MessageFromPlugin};
+
+use jsonrpc_core::Result as RpcResult;
+use jsonrpc_derive::rpc;
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+#[rpc(server)]
+pub trait PluginRpc {
+    #[rpc(name = "get_plugin_health")]
+    fn get_plugin_health(&self) -> RpcResult<HashMap<String, PluginHealth>>;
+
+    #[rpc(name = "list_plugins")]
+    fn list_plugins(&self) -> RpcResult<Vec<PluginStatus>>;
+
+    #[rpc(name = "get_plugin_status")]
+    fn get_plugin_status(&self, name: String) -> RpcResult<String>;
+
+    /// Ask the plugin for the transaction it has aggregated so far.
+    #[rpc(name = "get_plugin_proposed_tx")]
+    fn get_plugin_proposed_tx(&self, name: String) -> RpcResult<Option<OpenTransaction>>;
+}
+
+/// Managing the plugins runs binaries on the service host, it must be served
+/// on an admin endpoint apart from the public one.
+#[rpc(server)]
+pub trait PluginAdminRpc {
+    #[rpc(name = "activate_plugin")]
+    fn activate_plugin(&self, name: String) -> RpcResult<()>;
+
+    #[rpc(name = "deactivate_plugin")]
+    fn deactivate_plugin(&self, name: String) -> RpcResult<()>;
+
+    /// Install the plugin binary at the path on the service host as an
+    /// inactive plugin. The binary must be in the install dir of the plugin
+    /// manager. Returns the plugin name.
+    #[rpc(name = "install_plugin")]
+    fn install_plugin(&self, path: String) -> RpcResult<String>;
+
+    #[rpc(name = "remove_plugin")]
+    fn remove_plugin(&self, name: String) -> RpcResult<()>;
+}
+
+pub struct PluginRpcImpl {
+    plugin_manager: Arc<Mutex<PluginManager>>,
+}
+
+impl PluginRpcImpl {
+    pub fn new(plugin_manager: Arc<Mutex<PluginManager>>) -> Self {
+        PluginRpcImpl { plugin_manager }
+    }
+}
+
+impl PluginRpc for PluginRpcImpl {
+    fn get_plugin_health(&self) -> RpcResult<HashMap<String, PluginHealth>> {
+        let plugin_manager = self.plugin_manager.lock().expect("acquire lock");
+        Ok(plugin_manager.plugin_health())
+    }
+
+    fn list_plugins(&self) -> RpcResult<Vec<PluginStatus>> {
+        let plugin_manager = self.plugin_manager.lock().expect("acquire lock");
+        Ok(plugin_manager.list_plugins())
+    }
+
+    fn get_plugin_status(&self, name: String) -> RpcResult<String> {
+        let in_process_status = {
+            let plugin_manager = self.plugin_manager.lock().expect("acquire lock");
+            plugin_manager.in_process_plugin_status(&name)
+        };
+        if let Some(status) = in_process_status {
+            return Ok(status);
+        }
+        match self.request(&name, MessageFromHost::GetPluginStatus)? {
+            MessageFromPlugin::PluginStatus(status) => Ok(status),
+            response => Err(to_rpc_error(unexpected_response(response))),
+        }
+    }
+
+    fn get_plugin_proposed_tx(&self, name: String) -> RpcResult<Option<OpenTransaction>> {
+        match self.request(&name, MessageFromHost::GetProposedTx)? {
+            MessageFromPlugin::ProposedTx(otx) => Ok(otx),
+            response => Err(to_rpc_error(unexpected_response(response))),
+        }
+    }
+}
+
+impl PluginRpcImpl {
+    fn requester(&self, name: &str) -> RpcResult<PluginRequester> {
+        let plugin_manager = self.plugin_manager.lock().expect("acquire lock");
+        plugin_manager
+            .plugin_requester(name)
+            .ok_or_else(|| to_rpc_error(format!("plugin {} is not active", name)))
+    }
+
+    fn request(&self, name: &str, msg: MessageFromHost) -> RpcResult<MessageFromPlugin> {
+        // the manager is released before waiting for the response
+        let requester = self.requester(name)?;
+        requester
+            .request(msg, DEFAULT_REQUEST_TIMEOUT)
+            .map_err(to_rpc_error)
+    }
+}
+
+pub struct PluginAdminRpcImpl {
+    plugin_manager: Arc<Mutex<PluginManager>>,
+}
+
+impl PluginAdminRpcImpl {
+    pub fn new(plugin_manager: Arc<Mutex<PluginManager>>) -> Self {
+        PluginAdminRpcImpl { plugin_manager }
+    }
+}
+
+impl PluginAdminRpc for PluginAdminRpcImpl {
+    fn activate_plugin(&self, name: String) -> RpcResult<()> {
+        let mut plugin_manager = self.plugin_manager.lock().expect("acquire lock");
+        plugin_manager.activate_plugin(&name).map_err(to_rpc_error)
+    }
+
+    fn deactivate_plugin(&self, name: String) -> RpcResult<()> {
+        let mut plugin_manager = self.plugin_manager.lock().expect("acquire lock");
+        plugin_manager
+            .deactivate_plugin(&name)
+            .map_err(to_rpc_error)
+    }
+
+    fn install_plugin(&self, path: String) -> RpcResult<String> {
+        let binary_path = {
+            let plugin_manager = self.plugin_manager.lock().expect("acquire lock");
+            plugin_manager
+                .check_install_path(Path::new(&path))
+                .map_err(to_rpc_error)?
+        };
+        // the manager is released while the binary runs
+        let plugin_info =
+            PluginProxy::get_plugin_info(binary_path.clone()).map_err(to_rpc_error)?;
+        let mut plugin_manager = self.plugin_manager.lock().expect("acquire lock");
+        plugin_manager
+            .install_plugin(&binary_path, plugin_info)
+            .map_err(to_rpc_error)
+    }
+
+    fn remove_plugin(&self, name: String) -> RpcResult<()> {
+        let mut plugin_manager = self.plugin_manager.lock().expect("acquire lock");
+        plugin_manager.remove_plugin(&name).map_err(to_rpc_error)
+    }
+}
+
+fn to_rpc_error(err: String) -> jsonrpc_core::Error {
+    OtxRpcError::from(OtxPoolError::PluginManagerError(err)).into()
+}
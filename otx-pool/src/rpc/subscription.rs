@@ -0,0 +1,282 @@
+use super::types::OtxWithId;
+use crate::notify::NotifyController;
+
+use otx_format::jsonrpc_types::{tx_view::otx_to_tx_view, OpenTransaction};
+
+use ckb_async_runtime::Handle;
+use ckb_jsonrpc_types::{CellOutput, Script};
+use jsonrpc_core::{Metadata, Result};
+use jsonrpc_derive::rpc;
+use jsonrpc_pubsub::{
+    typed::{Sink, Subscriber},
+    PubSubMetadata, Session, SubscriptionId,
+};
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+const SUBSCRIBER_NAME: &str = "SubscriptionRpc";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Topic {
+    NewOtx,
+    DeletedOtx,
+}
+
+/// Only the otxs with an output matching all the given scripts are pushed.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ScriptFilter {
+    pub lock: Option<Script>,
+    pub type_script: Option<Script>,
+}
+
+impl ScriptFilter {
+    fn is_empty(&self) -> bool {
+        self.lock.is_none() && self.type_script.is_none()
+    }
+
+    /// `outputs` is `None` if the otx can not be converted to a transaction.
+    fn matches(&self, outputs: Option<&[CellOutput]>) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let outputs = match outputs {
+            Some(outputs) => outputs,
+            None => return false,
+        };
+        outputs.iter().any(|output| {
+            let lock_matches = match &self.lock {
+                Some(lock) => lock == &output.lock,
+                None => true,
+            };
+            let type_matches = match &self.type_script {
+                Some(type_script) => output.type_.as_ref() == Some(type_script),
+                None => true,
+            };
+            lock_matches && type_matches
+        })
+    }
+}
+
+/// Returns `None` if the otx can not be converted to a transaction.
+fn resolve_outputs(otx: &OpenTransaction) -> Option<Vec<CellOutput>> {
+    otx_to_tx_view(otx.clone())
+        .ok()
+        .map(|tx_view| tx_view.inner.outputs)
+}
+
+#[derive(Clone, Default)]
+pub struct SubscriptionSession(pub Option<Arc<Session>>);
+
+impl Metadata for SubscriptionSession {}
+
+impl PubSubMetadata for SubscriptionSession {
+    fn session(&self) -> Option<Arc<Session>> {
+        self.0.clone()
+    }
+}
+
+/// Pushes the pool events to the clients of the WebSocket and TCP servers.
+///
+/// The payload of each message is an `OtxWithId` object.
+#[rpc(server)]
+pub trait SubscriptionRpc {
+    type Metadata;
+
+    #[pubsub(subscription = "subscribe", subscribe, name = "subscribe")]
+    fn subscribe(
+        &self,
+        meta: Self::Metadata,
+        subscriber: Subscriber<OtxWithId>,
+        topic: Topic,
+        filter: Option<ScriptFilter>,
+    );
+
+    #[pubsub(subscription = "subscribe", unsubscribe, name = "unsubscribe")]
+    fn unsubscribe(&self, meta: Option<Self::Metadata>, id: SubscriptionId) -> Result<bool>;
+}
+
+type Subscribers = HashMap<Topic, HashMap<SubscriptionId, (Sink<OtxWithId>, ScriptFilter)>>;
+
+#[derive(Clone)]
+pub struct SubscriptionRpcImpl {
+    subscribers: Arc<RwLock<Subscribers>>,
+    id_generator: Arc<AtomicU64>,
+}
+
+impl SubscriptionRpcImpl {
+    pub async fn new(notify_ctrl: NotifyController, handle: Handle) -> Self {
+        let mut new_open_tx_receiver = notify_ctrl.subscribe_new_open_tx(SUBSCRIBER_NAME).await;
+        let mut delete_open_tx_receiver =
+            notify_ctrl.subscribe_delete_open_tx(SUBSCRIBER_NAME).await;
+
+        let subscription_rpc_impl = SubscriptionRpcImpl {
+            subscribers: Arc::default(),
+            id_generator: Arc::new(AtomicU64::new(0)),
+        };
+        let rpc_impl = subscription_rpc_impl.clone();
+        handle.spawn(async move {
+            loop {
+                tokio::select! {
+                    Some(otx) = new_open_tx_receiver.recv() => {
+                        rpc_impl.publish(Topic::NewOtx, otx);
+                    }
+                    Some(otx) = delete_open_tx_receiver.recv() => {
+                        rpc_impl.publish(Topic::DeletedOtx, otx);
+                    }
+                    else => break,
+                }
+            }
+        });
+        subscription_rpc_impl
+    }
+
+    fn publish(&self, topic: Topic, otx: OpenTransaction) {
+        let mut closed = vec![];
+        if let Some(sinks) = self.subscribers.read().expect("acquire lock").get(&topic) {
+            // the outputs are resolved once for all the filters
+            let outputs = if sinks.values().any(|(_, filter)| !filter.is_empty()) {
+                resolve_outputs(&otx)
+            } else {
+                None
+            };
+            let payload = OtxWithId {
+                id: otx.get_otx_hash(),
+                otx,
+            };
+            for (id, (sink, filter)) in sinks {
+                if filter.matches(outputs.as_deref()) && sink.notify(Ok(payload.clone())).is_err() {
+                    closed.push(id.clone());
+                }
+            }
+        }
+        if !closed.is_empty() {
+            let mut subscribers = self.subscribers.write().expect("acquire lock");
+            if let Some(sinks) = subscribers.get_mut(&topic) {
+                for id in closed {
+                    sinks.remove(&id);
+                }
+            }
+        }
+    }
+}
+
+impl SubscriptionRpc for SubscriptionRpcImpl {
+    type Metadata = SubscriptionSession;
+
+    fn subscribe(
+        &self,
+        _meta: Self::Metadata,
+        subscriber: Subscriber<OtxWithId>,
+        topic: Topic,
+        filter: Option<ScriptFilter>,
+    ) {
+        let id = SubscriptionId::String(format!(
+            "{:#x}",
+            self.id_generator.fetch_add(1, Ordering::SeqCst)
+        ));
+        if let Ok(sink) = subscriber.assign_id(id.clone()) {
+            self.subscribers
+                .write()
+                .expect("acquire lock")
+                .entry(topic)
+                .or_default()
+                .insert(id, (sink, filter.unwrap_or_default()));
+        }
+    }
+
+    fn unsubscribe(&self, _meta: Option<Self::Metadata>, id: SubscriptionId) -> Result<bool> {
+        let mut subscribers = self.subscribers.write().expect("acquire lock");
+        let removed = subscribers
+            .values_mut()
+            .any(|sinks| sinks.remove(&id).is_some());
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use otx_format::jsonrpc_types::constant::basic_keys::OTX_OUTPUT_CAPACITY;
+    use otx_format::jsonrpc_types::tx_view::tx_view_to_otx;
+    use otx_format::jsonrpc_types::{OtxKeyPair, OtxMap};
+
+    use ckb_jsonrpc_types::{JsonBytes, ScriptHashType};
+    use ckb_types::{core::TransactionBuilder, h256, packed, prelude::*};
+
+    fn script(args: u8) -> Script {
+        Script {
+            code_hash: h256!("0x1"),
+            hash_type: ScriptHashType::Type,
+            args: JsonBytes::from_vec(vec![args]),
+        }
+    }
+
+    fn output(lock: Script, type_script: Option<Script>) -> packed::CellOutput {
+        packed::CellOutput::new_builder()
+            .lock(lock.into())
+            .type_(type_script.map(Into::into).pack())
+            .build()
+    }
+
+    // output 0 is locked by script(1) without a type, output 1 is locked by
+    // script(2) with the type script(3)
+    fn outputs() -> Option<Vec<CellOutput>> {
+        let tx = TransactionBuilder::default()
+            .output(output(script(1), None))
+            .output_data(Default::default())
+            .output(output(script(2), Some(script(3))))
+            .output_data(Default::default())
+            .build();
+        resolve_outputs(&tx_view_to_otx(tx.into()).unwrap())
+    }
+
+    fn filter(lock: Option<Script>, type_script: Option<Script>) -> ScriptFilter {
+        ScriptFilter { lock, type_script }
+    }
+
+    #[test]
+    fn test_match_lock() {
+        let outputs = outputs();
+        assert!(filter(Some(script(1)), None).matches(outputs.as_deref()));
+        assert!(filter(Some(script(2)), None).matches(outputs.as_deref()));
+        assert!(!filter(Some(script(3)), None).matches(outputs.as_deref()));
+    }
+
+    #[test]
+    fn test_match_type() {
+        let outputs = outputs();
+        assert!(filter(None, Some(script(3))).matches(outputs.as_deref()));
+        assert!(!filter(None, Some(script(1))).matches(outputs.as_deref()));
+    }
+
+    #[test]
+    fn test_match_lock_and_type() {
+        let outputs = outputs();
+        assert!(filter(Some(script(2)), Some(script(3))).matches(outputs.as_deref()));
+        // both scripts must be on the same output
+        assert!(!filter(Some(script(1)), Some(script(3))).matches(outputs.as_deref()));
+    }
+
+    #[test]
+    fn test_match_unconvertible_otx() {
+        // the capacity is not a valid Uint64
+        let otx = OpenTransaction {
+            outputs: vec![OtxMap::from(vec![OtxKeyPair::new(
+                OTX_OUTPUT_CAPACITY.into(),
+                None,
+                JsonBytes::from_vec(vec![1, 2, 3]),
+            )])]
+            .into(),
+            ..Default::default()
+        };
+        let outputs = resolve_outputs(&otx);
+        assert!(outputs.is_none());
+        assert!(ScriptFilter::default().matches(outputs.as_deref()));
+        assert!(!filter(Some(script(1)), None).matches(outputs.as_deref()));
+    }
+}
@@ -0,0 +1,125 @@
+use crate::pool::Id;
+
+use otx_format::{
+    error::OtxFormatError,
+    jsonrpc_types::{tx_view::otx_to_tx_view, OpenTransaction},
+    types::packed,
+};
+
+use ckb_jsonrpc_types::{JsonBytes, TransactionView, Uint64};
+use ckb_types::prelude::Entity;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OtxEncoding {
+    #[default]
+    Json,
+    MoleculeHex,
+    CkbTxView,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum EncodedOtx {
+    Json(OpenTransaction),
+    MoleculeHex(JsonBytes),
+    CkbTxView(TransactionView),
+}
+
+impl EncodedOtx {
+    pub fn encode(otx: OpenTransaction, encoding: OtxEncoding) -> Result<Self, OtxFormatError> {
+        let encoded = match encoding {
+            OtxEncoding::Json => EncodedOtx::Json(otx),
+            OtxEncoding::MoleculeHex => {
+                let otx: packed::OpenTransaction = otx.into();
+                EncodedOtx::MoleculeHex(JsonBytes::from_bytes(otx.as_bytes()))
+            }
+            OtxEncoding::CkbTxView => EncodedOtx::CkbTxView(otx_to_tx_view(otx)?),
+        };
+        Ok(encoded)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OtxWithId {
+    pub id: Id,
+    pub otx: OpenTransaction,
+}
+
+impl From<(Id, OpenTransaction)> for OtxWithId {
+    fn from((id, otx): (Id, OpenTransaction)) -> Self {
+        OtxWithId { id, otx }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PaginatedOtxs {
+    pub otxs: Vec<OtxWithId>,
+    pub total: Uint64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PoolInfo {
+    pub otx_count: Uint64,
+    pub total_bytes: Uint64,
+    pub max_otxs: Uint64,
+    pub max_total_bytes: Uint64,
+    pub uptime_secs: Uint64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use otx_format::jsonrpc_types::tx_view::tx_view_to_otx;
+
+    use ckb_types::{
+        core::TransactionBuilder,
+        h256,
+        packed::{CellInput, CellOutput, OutPoint},
+        prelude::*,
+    };
+    use serde::de::DeserializeOwned;
+
+    fn build_otx() -> OpenTransaction {
+        let tx = TransactionBuilder::default()
+            .input(CellInput::new(OutPoint::new(h256!("0x1").pack(), 0), 0))
+            .witness(Default::default())
+            .output(CellOutput::new_builder().capacity(100u64.pack()).build())
+            .output_data(Default::default())
+            .build();
+        tx_view_to_otx(tx.into()).unwrap()
+    }
+
+    // the payload as a client receives it
+    fn to_client<T: DeserializeOwned>(encoded: EncodedOtx) -> T {
+        let json = serde_json::to_string(&encoded).unwrap();
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn test_encode_json() {
+        let otx = build_otx();
+        let encoded = EncodedOtx::encode(otx.clone(), OtxEncoding::Json).unwrap();
+        let decoded: OpenTransaction = to_client(encoded);
+        assert_eq!(decoded, otx);
+    }
+
+    #[test]
+    fn test_encode_molecule_hex() {
+        let otx = build_otx();
+        let encoded = EncodedOtx::encode(otx.clone(), OtxEncoding::MoleculeHex).unwrap();
+        let bytes: JsonBytes = to_client(encoded);
+        let decoded = packed::OpenTransaction::from_slice(bytes.as_bytes()).unwrap();
+        assert_eq!(OpenTransaction::from(decoded), otx);
+    }
+
+    #[test]
+    fn test_encode_ckb_tx_view() {
+        let otx = build_otx();
+        let encoded = EncodedOtx::encode(otx.clone(), OtxEncoding::CkbTxView).unwrap();
+        let tx_view: TransactionView = to_client(encoded);
+        assert_eq!(tx_view_to_otx(tx_view).unwrap(), otx);
+    }
+}
@@ -0,0 +1,120 @@
+use super::OtxStore;
+use crate::error::{InnerResult, OtxPoolError};
+use crate::pool::Id;
+
+use otx_format::{jsonrpc_types::OpenTransaction, types::packed};
+
+use ckb_types::prelude::Entity;
+
+use std::path::Path;
+
+/// An embedded on-disk store, otxs are saved as molecule bytes keyed by id.
+pub struct SledStore {
+    db: sled::Db,
+}
+
+impl SledStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> InnerResult<Self> {
+        let db = sled::open(path).map_err(|err| OtxPoolError::StoreError(err.to_string()))?;
+        Ok(SledStore { db })
+    }
+}
+
+impl OtxStore for SledStore {
+    fn insert(&self, id: &Id, otx: &OpenTransaction) -> InnerResult<()> {
+        let otx: packed::OpenTransaction = otx.to_owned().into();
+        self.db
+            .insert(id.as_bytes(), otx.as_slice())
+            .map_err(|err| OtxPoolError::StoreError(err.to_string()))?;
+        self.db
+            .flush()
+            .map_err(|err| OtxPoolError::StoreError(err.to_string()))?;
+        Ok(())
+    }
+
+    fn remove(&self, id: &Id) -> InnerResult<()> {
+        self.db
+            .remove(id.as_bytes())
+            .map_err(|err| OtxPoolError::StoreError(err.to_string()))?;
+        self.db
+            .flush()
+            .map_err(|err| OtxPoolError::StoreError(err.to_string()))?;
+        Ok(())
+    }
+
+    fn load_all(&self) -> InnerResult<Vec<(Id, OpenTransaction)>> {
+        let mut otxs = vec![];
+        for item in self.db.iter() {
+            let (key, value) = item.map_err(|err| OtxPoolError::StoreError(err.to_string()))?;
+            let id = Id::from_slice(key.as_ref())
+                .map_err(|_| OtxPoolError::StoreError("invalid otx id in store".to_string()))?;
+            let otx = packed::OpenTransaction::from_slice(value.as_ref())?;
+            otxs.push((id, otx.into()));
+        }
+        Ok(otxs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use otx_format::error::OtxError;
+    use otx_format::jsonrpc_types::tx_view::tx_view_to_otx;
+
+    use molecule::error::VerificationError;
+
+    use ckb_types::{core::TransactionBuilder, packed::CellOutput, prelude::*};
+    use tempfile::TempDir;
+
+    fn build_otx(capacity: u64) -> OpenTransaction {
+        let tx = TransactionBuilder::default()
+            .output(CellOutput::new_builder().capacity(capacity.pack()).build())
+            .output_data(Default::default())
+            .build();
+        tx_view_to_otx(tx.into()).unwrap()
+    }
+
+    #[test]
+    fn test_insert_remove_load_all() {
+        let dir = TempDir::new().unwrap();
+        let otxs: Vec<_> = (1..=3).map(build_otx).collect();
+        {
+            let store = SledStore::open(dir.path()).unwrap();
+            for otx in &otxs {
+                store.insert(&otx.get_otx_hash(), otx).unwrap();
+            }
+            store.remove(&otxs[1].get_otx_hash()).unwrap();
+        }
+
+        // reopen to read what was flushed to the disk
+        let store = SledStore::open(dir.path()).unwrap();
+        let mut loaded = store.load_all().unwrap();
+        loaded.sort_by_key(|(_, otx)| otx.get_otx_hash());
+        let mut expected: Vec<_> = [&otxs[0], &otxs[2]]
+            .into_iter()
+            .map(|otx| (otx.get_otx_hash(), otx.clone()))
+            .collect();
+        expected.sort_by_key(|(id, _)| id.clone());
+        assert_eq!(loaded, expected);
+    }
+
+    #[test]
+    fn test_load_all_corrupted_value() {
+        let dir = TempDir::new().unwrap();
+        let store = SledStore::open(dir.path()).unwrap();
+        let otx = build_otx(1);
+        store.insert(&otx.get_otx_hash(), &otx).unwrap();
+        store
+            .db
+            .insert(Id::default().as_bytes(), &[0u8, 1, 2][..])
+            .unwrap();
+
+        // the value is too short for the molecule header
+        let err = store.load_all().unwrap_err();
+        assert_eq!(
+            err.0.err_code(),
+            VerificationError::HeaderIsBroken(String::new(), 0, 0).err_code()
+        );
+    }
+}
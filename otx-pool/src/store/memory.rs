@@ -0,0 +1,39 @@
+use super::OtxStore;
+use crate::error::InnerResult;
+use crate::pool::Id;
+
+use otx_format::jsonrpc_types::OpenTransaction;
+
+use dashmap::DashMap;
+
+/// A volatile store, everything is lost when the service stops.
+#[derive(Default)]
+pub struct MemoryStore {
+    otxs: DashMap<Id, OpenTransaction>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+}
+
+impl OtxStore for MemoryStore {
+    fn insert(&self, id: &Id, otx: &OpenTransaction) -> InnerResult<()> {
+        self.otxs.insert(id.to_owned(), otx.to_owned());
+        Ok(())
+    }
+
+    fn remove(&self, id: &Id) -> InnerResult<()> {
+        self.otxs.remove(id);
+        Ok(())
+    }
+
+    fn load_all(&self) -> InnerResult<Vec<(Id, OpenTransaction)>> {
+        Ok(self
+            .otxs
+            .iter()
+            .map(|pair| (pair.key().to_owned(), pair.value().to_owned()))
+            .collect())
+    }
+}
@@ -0,0 +1,22 @@
+mod disk;
+mod memory;
+
+pub use disk::SledStore;
+pub use memory::MemoryStore;
+
+use crate::error::InnerResult;
+use crate::pool::Id;
+
+use otx_format::jsonrpc_types::OpenTransaction;
+
+/// Persistence backend of the otx pool.
+///
+/// The pool keeps its working set in memory and writes every change through
+/// to the store, so that a restarted service can recover submitted otxs.
+pub trait OtxStore: Send + Sync {
+    fn insert(&self, id: &Id, otx: &OpenTransaction) -> InnerResult<()>;
+
+    fn remove(&self, id: &Id) -> InnerResult<()>;
+
+    fn load_all(&self) -> InnerResult<Vec<(Id, OpenTransaction)>>;
+}
@@ -1,23 +1,65 @@
+use aggregator::atomic_swap::{AtomicSwapConfig, AtomicSwapMatcher};
+use aggregator::batcher::{Batcher, BatcherConfig};
+use aggregator::Signer;
 use otx_pool::{
     notify::NotifyService,
-    plugin::manager::PluginManager,
-    rpc::{OtxPoolRpc, OtxPoolRpcImpl},
+    plugin::{manager::PluginManager, tx_sender::CkbTxSender},
+    pool::{
+        cell_provider::CkbCellProvider,
+        config::{OtxPoolConfig, POOL_CONFIG_FILENAME},
+        OtxPool,
+    },
+    rpc::{
+        plugin::{PluginAdminRpc, PluginAdminRpcImpl, PluginRpc, PluginRpcImpl},
+        subscription::{SubscriptionRpc, SubscriptionRpcImpl, SubscriptionSession},
+        OtxPoolRpc, OtxPoolRpcImpl,
+    },
+    store::SledStore,
 };
-use utils::const_definition::SERVICE_URI;
+use utils::const_definition::{CKB_URI, SERVICE_TCP_ADDR, SERVICE_URI, SERVICE_WS_ADDR};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use ckb_async_runtime::new_global_runtime;
-use jsonrpc_core::IoHandler;
+use ckb_jsonrpc_types::OutPoint;
+use ckb_sdk::Address;
+use ckb_types::{packed, H256};
+use jsonrpc_core::{IoHandler, MetaIoHandler};
 use jsonrpc_http_server::ServerBuilder;
+use jsonrpc_pubsub::{PubSubHandler, Session};
 use jsonrpc_server_utils::cors::AccessControlAllowOrigin;
 use jsonrpc_server_utils::hosts::DomainsValidation;
 use tokio::time::{self, Duration};
 
-use std::{net::SocketAddr, path::Path};
+use std::{
+    env,
+    net::SocketAddr,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
 
 pub const MESSAGE_CHANNEL_SIZE: usize = 1024;
 const RUNTIME_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+const STOP_NOTIFY_DELAY: Duration = Duration::from_secs(1);
 pub const PLUGINS_DIRNAME: &str = "plugins";
+pub const STORE_DIRNAME: &str = "otx-store";
+/// The private key of the aggregator, the batcher runs only if it is set.
+/// Exclusive with `SWAP_CHANGE_ADDRESS_ENV`.
+pub const AGGREGATOR_PK_ENV: &str = "OTX_AGGREGATOR_PK";
+/// The live cell of the aggregator, as `<tx_hash>:<index>`.
+pub const AGGREGATOR_CELL_ENV: &str = "OTX_AGGREGATOR_CELL";
+/// The address taking the capacity left over by the swaps, the atomic swap
+/// matcher runs only if it is set. Exclusive with `AGGREGATOR_PK_ENV`.
+pub const SWAP_CHANGE_ADDRESS_ENV: &str = "OTX_SWAP_CHANGE_ADDRESS";
+/// The most otxs the matcher merges, optional.
+pub const SWAP_MAX_RING_SIZE_ENV: &str = "OTX_SWAP_MAX_RING_SIZE";
+/// The range of the fee of a swap in shannons, optional.
+pub const SWAP_MIN_FEE_ENV: &str = "OTX_SWAP_MIN_FEE";
+pub const SWAP_MAX_FEE_ENV: &str = "OTX_SWAP_MAX_FEE";
+/// The address of the plugin admin rpc server, which runs only if it is set.
+pub const ADMIN_ADDR_ENV: &str = "OTX_ADMIN_ADDR";
+/// The only dir the admin may install plugin binaries from.
+pub const PLUGIN_INSTALL_DIR_ENV: &str = "OTX_PLUGIN_INSTALL_DIR";
 
 fn main() -> Result<()> {
     if std::env::var("RUST_LOG").is_err() {
@@ -44,25 +86,93 @@ pub fn start() -> Result<()> {
     let notify_service = NotifyService::new();
     let notify_ctrl = notify_service.start(handle.clone());
 
+    // init otx pool
+    let store = SledStore::open(Path::new("./").join(STORE_DIRNAME))
+        .map_err(|err| anyhow!(err.to_string()))?;
+    let cell_provider = CkbCellProvider::new(CKB_URI);
+    let pool_config = OtxPoolConfig::load(&Path::new("./").join(POOL_CONFIG_FILENAME))
+        .map_err(|err| anyhow!(err))?;
+    log::info!("otx pool config: {:?}", pool_config);
+    let otx_pool = Arc::new(OtxPool::new(
+        notify_ctrl.clone(),
+        Box::new(store),
+        Box::new(cell_provider),
+        pool_config,
+    ));
+
     // interval loop
     let notifier = notify_ctrl.clone();
+    let pool = otx_pool.clone();
     let interval_handler = handle.spawn(async move {
         let mut interval = time::interval(Duration::from_secs(5));
         loop {
             interval.tick().await;
             notifier.notify_interval();
+
+            // evict the otxs which are expired or whose inputs have been spent
+            let expiring_pool = pool.clone();
+            match tokio::task::spawn_blocking(move || expiring_pool.evict_expired_otxs()).await {
+                Ok(0) => {}
+                Ok(count) => log::info!("evicted expired otxs count: {:?}", count),
+                Err(err) => log::warn!("evict expired otxs error: {}", err),
+            }
+            let pool = pool.clone();
+            match tokio::task::spawn_blocking(move || pool.evict_dead_otxs()).await {
+                Ok(0) => {}
+                Ok(count) => log::info!("evicted dead otxs count: {:?}", count),
+                Err(err) => log::warn!("evict dead otxs error: {}", err),
+            }
         }
     });
 
     // init plugins
-    let plugin_manager = PluginManager::init(handle, notify_ctrl.clone(), Path::new("./")).unwrap();
-    let plugins = plugin_manager.plugin_configs();
-    log::info!("actived plugins count: {:?}", plugins.len());
+    let mut plugin_manager = PluginManager::init(
+        handle.clone(),
+        notify_ctrl.clone(),
+        otx_pool.clone(),
+        Box::new(CkbTxSender::new(CKB_URI)),
+        Path::new("./"),
+    )
+    .unwrap();
+    if let Ok(install_dir) = env::var(PLUGIN_INSTALL_DIR_ENV) {
+        plugin_manager
+            .set_install_dir(Path::new(&install_dir))
+            .map_err(|err| anyhow!(err))?;
+    }
+    // the batcher and the matcher would both claim the same pooled otxs
+    if env::var(AGGREGATOR_PK_ENV).is_ok() && env::var(SWAP_CHANGE_ADDRESS_ENV).is_ok() {
+        return Err(anyhow!(
+            "{} and {} are exclusive, only one of the batcher and the atomic swap matcher may run",
+            AGGREGATOR_PK_ENV,
+            SWAP_CHANGE_ADDRESS_ENV
+        ));
+    }
+    if let Some(matcher) = init_matcher()? {
+        plugin_manager
+            .register_plugin(Arc::new(matcher))
+            .map_err(|err| anyhow!(err))?;
+    }
+    if let Some(batcher) = init_batcher()? {
+        plugin_manager
+            .register_plugin(Arc::new(batcher))
+            .map_err(|err| anyhow!(err))?;
+    }
+    log::info!(
+        "actived plugins count: {:?}",
+        plugin_manager.plugin_configs().len()
+    );
+    let plugin_manager = Arc::new(Mutex::new(plugin_manager));
+    notify_ctrl.notify_start();
+
+    // recover the otxs saved before the last shutdown
+    let recovered = otx_pool.recover().map_err(|err| anyhow!(err.to_string()))?;
+    log::info!("recovered otxs count: {:?}", recovered);
 
     // init otx pool rpc
-    let rpc_impl = OtxPoolRpcImpl::new(notify_ctrl);
+    let rpc_impl = OtxPoolRpcImpl::new(otx_pool);
     let mut io_handler = IoHandler::new();
     io_handler.extend_with(rpc_impl.to_delegate());
+    io_handler.extend_with(PluginRpcImpl::new(plugin_manager.clone()).to_delegate());
 
     // start rpc server
     let server = ServerBuilder::new(io_handler)
@@ -74,6 +184,32 @@ pub fn start() -> Result<()> {
         .start_http(&bind_addr)
         .expect("Start Jsonrpc HTTP service");
     log::info!("jsonrpc server started: {}", SERVICE_URI);
+    let admin_server = start_admin_server(plugin_manager)?;
+
+    // start subscription servers
+    let subscription_rpc_impl = handle.block_on(SubscriptionRpcImpl::new(
+        notify_ctrl.clone(),
+        handle.clone(),
+    ));
+    let mut pubsub_handler = PubSubHandler::new(MetaIoHandler::default());
+    pubsub_handler.extend_with(subscription_rpc_impl.to_delegate());
+    let ws_server = jsonrpc_ws_server::ServerBuilder::with_meta_extractor(
+        pubsub_handler.clone(),
+        |context: &jsonrpc_ws_server::RequestContext| {
+            SubscriptionSession(Some(Arc::new(Session::new(context.sender()))))
+        },
+    )
+    .start(&SERVICE_WS_ADDR.parse()?)
+    .map_err(|err| anyhow!(err.to_string()))?;
+    log::info!("subscription websocket server started: {}", SERVICE_WS_ADDR);
+    let tcp_server = jsonrpc_tcp_server::ServerBuilder::with_meta_extractor(
+        pubsub_handler,
+        |context: &jsonrpc_tcp_server::RequestContext| {
+            SubscriptionSession(Some(Arc::new(Session::new(context.sender.clone()))))
+        },
+    )
+    .start(&SERVICE_TCP_ADDR.parse()?)?;
+    log::info!("subscription tcp server started: {}", SERVICE_TCP_ADDR);
 
     // test
     // let mut rx = notify_ctrl.subscribe_new_open_tx("main-test").await;
@@ -86,11 +222,125 @@ pub fn start() -> Result<()> {
     log::info!("Waiting for Ctrl-C...");
     rx.recv().expect("Receive Ctrl-C from channel.");
 
+    // give plugins a chance to handle the stop event before shutdown
+    notify_ctrl.notify_stop();
+    handle.block_on(time::sleep(STOP_NOTIFY_DELAY));
+
     interval_handler.abort();
     server.close();
+    if let Some(admin_server) = admin_server {
+        admin_server.close();
+    }
+    ws_server.close();
+    tcp_server.close();
     runtime.shutdown_timeout(RUNTIME_SHUTDOWN_TIMEOUT);
 
     log::info!("Closing!");
 
     Ok(())
 }
+
+fn start_admin_server(
+    plugin_manager: Arc<Mutex<PluginManager>>,
+) -> Result<Option<jsonrpc_http_server::Server>> {
+    let admin_addr = match env::var(ADMIN_ADDR_ENV) {
+        Ok(admin_addr) => admin_addr,
+        Err(_) => {
+            log::info!("{} is not set, the admin rpc is disabled", ADMIN_ADDR_ENV);
+            return Ok(None);
+        }
+    };
+    let admin_addr: SocketAddr = admin_addr
+        .parse()
+        .map_err(|err| anyhow!("invalid {}: {}", ADMIN_ADDR_ENV, err))?;
+    if !admin_addr.ip().is_loopback() {
+        log::warn!(
+            "the admin rpc listens on {}, which is not a loopback address",
+            admin_addr
+        );
+    }
+
+    let mut io_handler = IoHandler::new();
+    io_handler.extend_with(PluginAdminRpcImpl::new(plugin_manager).to_delegate());
+    let server = ServerBuilder::new(io_handler)
+        .start_http(&admin_addr)
+        .map_err(|err| anyhow!(err.to_string()))?;
+    log::info!("admin jsonrpc server started: {}", admin_addr);
+    Ok(Some(server))
+}
+
+fn init_matcher() -> Result<Option<AtomicSwapMatcher>> {
+    let change_address = match env::var(SWAP_CHANGE_ADDRESS_ENV) {
+        Ok(change_address) => change_address,
+        Err(_) => {
+            log::info!(
+                "{} is not set, the atomic swap matcher is disabled",
+                SWAP_CHANGE_ADDRESS_ENV
+            );
+            return Ok(None);
+        }
+    };
+    let change_address = Address::from_str(&change_address)
+        .map_err(|err| anyhow!("invalid {}: {}", SWAP_CHANGE_ADDRESS_ENV, err))?;
+    let mut config = AtomicSwapConfig {
+        change_lock: Some(packed::Script::from(change_address.payload()).into()),
+        ..Default::default()
+    };
+    if let Some(max_ring_size) = parse_env(SWAP_MAX_RING_SIZE_ENV)? {
+        config.max_ring_size = max_ring_size;
+    }
+    if let Some(min_fee) = parse_env(SWAP_MIN_FEE_ENV)? {
+        config.min_fee = min_fee;
+    }
+    if let Some(max_fee) = parse_env(SWAP_MAX_FEE_ENV)? {
+        config.max_fee = max_fee;
+    }
+    log::info!("atomic swap config: {:?}", config);
+    Ok(Some(AtomicSwapMatcher::new(config)))
+}
+
+fn parse_env<T: FromStr>(key: &str) -> Result<Option<T>>
+where
+    T::Err: std::fmt::Display,
+{
+    match env::var(key) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|err| anyhow!("invalid {}: {}", key, err)),
+        Err(_) => Ok(None),
+    }
+}
+
+fn init_batcher() -> Result<Option<Batcher>> {
+    let pk = match env::var(AGGREGATOR_PK_ENV) {
+        Ok(pk) => pk,
+        Err(_) => {
+            log::info!("{} is not set, the batcher is disabled", AGGREGATOR_PK_ENV);
+            return Ok(None);
+        }
+    };
+    let pk = H256::from_str(pk.trim_start_matches("0x"))
+        .map_err(|err| anyhow!("invalid {}: {}", AGGREGATOR_PK_ENV, err))?;
+    let cell = env::var(AGGREGATOR_CELL_ENV).map_err(|_| {
+        anyhow!(
+            "{} must be set with {}",
+            AGGREGATOR_CELL_ENV,
+            AGGREGATOR_PK_ENV
+        )
+    })?;
+    let (tx_hash, index) = cell
+        .split_once(':')
+        .ok_or_else(|| anyhow!("invalid {}: {}", AGGREGATOR_CELL_ENV, cell))?;
+    let own_cell = OutPoint {
+        tx_hash: H256::from_str(tx_hash.trim_start_matches("0x"))
+            .map_err(|err| anyhow!("invalid {}: {}", AGGREGATOR_CELL_ENV, err))?,
+        index: index.parse::<u32>()?.into(),
+    };
+    let signer = Signer::new(pk)?;
+    Ok(Some(Batcher::new(
+        BatcherConfig::default(),
+        signer,
+        own_cell,
+    )))
+}
@@ -4,6 +4,7 @@ use crate::IntegrationTest;
 use ckb_types::prelude::Entity;
 use otx_format::jsonrpc_types::tx_view::{otx_to_tx_view, tx_view_to_otx};
 use otx_format::types::packed;
+use otx_pool::pool::Id;
 use utils::client::service_client::ServiceRpcClient;
 use utils::const_definition::SERVICE_URI;
 
@@ -17,7 +18,7 @@ fn test_service_rpc() {
     let service_client = ServiceRpcClient::new(SERVICE_URI.to_string());
     let ret = service_client.submit_otx(JsonBytes::default());
     assert!(ret.is_err());
-    let ret = service_client.query_otx_by_id(u64::MAX);
+    let ret = service_client.query_otx_by_id(Id::default());
     assert!(ret.is_ok())
 }
 
@@ -1,5 +1,6 @@
 use super::super::IntegrationTest;
 
+use aggregator::fee::{resolve_input_capacity, FeeEngine};
 use utils::client::ckb_cli_client::{ckb_cli_get_capacity, ckb_cli_transfer_ckb};
 use utils::const_definition::{CKB_URI, OMNI_OPENTX_TX_HASH, OMNI_OPENTX_TX_IDX};
 use utils::instruction::ckb::dump_data;
@@ -31,7 +32,7 @@ use ckb_sdk::{
 };
 use ckb_types::{
     bytes::Bytes,
-    core::{BlockView, Capacity, ScriptHashType, TransactionView},
+    core::{BlockView, ScriptHashType, TransactionView},
     packed::{Byte32, CellDep, CellOutput, OutPoint, Script, Transaction, WitnessArgs},
     prelude::*,
     H160, H256,
@@ -95,13 +96,9 @@ fn test_sighash_open_transaction() {
     let open_tx = add_input(args, "./free-space/1_otx_sighash_signed.json".into()).unwrap();
     dump_data(&open_tx, "./free-space/1_otx_sighash_signed_add_input.json").unwrap();
 
-    // 6. Add output
-    let args = AddOutputArgs {
-        to_address: payee_address,
-        capacity: (99_0000_0000 + 1_0000_0000 - 10_0000).into(),
-    };
-    let open_tx = add_output(
-        args,
+    // 6. Pay the fee and add the change output
+    let open_tx = collect_fee(
+        &payee_address,
         "./free-space/1_otx_sighash_signed_add_input.json".into(),
     )
     .unwrap();
@@ -181,13 +178,9 @@ fn test_ethereum_open_transaction() {
     )
     .unwrap();
 
-    // 6. Add output
-    let args = AddOutputArgs {
-        to_address: payee_address,
-        capacity: (99_0000_0000 + 1_0000_0000 - 10_0000).into(),
-    };
-    let open_tx = add_output(
-        args,
+    // 6. Pay the fee and add the change output
+    let open_tx = collect_fee(
+        &payee_address,
         "./free-space/2_otx_ethereum_signed_add_input.json".into(),
     )
     .unwrap();
@@ -284,11 +277,7 @@ fn test_merge_multiple_otxs() {
     let args = AddInputArgs { tx_hash, index: 0 };
     let open_tx = add_input(args, "./free-space/3_full_tx.json".into()).unwrap();
     dump_data(&open_tx, "./free-space/3_full_tx.json").unwrap();
-    let args = AddOutputArgs {
-        to_address: payee_address,
-        capacity: (99_0000_0000 + 2_0000_0000 - 10_0000).into(),
-    };
-    let open_tx = add_output(args, "./free-space/3_full_tx.json".into()).unwrap();
+    let open_tx = collect_fee(&payee_address, "./free-space/3_full_tx.json".into()).unwrap();
     dump_data(&open_tx, "./free-space/3_full_tx.json").unwrap();
     let args = &SignTxArgs {
         sender_key: vec![payee_pk],
@@ -372,15 +361,6 @@ struct AddInputArgs {
     index: usize,
 }
 
-#[derive(Args)]
-struct AddOutputArgs {
-    #[clap(long, value_name = "ADDRESS")]
-    to_address: Address,
-    /// The capacity to transfer (unit: CKB, example: 102.43)
-    #[clap(long, value_name = "CKB")]
-    capacity: HumanCapacity,
-}
-
 #[derive(Args)]
 struct MergeOpenTxArgs {
     /// The output transaction info file (.json)
@@ -731,20 +711,14 @@ fn add_live_cell(args: &AddInputArgs, tx: TransactionView) -> Result<Transaction
     Ok(tx.as_advanced_builder().input(input).cell_dep(dep).build())
 }
 
-fn add_output(args: AddOutputArgs, path: PathBuf) -> Result<TxInfo> {
+/// Pay the fee out of the open capacity and send the surplus to `to_address`,
+/// leaving one witness to sign.
+fn collect_fee(to_address: &Address, path: PathBuf) -> Result<TxInfo> {
     let tx_info: TxInfo = serde_json::from_slice(&fs::read(&path)?)?;
-    // println!("> tx: {}", serde_json::to_string_pretty(&tx_info.tx)?);
     let tx = Transaction::from(tx_info.tx.inner).into_view();
-    let lock_script = Script::from(args.to_address.payload());
-    let output = CellOutput::new_builder()
-        .capacity(Capacity::shannons(args.capacity.0).pack())
-        .lock(lock_script)
-        .build();
-    let tx = tx
-        .as_advanced_builder()
-        .output(output)
-        .output_data(Bytes::default().pack())
-        .build();
+    let input_capacity = resolve_input_capacity(CKB_URI, &tx)?;
+    let change_lock = Script::from(to_address.payload());
+    let tx = FeeEngine::default().add_change_output(tx, input_capacity, 1, change_lock)?;
     let tx_info = TxInfo {
         tx: json_types::TransactionView::from(tx),
         omnilock_config: tx_info.omnilock_config,
@@ -61,7 +61,8 @@ fn test_burn_udt() {
     //     ],
     //     outputs: [
     //         {capacity: 144, data: 51-51, type: xudt z, lock: Bob},
-    //         {capacity: 99, data: "", type: "", lock: Z} ]
+    //         {capacity: 61, data: "", type: "", lock: Z},
+    //         {capacity: 100-61-fee, data: "", type: "", lock: Z} ]
     // }
 
     let open_tx = bob_build_signed_otx().unwrap();
@@ -72,13 +73,16 @@ fn test_burn_udt() {
     // builder in Z service build full tx
     let input = AddInputArgs { tx_hash, index: 0 };
     let output = AddOutputArgs {
-        capacity: (100_0000_0000 - 1_0000_0000).into(),
+        capacity: 61_0000_0000.into(),
         udt_amount: None,
     };
     let full_tx = z_service
         .add_input_and_output(open_tx, input, output)
         .unwrap();
 
+    // Z pays the fee out of its input
+    let full_tx = z_service.collect_fee(full_tx, 1).unwrap();
+
     // signer in Z service sign the full tx
     let full_tx = z_service.signer.sign_tx(full_tx).unwrap();
     dump_data(&full_tx, "./free-space/udt_full_tx.json").unwrap();
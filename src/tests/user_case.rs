@@ -42,7 +42,8 @@ fn z_aggregate_otxs_omni_lock() {
     //         {capacity: 144, data: 51-51, type: xudt z, lock: Bob},
     //         {capacity: 100-1, data: "", type: "", lock: Carol},
     //         {capacity: 144, data: 9+1, type: xudt z, lock: Carol},
-    //         {capacity: 142+50+1, data: 100+50, type: xudt z, lock: Z} ]
+    //         {capacity: 142, data: 100+50, type: xudt z, lock: Z},
+    //         {capacity: 51+1-fee, data: "", type: "", lock: Z} ]
     // }
     let alice_otx = alice_build_signed_otx().unwrap();
     let bob_otx = bob_build_signed_otx().unwrap();
@@ -56,13 +57,16 @@ fn z_aggregate_otxs_omni_lock() {
     dump_data(&open_tx, "./free-space/usercase_otxs_merged.json").unwrap();
     let input = AddInputArgs { tx_hash, index: 0 };
     let output = AddOutputArgs {
-        capacity: (142_0000_0000 + 50_0000_0000 + 1_0000_0000).into(),
+        capacity: 142_0000_0000.into(),
         udt_amount: Some(100 + 50),
     };
     let full_tx = z_service
         .add_input_and_output(open_tx, input, output)
         .unwrap();
 
+    // Z pays the fee and takes the capacity left open by Alice and Carol
+    let full_tx = z_service.collect_fee(full_tx, 1).unwrap();
+
     // signer in Z service sign the full tx
     let full_tx = z_service.signer.sign_tx(full_tx).unwrap();
     dump_data(&full_tx, "./free-space/usercase_full_tx.json").unwrap();
@@ -1,10 +1,14 @@
 use super::{request, RpcClient};
 
 use otx_format::jsonrpc_types::OpenTransaction;
+use otx_pool::plugin::{manager::PluginStatus, supervisor::PluginHealth};
 use otx_pool::pool::Id;
+use otx_pool::rpc::types::{EncodedOtx, OtxEncoding, OtxWithId, PaginatedOtxs, PoolInfo};
 
 use anyhow::Result;
-use ckb_jsonrpc_types::JsonBytes;
+use ckb_jsonrpc_types::{JsonBytes, OutPoint, Script, Uint64};
+
+use std::collections::HashMap;
 
 pub struct ServiceRpcClient {
     client: RpcClient,
@@ -16,11 +20,104 @@ impl ServiceRpcClient {
         ServiceRpcClient { client }
     }
 
-    pub fn submit_otx(&self, otx: JsonBytes) -> Result<u64> {
+    pub fn submit_otx(&self, otx: JsonBytes) -> Result<Id> {
         request(&self.client, "submit_otx", vec![otx])
     }
 
     pub fn query_otx_by_id(&self, otx: Id) -> Result<Option<OpenTransaction>> {
         request(&self.client, "query_otx_by_id", vec![otx])
     }
+
+    pub fn query_otx_by_id_with_encoding(
+        &self,
+        id: Id,
+        encoding: OtxEncoding,
+    ) -> Result<Option<EncodedOtx>> {
+        request(&self.client, "query_otx_by_id", (id, encoding))
+    }
+
+    pub fn query_otx_conflicts(&self, id: Id) -> Result<Option<Vec<Id>>> {
+        request(&self.client, "query_otx_conflicts", vec![id])
+    }
+
+    pub fn get_all_otxs(
+        &self,
+        offset: Option<Uint64>,
+        limit: Option<Uint64>,
+    ) -> Result<PaginatedOtxs> {
+        request(&self.client, "get_all_otxs", (offset, limit))
+    }
+
+    pub fn query_otxs_by_lock_script(&self, lock: Script) -> Result<Vec<OtxWithId>> {
+        request(&self.client, "query_otxs_by_lock_script", vec![lock])
+    }
+
+    pub fn query_otxs_by_type_script(&self, type_script: Script) -> Result<Vec<OtxWithId>> {
+        request(&self.client, "query_otxs_by_type_script", vec![type_script])
+    }
+
+    pub fn query_otxs_by_input_outpoint(&self, out_point: OutPoint) -> Result<Vec<OtxWithId>> {
+        request(
+            &self.client,
+            "query_otxs_by_input_outpoint",
+            vec![out_point],
+        )
+    }
+
+    pub fn get_pool_info(&self) -> Result<PoolInfo> {
+        request(&self.client, "get_pool_info", ())
+    }
+
+    pub fn get_residual_otx(&self, parent_id: Id) -> Result<Option<OpenTransaction>> {
+        request(&self.client, "get_residual_otx", vec![parent_id])
+    }
+
+    pub fn delete_otx(&self, id: Id, expire_at: Uint64, signature: JsonBytes) -> Result<bool> {
+        request(&self.client, "delete_otx", (id, expire_at, signature))
+    }
+
+    pub fn get_plugin_health(&self) -> Result<HashMap<String, PluginHealth>> {
+        request(&self.client, "get_plugin_health", ())
+    }
+
+    pub fn list_plugins(&self) -> Result<Vec<PluginStatus>> {
+        request(&self.client, "list_plugins", ())
+    }
+
+    pub fn get_plugin_status(&self, name: String) -> Result<String> {
+        request(&self.client, "get_plugin_status", vec![name])
+    }
+
+    pub fn get_plugin_proposed_tx(&self, name: String) -> Result<Option<OpenTransaction>> {
+        request(&self.client, "get_plugin_proposed_tx", vec![name])
+    }
+}
+
+/// The client of the plugin admin endpoint, which the service serves apart
+/// from the public one.
+pub struct ServiceAdminRpcClient {
+    client: RpcClient,
+}
+
+impl ServiceAdminRpcClient {
+    pub fn new(uri: String) -> Self {
+        let client = RpcClient::new(uri);
+        ServiceAdminRpcClient { client }
+    }
+
+    pub fn activate_plugin(&self, name: String) -> Result<()> {
+        request(&self.client, "activate_plugin", vec![name])
+    }
+
+    pub fn deactivate_plugin(&self, name: String) -> Result<()> {
+        request(&self.client, "deactivate_plugin", vec![name])
+    }
+
+    pub fn install_plugin(&self, path: String) -> Result<String> {
+        request(&self.client, "install_plugin", vec![path])
+    }
+
+    pub fn remove_plugin(&self, name: String) -> Result<()> {
+        request(&self.client, "remove_plugin", vec![name])
+    }
 }
@@ -9,8 +9,13 @@ use ckb_types::{bytes::Bytes, core::ScriptHashType, packed, prelude::*, H256};
 pub fn generate_rand_secp_address_pk_pair() -> (Address, H256) {
     // generate pubkey by privkey
     let pk = generate_rand_private_key();
+    let address = secp_address_from_pk(&pk).unwrap();
 
-    let args = generate_secp_args_from_pk(&pk).unwrap();
+    (address, pk)
+}
+
+pub fn secp_address_from_pk(pk: &H256) -> Result<Address> {
+    let args = generate_secp_args_from_pk(pk)?;
 
     // secp address
     let secp_code_hash =
@@ -20,9 +25,7 @@ pub fn generate_rand_secp_address_pk_pair() -> (Address, H256) {
         secp_code_hash,
         Bytes::from(args.as_bytes().to_owned()),
     );
-    let address = Address::new(NetworkType::Testnet, payload, true);
-
-    (address, pk)
+    Ok(Address::new(NetworkType::Testnet, payload, true))
 }
 
 pub fn prepare_secp_address_with_ckb_capacity(capacity: u64) -> Result<(Address, H256, OutPoint)> {